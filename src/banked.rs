@@ -0,0 +1,165 @@
+use super::bus::MemoryDevice;
+use super::ioport::IoDevice;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Banks of RAM or ROM paged into one window of the address space.
+/// the active bank is chosen through a `BankSelect`, which is mapped at an
+/// address or added to an I/O port. bank numbers wrap around the number of banks.
+pub struct BankedMemory {
+    banks: Vec<Vec<u8>>,
+    read_only: bool,
+    bank: Arc<AtomicU8>, // shared with the selects
+}
+
+impl BankedMemory {
+    /// `banks` banks of RAM of `size` bytes, the size of the window.
+    pub fn ram(size: usize, banks: usize) -> BankedMemory {
+        assert!(banks > 0);
+        BankedMemory {
+            banks: vec![vec![0; size]; banks],
+            read_only: false,
+            bank: Arc::new(AtomicU8::new(0)),
+        }
+    }
+
+    /// ROM banks with the given images.
+    pub fn rom(images: Vec<Vec<u8>>) -> BankedMemory {
+        assert!(!images.is_empty());
+        BankedMemory {
+            banks: images,
+            read_only: true,
+            bank: Arc::new(AtomicU8::new(0)),
+        }
+    }
+
+    pub fn bank(&self) -> u8 {
+        self.bank.load(Ordering::Relaxed)
+    }
+
+    pub fn select(&self, bank: u8) {
+        self.bank.store(bank, Ordering::Relaxed);
+    }
+
+    /// a register that selects the bank of this memory.
+    pub fn selector(&self) -> BankSelect {
+        BankSelect {
+            bank: self.bank.clone(),
+        }
+    }
+
+    pub fn banks(&self) -> usize {
+        self.banks.len()
+    }
+
+    /// contents of bank `bank`.
+    pub fn bank_data(&self, bank: usize) -> &[u8] {
+        &self.banks[bank]
+    }
+
+    fn active(&self) -> usize {
+        self.bank.load(Ordering::Relaxed) as usize % self.banks.len()
+    }
+}
+
+impl MemoryDevice for BankedMemory {
+    fn write(&mut self, offset: u16, data: u8) {
+        let bank = self.active();
+        if let (false, Some(b)) = (self.read_only, self.banks[bank].get_mut(offset as usize)) {
+            *b = data;
+        }
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        let bank = &self.banks[self.active()];
+        bank.get(offset as usize).copied().unwrap_or(0)
+    }
+
+    /// loads into the selected bank.
+    fn poke(&mut self, offset: u16, data: u8) {
+        let bank = self.active();
+        if let Some(b) = self.banks[bank].get_mut(offset as usize) {
+            *b = data;
+        }
+    }
+
+    /// the selected bank, then every RAM bank.
+    fn save(&self) -> Vec<u8> {
+        let mut res = vec![self.bank.load(Ordering::Relaxed)];
+        if !self.read_only {
+            self.banks.iter().for_each(|b| res.extend_from_slice(b));
+        }
+        res
+    }
+
+    fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        let size: usize = match self.read_only {
+            true => 0,
+            false => self.banks.iter().map(|b| b.len()).sum(),
+        };
+        if data.len() != size + 1 {
+            return Err("Saved banked memory does not match the machine".to_string());
+        }
+        self.bank.store(data[0], Ordering::Relaxed);
+        let mut rest = &data[1..];
+        if !self.read_only {
+            for b in self.banks.iter_mut() {
+                let (this, next) = rest.split_at(b.len());
+                b.copy_from_slice(this);
+                rest = next;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bank select register of a `BankedMemory`, writes select the bank and reads
+/// return it. its state is saved with the memory.
+#[derive(Clone)]
+pub struct BankSelect {
+    bank: Arc<AtomicU8>,
+}
+
+impl BankSelect {
+    pub fn get(&self) -> u8 {
+        self.bank.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, bank: u8) {
+        self.bank.store(bank, Ordering::Relaxed);
+    }
+}
+
+impl MemoryDevice for BankSelect {
+    fn write(&mut self, _offset: u16, data: u8) {
+        self.set(data);
+    }
+
+    fn peek(&self, _offset: u16) -> u8 {
+        self.get()
+    }
+}
+
+impl IoDevice for BankSelect {
+    fn io_write(&mut self, _offset: u8, data: u8) {
+        self.set(data);
+    }
+
+    fn io_peek(&self, _offset: u8) -> u8 {
+        self.get()
+    }
+
+    fn save(&self) -> Vec<u8> {
+        vec![self.get()]
+    }
+
+    fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        match data {
+            [bank] => {
+                self.set(*bank);
+                Ok(())
+            }
+            _ => Err("Saved bank select does not match the machine".to_string()),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use super::trace::Registers;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Which accesses a watchpoint triggers on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Watch {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Watch {
+    pub fn new(read: bool, write: bool) -> Option<Watch> {
+        match (read, write) {
+            (true, true) => Some(Watch::ReadWrite),
+            (true, false) => Some(Watch::Read),
+            (false, true) => Some(Watch::Write),
+            (false, false) => None,
+        }
+    }
+
+    fn matches(&self, write: bool) -> bool {
+        match self {
+            Watch::Read => !write,
+            Watch::Write => write,
+            Watch::ReadWrite => true,
+        }
+    }
+}
+
+/// Registers and register pairs a condition can test.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+impl Register {
+    pub fn value(&self, r: &Registers) -> u16 {
+        let pair = |h: u8, l: u8| (h as u16) << 8 | l as u16;
+        match self {
+            Register::A => r.a as u16,
+            Register::F => r.f as u16,
+            Register::B => r.b as u16,
+            Register::C => r.c as u16,
+            Register::D => r.d as u16,
+            Register::E => r.e as u16,
+            Register::H => r.h as u16,
+            Register::L => r.l as u16,
+            Register::BC => pair(r.b, r.c),
+            Register::DE => pair(r.d, r.e),
+            Register::HL => pair(r.h, r.l),
+            Register::SP => r.sp,
+            Register::PC => r.pc,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Register, String> {
+        match s.to_lowercase().as_ref() {
+            "a" => Ok(Register::A),
+            "f" => Ok(Register::F),
+            "b" => Ok(Register::B),
+            "c" => Ok(Register::C),
+            "d" => Ok(Register::D),
+            "e" => Ok(Register::E),
+            "h" => Ok(Register::H),
+            "l" => Ok(Register::L),
+            "bc" => Ok(Register::BC),
+            "de" => Ok(Register::DE),
+            "hl" => Ok(Register::HL),
+            "sp" => Ok(Register::SP),
+            "pc" => Ok(Register::PC),
+            _ => Err(format!("Unknown register {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Compare {
+    fn test(&self, a: u16, b: u16) -> bool {
+        match self {
+            Compare::Eq => a == b,
+            Compare::Ne => a != b,
+            Compare::Lt => a < b,
+            Compare::Le => a <= b,
+            Compare::Gt => a > b,
+            Compare::Ge => a >= b,
+        }
+    }
+}
+
+impl FromStr for Compare {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Compare, String> {
+        match s {
+            "==" => Ok(Compare::Eq),
+            "!=" => Ok(Compare::Ne),
+            "<" => Ok(Compare::Lt),
+            "<=" => Ok(Compare::Le),
+            ">" => Ok(Compare::Gt),
+            ">=" => Ok(Compare::Ge),
+            _ => Err(format!("Unknown comparison {}", s)),
+        }
+    }
+}
+
+/// A reason to stop execution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Breakpoint {
+    /// stop before the instruction at the address is executed.
+    Pc(u16),
+    /// stop after an instruction accessed memory in `start..=end`.
+    /// instruction fetches are not data accesses and do not trigger it.
+    Memory { start: u16, end: u16, watch: Watch },
+    /// stop after an instruction accessed the port.
+    IoPort { port: u8, watch: Watch },
+    /// stop when the comparison becomes true.
+    Register {
+        reg: Register,
+        cmp: Compare,
+        value: u16,
+    },
+}
+
+/// What the breakpoints are checked against after each step.
+pub struct StepAccesses<'a> {
+    pub before: &'a Registers,
+    pub after: &'a Registers,
+    pub mem: &'a [(u16, bool)], // (address, is write) of the data accesses
+    pub io: &'a [(u8, bool)],   // (port, is write)
+}
+
+impl Breakpoint {
+    fn hit(&self, s: &StepAccesses) -> bool {
+        match *self {
+            Breakpoint::Pc(pc) => s.after.pc == pc,
+            Breakpoint::Memory { start, end, watch } => s
+                .mem
+                .iter()
+                .any(|&(addr, w)| start <= addr && addr <= end && watch.matches(w)),
+            Breakpoint::IoPort { port, watch } => {
+                s.io.iter().any(|&(p, w)| p == port && watch.matches(w))
+            }
+            Breakpoint::Register { reg, cmp, value } => {
+                !cmp.test(reg.value(s.before), value) && cmp.test(reg.value(s.after), value)
+            }
+        }
+    }
+}
+
+/// Breakpoints set on the cpu, each one has an id to remove it by.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Breakpoints {
+    list: Vec<(u32, Breakpoint)>,
+    next_id: u32,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            list: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn add(&mut self, b: Breakpoint) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.list.push((id, b));
+        id
+    }
+
+    /// returns false if there is no breakpoint with the id.
+    pub fn remove(&mut self, id: u32) -> bool {
+        let len = self.list.len();
+        self.list.retain(|(i, _)| *i != id);
+        self.list.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn list(&self) -> &[(u32, Breakpoint)] {
+        &self.list
+    }
+
+    /// id of the first breakpoint hit by the step.
+    pub fn check(&self, s: &StepAccesses) -> Option<u32> {
+        self.list.iter().find(|(_, b)| b.hit(s)).map(|(id, _)| *id)
+    }
+}
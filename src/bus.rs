@@ -0,0 +1,704 @@
+use super::banked::BankSelect;
+use super::interrupt::Line;
+use super::ioport::{IoDevice, IoPort};
+use super::memory::OutOfRange;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+/// Memory and I/O as seen from the cpu side. the cpu owns a `SystemBus`
+/// unless `PP8085::set_bus` gives it another `Bus`, the disassembler and the
+/// file writers take any `Bus`. buses are `Send` so the cpu can be moved to
+/// another thread.
+pub trait Bus: Any + Send {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+    /// read without side effects, for viewers and the debugger.
+    fn peek(&self, addr: u16) -> u8;
+    fn io_read(&mut self, port: u8) -> u8;
+    fn io_write(&mut self, port: u8, data: u8);
+    /// I/O read without side effects.
+    fn io_peek(&self, port: u8) -> u8;
+    /// the fault of the last access, cleared by the call.
+    fn take_fault(&mut self) -> Option<Fault> {
+        None
+    }
+    /// T-states elapsed, for devices that count time.
+    fn tick(&mut self, _cycles: u32) {}
+    /// store a byte the way a loader does, read only memory included. false
+    /// if nothing answers to `addr`.
+    fn poke(&mut self, addr: u16, data: u8) -> bool {
+        self.write(addr, data);
+        true
+    }
+    /// whether a device answers to `addr`.
+    fn is_mapped(&self, _addr: u16) -> bool {
+        true
+    }
+    /// what accesses to addresses nothing answers to do.
+    fn set_out_of_range(&mut self, _out_of_range: OutOfRange) {}
+    /// RESET OUT of the cpu.
+    fn reset_io(&mut self) {}
+    /// the first port of the I/O device that answers to `port`. devices are
+    /// saved and restored by their first port to undo instructions.
+    fn io_start(&self, _port: u8) -> Option<u8> {
+        None
+    }
+    /// first ports of every I/O device, the ones `tick` may change.
+    fn io_starts(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    /// state of the I/O device at `port`, to undo a change with `io_restore`.
+    fn io_save(&self, _port: u8) -> Option<Vec<u8>> {
+        None
+    }
+    fn io_restore(&mut self, _port: u8, _data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// An access the bus refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fault {
+    /// nothing answers to the address.
+    OutOfRange(u16),
+    /// write to read only memory.
+    RomWrite(u16),
+}
+
+impl Fault {
+    /// the fault with its address moved up by `offset`.
+    fn moved(self, offset: u16) -> Fault {
+        match self {
+            Fault::OutOfRange(a) => Fault::OutOfRange(a.wrapping_add(offset)),
+            Fault::RomWrite(a) => Fault::RomWrite(a.wrapping_add(offset)),
+        }
+    }
+}
+
+/// A device that answers to a range of the address space.
+/// addresses are given as the offset from the start of the range. devices are
+/// `Send` so the cpu can be moved to another thread.
+pub trait MemoryDevice: Any + Send {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+    fn write(&mut self, offset: u16, data: u8);
+    fn peek(&self, offset: u16) -> u8;
+    /// store a byte the way a loader does, read only memory included.
+    fn poke(&mut self, offset: u16, data: u8) {
+        self.write(offset, data)
+    }
+    /// contents to keep in a save state, nothing for devices without state.
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn load(&mut self, _data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+    /// the fault of the last access, with the address as an offset.
+    fn take_fault(&mut self) -> Option<Fault> {
+        None
+    }
+    /// what accesses to the addresses the device leaves unmapped do, given
+    /// by the bus it is mapped on.
+    fn set_out_of_range(&mut self, _out_of_range: OutOfRange) {}
+}
+
+/// Answers the addresses nothing is mapped at, writes are lost.
+pub struct Unmapped {
+    out_of_range: OutOfRange,
+    fault: Option<u16>,
+}
+
+impl Unmapped {
+    pub fn new(out_of_range: OutOfRange) -> Unmapped {
+        Unmapped {
+            out_of_range,
+            fault: None,
+        }
+    }
+
+    fn access(&mut self, addr: u16) {
+        if self.out_of_range == OutOfRange::Fault {
+            self.fault = Some(addr);
+        }
+    }
+}
+
+impl MemoryDevice for Unmapped {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.access(offset);
+        self.out_of_range.value()
+    }
+
+    fn write(&mut self, offset: u16, _data: u8) {
+        self.access(offset);
+    }
+
+    fn peek(&self, _offset: u16) -> u8 {
+        self.out_of_range.value()
+    }
+
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.fault.take().map(Fault::OutOfRange)
+    }
+}
+
+/// A range of the address space and the device mapped there.
+pub struct Mapping {
+    pub start: u16,
+    pub end: u16, // inclusive
+    pub name: String,
+    pub device: Box<dyn MemoryDevice>,
+}
+
+impl Mapping {
+    /// the device if it is a `T`.
+    pub fn downcast_mut<T: MemoryDevice>(&mut self) -> Option<&mut T> {
+        (self.device.as_mut() as &mut dyn Any).downcast_mut::<T>()
+    }
+}
+
+/// A range of I/O ports and the device answering there.
+pub struct IoMapping {
+    pub start: u8,
+    pub end: u8, // inclusive
+    pub name: String,
+    pub device: Box<dyn IoDevice>,
+}
+
+impl IoMapping {
+    /// the device if it is a `T`.
+    pub fn downcast<T: IoDevice>(&self) -> Option<&T> {
+        (self.device.as_ref() as &dyn Any).downcast_ref::<T>()
+    }
+
+    pub fn downcast_mut<T: IoDevice>(&mut self) -> Option<&mut T> {
+        (self.device.as_mut() as &mut dyn Any).downcast_mut::<T>()
+    }
+
+    /// the device if it is a plain port.
+    fn latch(&self) -> Option<&IoPort> {
+        self.downcast::<IoPort>()
+    }
+
+    fn latch_mut(&mut self) -> Option<&mut IoPort> {
+        self.downcast_mut::<IoPort>()
+    }
+}
+
+/// Range and name of a mapping, for listing the memory map.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub name: String,
+}
+
+/// Saved contents of the device mapped at `start..=end`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeviceState {
+    pub start: u16,
+    pub end: u16,
+    pub data: Vec<u8>,
+}
+
+/// Address decoding of a system: devices mapped at address ranges that may
+/// not overlap, a handler for the addresses left unmapped and the devices
+/// mapped at ranges of I/O ports. unmapped ports read 0. the out of range
+/// setting applies to the unmapped addresses and to the mapped devices.
+pub struct SystemBus {
+    mappings: Vec<Mapping>, // sorted by start
+    unmapped: Box<dyn MemoryDevice>,
+    out_of_range: OutOfRange,
+    io_mappings: Vec<IoMapping>, // sorted by start
+    fault: Option<Fault>,
+}
+
+impl Default for SystemBus {
+    fn default() -> SystemBus {
+        SystemBus::new()
+    }
+}
+
+impl SystemBus {
+    /// a bus with nothing mapped, accesses to unmapped addresses fault.
+    pub fn new() -> SystemBus {
+        SystemBus {
+            mappings: Vec::new(),
+            unmapped: Box::new(Unmapped::new(OutOfRange::Fault)),
+            out_of_range: OutOfRange::Fault,
+            io_mappings: Vec::new(),
+            fault: None,
+        }
+    }
+
+    /// map `device` at `start..=end`, the range must not overlap another.
+    pub fn map(
+        &mut self,
+        start: u16,
+        end: u16,
+        name: &str,
+        mut device: Box<dyn MemoryDevice>,
+    ) -> Result<(), String> {
+        if start > end {
+            return Err(format!("Empty range {:04X}-{:04X}", start, end));
+        }
+        if let Some(m) = self
+            .mappings
+            .iter()
+            .find(|m| start <= m.end && m.start <= end)
+        {
+            return Err(format!(
+                "{} at {:04X}-{:04X} overlaps {} at {:04X}-{:04X}",
+                name, start, end, m.name, m.start, m.end
+            ));
+        }
+        device.set_out_of_range(self.out_of_range);
+        let i = self.mappings.partition_point(|m| m.start < start);
+        self.mappings.insert(
+            i,
+            Mapping {
+                start,
+                end,
+                name: name.to_string(),
+                device,
+            },
+        );
+        Ok(())
+    }
+
+    /// remove the device mapped at `start`.
+    pub fn unmap(&mut self, start: u16) -> Option<Box<dyn MemoryDevice>> {
+        let i = self.mappings.iter().position(|m| m.start == start)?;
+        Some(self.mappings.remove(i).device)
+    }
+
+    pub fn unmap_all(&mut self) {
+        self.mappings.clear();
+    }
+
+    /// handler of the addresses no device is mapped at, it is given the full address.
+    pub fn set_unmapped(&mut self, device: Box<dyn MemoryDevice>) {
+        self.unmapped = device;
+    }
+
+    pub fn get_out_of_range(&self) -> OutOfRange {
+        self.out_of_range
+    }
+
+    pub fn regions(&self) -> Vec<Region> {
+        self.mappings
+            .iter()
+            .map(|m| Region {
+                start: m.start,
+                end: m.end,
+                name: m.name.clone(),
+            })
+            .collect()
+    }
+
+    /// the mapping `start..=end` is in.
+    pub fn mapping_mut(&mut self, start: u16, end: u16) -> Option<&mut Mapping> {
+        let i = self.find(start)?;
+        let m = &mut self.mappings[i];
+        if end > m.end {
+            return None;
+        }
+        Some(m)
+    }
+
+    /// the device mapped at `start`.
+    pub fn device_mut(&mut self, start: u16) -> Option<&mut Box<dyn MemoryDevice>> {
+        self.mappings
+            .iter_mut()
+            .find(|m| m.start == start)
+            .map(|m| &mut m.device)
+    }
+
+    fn find(&self, addr: u16) -> Option<usize> {
+        let i = self.mappings.partition_point(|m| m.start <= addr);
+        match i {
+            0 => None,
+            i if addr <= self.mappings[i - 1].end => Some(i - 1),
+            _ => None,
+        }
+    }
+
+    /// map `device` at ports `start..=end`, the range must not overlap another.
+    pub fn map_io(
+        &mut self,
+        start: u8,
+        end: u8,
+        name: &str,
+        device: Box<dyn IoDevice>,
+    ) -> Result<(), String> {
+        if start > end {
+            return Err(format!("Empty port range {:02X}-{:02X}", start, end));
+        }
+        if let Some(m) = self
+            .io_mappings
+            .iter()
+            .find(|m| start <= m.end && m.start <= end)
+        {
+            return Err(format!(
+                "{} at ports {:02X}-{:02X} overlaps {} at {:02X}-{:02X}",
+                name, start, end, m.name, m.start, m.end
+            ));
+        }
+        let i = self.io_mappings.partition_point(|m| m.start < start);
+        self.io_mappings.insert(
+            i,
+            IoMapping {
+                start,
+                end,
+                name: name.to_string(),
+                device,
+            },
+        );
+        Ok(())
+    }
+
+    /// remove the device mapped at port `start`.
+    pub fn unmap_io(&mut self, start: u8) -> Option<Box<dyn IoDevice>> {
+        let i = self.io_mappings.iter().position(|m| m.start == start)?;
+        Some(self.io_mappings.remove(i).device)
+    }
+
+    pub fn io_regions(&self) -> Vec<Region> {
+        self.io_mappings
+            .iter()
+            .map(|m| Region {
+                start: m.start as u16,
+                end: m.end as u16,
+                name: m.name.clone(),
+            })
+            .collect()
+    }
+
+    /// the device mapped at port `start`.
+    pub fn io_device_mut(&mut self, start: u8) -> Option<&mut Box<dyn IoDevice>> {
+        self.io_mappings
+            .iter_mut()
+            .find(|m| m.start == start)
+            .map(|m| &mut m.device)
+    }
+
+    /// the device mapped at port `start` if it is a `T`.
+    pub fn io_device<T: IoDevice>(&self, start: u8) -> Option<&T> {
+        let m = self.io_mappings.iter().find(|m| m.start == start)?;
+        m.downcast::<T>()
+    }
+
+    pub fn io_device_as_mut<T: IoDevice>(&mut self, start: u8) -> Option<&mut T> {
+        let m = self.io_mappings.iter_mut().find(|m| m.start == start)?;
+        m.downcast_mut::<T>()
+    }
+
+    /// output `pin` of the device mapped at port `start`.
+    pub fn io_line(&self, start: u8, pin: &str) -> Option<Line> {
+        let m = self.io_mappings.iter().find(|m| m.start == start)?;
+        m.device.line(pin)
+    }
+
+    fn find_io(&self, port: u8) -> Option<usize> {
+        let i = self.io_mappings.partition_point(|m| m.start <= port);
+        match i {
+            0 => None,
+            i if port <= self.io_mappings[i - 1].end => Some(i - 1),
+            _ => None,
+        }
+    }
+
+    /// a latch at `addr`, replacing the latch already there. false if another
+    /// device answers to the port.
+    pub fn add_io_port(&mut self, addr: u8) -> bool {
+        match self
+            .find_io(addr)
+            .map(|i| self.io_mappings[i].latch().is_some())
+        {
+            Some(false) => return false,
+            Some(true) => {
+                self.unmap_io(addr);
+            }
+            None => {}
+        }
+        self.map_io(addr, addr, "port", Box::new(IoPort::new(addr)))
+            .is_ok()
+    }
+
+    /// remove the latch at `addr`, other devices are left alone.
+    pub fn remove_io_port(&mut self, addr: u8) -> bool {
+        match self.find_io(addr) {
+            Some(i) if self.io_mappings[i].latch().is_some() => {
+                self.io_mappings.remove(i);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// set the input latch of the port at `addr`, false if there is none.
+    pub fn set_io_input(&mut self, addr: u8, data: u8) -> bool {
+        let port = self
+            .find_io(addr)
+            .and_then(|i| self.io_mappings[i].latch_mut());
+        match port {
+            Some(p) => {
+                p.set_input(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// the output latch of the port at `addr`.
+    pub fn io_output(&self, addr: u8) -> Option<u8> {
+        let i = self.find_io(addr)?;
+        self.io_mappings[i].latch().map(|p| p.output())
+    }
+
+    /// the latches, in port order.
+    pub fn io_ports(&self) -> Vec<IoPort> {
+        self.io_mappings
+            .iter()
+            .filter_map(|m| m.latch())
+            .cloned()
+            .collect()
+    }
+
+    /// select the bank of a banked memory with OUT to `port`.
+    pub fn add_bank_port(&mut self, port: u8, select: BankSelect) -> Result<(), String> {
+        self.map_io(port, port, "bank select", Box::new(select))
+    }
+
+    /// contents of every mapped device, in address order.
+    pub fn save_devices(&self) -> Vec<DeviceState> {
+        self.mappings
+            .iter()
+            .map(|m| DeviceState {
+                start: m.start,
+                end: m.end,
+                data: m.device.save(),
+            })
+            .collect()
+    }
+
+    /// contents of the I/O devices other than the latches, in port order.
+    pub fn save_io_devices(&self) -> Vec<DeviceState> {
+        self.io_mappings
+            .iter()
+            .filter(|m| m.latch().is_none())
+            .map(|m| DeviceState {
+                start: m.start as u16,
+                end: m.end as u16,
+                data: m.device.save(),
+            })
+            .collect()
+    }
+
+    /// restore the devices saved by `save_devices` and `save_io_devices` and
+    /// replace the latches with `ports`. the devices must be mapped the same
+    /// and the latches may not overlap them. nothing changes if the state
+    /// does not fit the machine or a device rejects its data.
+    pub fn load_devices(
+        &mut self,
+        memory: &[DeviceState],
+        io: &[DeviceState],
+        ports: Vec<IoPort>,
+    ) -> Result<(), String> {
+        let same = memory.len() == self.mappings.len()
+            && memory
+                .iter()
+                .zip(&self.mappings)
+                .all(|(s, m)| s.start == m.start && s.end == m.end);
+        if !same {
+            return Err("Save state memory map does not match the machine".to_string());
+        }
+        let devices: Vec<&IoMapping> = self
+            .io_mappings
+            .iter()
+            .filter(|m| m.latch().is_none())
+            .collect();
+        let same = io.len() == devices.len()
+            && io
+                .iter()
+                .zip(&devices)
+                .all(|(s, m)| s.start == m.start as u16 && s.end == m.end as u16);
+        if !same {
+            return Err("Save state I/O devices do not match the machine".to_string());
+        }
+        for (i, p) in ports.iter().enumerate() {
+            let addr = p.get_addr();
+            if ports[..i].iter().any(|q| q.get_addr() == addr) {
+                return Err(format!("Save state has port {:02X} twice", addr));
+            }
+            if let Some(m) = devices.iter().find(|m| m.start <= addr && addr <= m.end) {
+                return Err(format!(
+                    "Saved port {:02X} overlaps {} at {:02X}-{:02X}",
+                    addr, m.name, m.start, m.end
+                ));
+            }
+        }
+
+        let old_memory = self.save_devices();
+        let old_io = self.save_io_devices();
+        if let Err(e) = self.load_device_data(memory, io) {
+            // the devices loaded so far, and the one that failed, go back
+            let _ = self.load_device_data(&old_memory, &old_io);
+            return Err(e);
+        }
+        self.io_mappings.retain(|m| m.latch().is_none());
+        for p in ports {
+            let addr = p.get_addr();
+            self.map_io(addr, addr, "port", Box::new(p))?;
+        }
+        Ok(())
+    }
+
+    fn load_device_data(
+        &mut self,
+        memory: &[DeviceState],
+        io: &[DeviceState],
+    ) -> Result<(), String> {
+        for (s, m) in memory.iter().zip(self.mappings.iter_mut()) {
+            m.device.load(&s.data)?;
+        }
+        let devices = self.io_mappings.iter_mut().filter(|m| m.latch().is_none());
+        for (s, m) in io.iter().zip(devices) {
+            m.device.load(&s.data)?;
+        }
+        Ok(())
+    }
+}
+
+impl Bus for SystemBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let (device, start) = match self.find(addr) {
+            Some(i) => {
+                let m = &mut self.mappings[i];
+                (&mut m.device, m.start)
+            }
+            None => (&mut self.unmapped, 0),
+        };
+        let data = device.read(addr - start);
+        if let Some(f) = device.take_fault() {
+            self.fault = Some(f.moved(start));
+        }
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let (device, start) = match self.find(addr) {
+            Some(i) => {
+                let m = &mut self.mappings[i];
+                (&mut m.device, m.start)
+            }
+            None => (&mut self.unmapped, 0),
+        };
+        device.write(addr - start, data);
+        if let Some(f) = device.take_fault() {
+            self.fault = Some(f.moved(start));
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match self.find(addr) {
+            Some(i) => {
+                let m = &self.mappings[i];
+                m.device.peek(addr - m.start)
+            }
+            None => self.unmapped.peek(addr),
+        }
+    }
+
+    fn io_read(&mut self, port: u8) -> u8 {
+        match self.find_io(port) {
+            Some(i) => {
+                let m = &mut self.io_mappings[i];
+                m.device.io_read(port - m.start)
+            }
+            None => 0,
+        }
+    }
+
+    fn io_write(&mut self, port: u8, data: u8) {
+        if let Some(i) = self.find_io(port) {
+            let m = &mut self.io_mappings[i];
+            m.device.io_write(port - m.start, data);
+        }
+    }
+
+    fn io_peek(&self, port: u8) -> u8 {
+        match self.find_io(port) {
+            Some(i) => {
+                let m = &self.io_mappings[i];
+                m.device.io_peek(port - m.start)
+            }
+            None => 0,
+        }
+    }
+
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.fault.take()
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        for m in self.io_mappings.iter_mut() {
+            m.device.tick(cycles);
+        }
+    }
+
+    /// load a byte into the device at `addr`, false if nothing is mapped there.
+    fn poke(&mut self, addr: u16, data: u8) -> bool {
+        match self.find(addr) {
+            Some(i) => {
+                let m = &mut self.mappings[i];
+                m.device.poke(addr - m.start, data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// for the unmapped addresses and those the mapped devices leave
+    /// unmapped, e.g. the unmapped regions of a `Memory`.
+    fn set_out_of_range(&mut self, out_of_range: OutOfRange) {
+        self.out_of_range = out_of_range;
+        self.unmapped = Box::new(Unmapped::new(out_of_range));
+        for m in &mut self.mappings {
+            m.device.set_out_of_range(out_of_range);
+        }
+    }
+
+    fn is_mapped(&self, addr: u16) -> bool {
+        self.find(addr).is_some()
+    }
+
+    /// pass RESET OUT on to the I/O devices.
+    fn reset_io(&mut self) {
+        for m in self.io_mappings.iter_mut() {
+            m.device.reset();
+        }
+    }
+
+    fn io_start(&self, port: u8) -> Option<u8> {
+        self.find_io(port).map(|i| self.io_mappings[i].start)
+    }
+
+    fn io_starts(&self) -> Vec<u8> {
+        self.io_mappings.iter().map(|m| m.start).collect()
+    }
+
+    fn io_save(&self, port: u8) -> Option<Vec<u8>> {
+        self.find_io(port)
+            .map(|i| self.io_mappings[i].device.save())
+    }
+
+    fn io_restore(&mut self, port: u8, data: &[u8]) -> Result<(), String> {
+        match self.find_io(port) {
+            Some(i) => self.io_mappings[i].device.load(data),
+            None => Ok(()),
+        }
+    }
+}
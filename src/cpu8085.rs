@@ -1,9 +1,51 @@
-use super::ioport::IoPort;
-use super::memory::Memory;
-use std::collections::HashMap;
+use super::banked::BankedMemory;
+use super::breakpoint::{Breakpoint, Breakpoints, Compare, Register, StepAccesses, Watch};
+use super::bus::{Bus, Fault, Region, SystemBus};
+use super::disasm8085;
+use super::error::CpuError;
+use super::history::{CpuSnapshot, History};
+use super::i8155::{self, I8155};
+use super::i8254::{self, I8254};
+use super::i8255::{self, I8255};
+use super::ihex;
+use super::interrupt::{Interrupt, Interrupts, Wire};
+use super::ioport::IoDevice;
+use super::loader::{self, Segment};
+use super::machine_cycle::{self, BusCallback, MachineCycle, MachineCycleKind, TState};
+use super::memory::{Memory, OutOfRange, RegionKind, Rom};
+use super::opcodes::{self, OPCODES};
+use super::serial;
+use super::srec;
+use super::state::{SaveState, STATE_VERSION};
+use super::trace::{Access, Registers, TraceRecord, Tracer};
+use serde::Serialize;
+use std::any::Any;
 use std::fmt;
 use wasm_bindgen::prelude::*;
 
+/// Default clock of the 8085, a 6.144 MHz crystal divided by two.
+pub const DEFAULT_CLOCK_HZ: u32 = 3_072_000;
+
+/// What a single step did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct StepInfo {
+    pub pc: u16,                      // address of the instruction, or PC when interrupted
+    pub opcode: u8,                   // instruction executed, 0x76 while halted, RST of INTR
+    pub t_states: u8,                 // T-states spent
+    pub interrupt: Option<Interrupt>, // interrupt acknowledged instead of an instruction
+    pub halted: bool,                 // the cpu is in the HLT state after the step
+}
+
+/// Why `run_until` returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum StopReason {
+    Halted,
+    Condition,
+    StepLimit,
+    Breakpoint(u32), // id of the breakpoint that was hit
+    Error(CpuError),
+}
+
 #[allow(non_snake_case)]
 #[wasm_bindgen]
 pub struct PP8085 {
@@ -22,12 +64,36 @@ pub struct PP8085 {
     PC: u16, // Program Counter Register
     SP: u16, // Stack Pointer
 
-    memory: Memory,
-    io_ports: HashMap<u8, IoPort>,
+    bus: Box<dyn Bus>, // memory map and I/O ports, a SystemBus unless set_bus replaced it
 
     cycles: u32,
     IE: bool,  // Interrupt enable
     HLT: bool, // indicates hlt state
+
+    interrupts: Interrupts,
+    wires: Vec<Wire>, // device outputs driving the interrupt inputs
+    ei_delay: bool,   // interrupts are enabled only after the instruction following EI
+
+    total_cycles: u64, // T-states elapsed since reset
+    clock_hz: u32,
+    sid: bool,                 // serial input data pin
+    sod: bool,                 // serial output data latch
+    sod_log: Vec<(u64, bool)>, // every level written to SOD with the cycle count
+
+    bus_mode: bool,                    // record machine cycles of every instruction
+    bus_accesses: Vec<MachineCycle>,   // accesses of the instruction being executed
+    machine_cycles: Vec<MachineCycle>, // machine cycles of the last instruction
+    bus_trace: Vec<TState>,            // collected T-states
+    bus_callback: Option<BusCallback>,
+
+    undocumented: bool, // execute the undocumented 8085 instructions
+
+    tracer: Option<Tracer>,
+    breakpoints: Breakpoints,
+    history: History,
+
+    instr_pc: u16,           // address of the instruction being executed
+    fault: Option<CpuError>, // first error raised by the instruction being executed
 }
 
 impl fmt::Display for PP8085 {
@@ -45,7 +111,7 @@ impl PP8085 {
     /// creates a new cpu and initializes everything to zero.
     pub fn new() -> PP8085 {
         console_error_panic_hook::set_once();
-        PP8085 {
+        let mut cpu = PP8085 {
             IR: 0, // Instruction Register
             A: 0,  // Accumulator
             F: 0,  // Process Status Register
@@ -61,69 +127,667 @@ impl PP8085 {
             PC: 0, // Program Counter Register
             SP: 0, // Stack Pointer
 
-            memory: Memory::new(8192),
-            io_ports: HashMap::new(),
+            bus: Box::new(SystemBus::new()),
 
             cycles: 0,
             IE: false,
             HLT: false,
+
+            interrupts: Interrupts::new(),
+            wires: Vec::new(),
+            ei_delay: false,
+
+            total_cycles: 0,
+            clock_hz: DEFAULT_CLOCK_HZ,
+            sid: false,
+            sod: false,
+            sod_log: Vec::new(),
+
+            bus_mode: false,
+            bus_accesses: Vec::new(),
+            machine_cycles: Vec::new(),
+            bus_trace: Vec::new(),
+            bus_callback: None,
+
+            undocumented: false,
+
+            tracer: None,
+            breakpoints: Breakpoints::new(),
+            history: History::new(),
+
+            instr_pc: 0,
+            fault: None,
+        };
+        cpu.load_memory(Memory::new(8192).unwrap());
+        cpu
+    }
+
+    /// run until HLT, see `run`.
+    pub fn run_wasm(&mut self) -> Result<(), JsValue> {
+        self.run()?;
+        Ok(())
+    }
+
+    /// execute one instruction, see `run_next`.
+    pub fn run_next_wasm(&mut self) -> Result<(), JsValue> {
+        self.run_next()?;
+        Ok(())
+    }
+
+    /// like `run_next`, returns the `StepInfo` of the step.
+    pub fn step_wasm(&mut self) -> Result<JsValue, JsValue> {
+        let info = self.step()?;
+        Ok(serde_wasm_bindgen::to_value(&info).unwrap())
+    }
+
+    /// run until HLT or `max_steps` steps, returns the `StopReason`.
+    /// errors are thrown.
+    pub fn run_until_wasm(&mut self, max_steps: u32) -> Result<JsValue, JsValue> {
+        match self.run_until(max_steps as u64, |cpu| cpu.HLT) {
+            StopReason::Error(e) => Err(e.into()),
+            r => Ok(serde_wasm_bindgen::to_value(&r).unwrap()),
         }
     }
 
-    /// execution cycle
-    pub fn run(&mut self) {
-        while !self.HLT {
-            if self.cycles == 0 {
-                self.IR = self.read_8bits();
-                self.cycles += self.decode_and_run() as u32;
-            }
-            self.cycles -= 1;
+    /// stop before the instruction at `pc`, returns the breakpoint id.
+    pub fn add_breakpoint(&mut self, pc: u16) -> u32 {
+        self.breakpoints.add(Breakpoint::Pc(pc))
+    }
+
+    /// stop after memory in `start..=end` is read and/or written.
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, read: bool, write: bool) -> u32 {
+        let watch = Watch::new(read, write).unwrap_or(Watch::ReadWrite);
+        self.breakpoints
+            .add(Breakpoint::Memory { start, end, watch })
+    }
+
+    /// stop after the port is read and/or written.
+    pub fn add_io_watchpoint(&mut self, port: u8, read: bool, write: bool) -> u32 {
+        let watch = Watch::new(read, write).unwrap_or(Watch::ReadWrite);
+        self.breakpoints.add(Breakpoint::IoPort { port, watch })
+    }
+
+    /// stop when `reg cmp value` becomes true, see `add_register_condition`.
+    pub fn add_register_condition_wasm(
+        &mut self,
+        reg: &str,
+        cmp: &str,
+        value: u16,
+    ) -> Result<u32, JsValue> {
+        Ok(self.add_register_condition(reg, cmp, value)?)
+    }
+
+    pub fn remove_breakpoint(&mut self, id: u32) -> bool {
+        self.breakpoints.remove(id)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// list of [id, breakpoint] pairs.
+    pub fn get_breakpoints(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self.breakpoints.list()).unwrap()
+    }
+
+    /// run until a breakpoint is hit, HLT or `max_instructions` steps,
+    /// returns the `StopReason`. errors are thrown.
+    pub fn run_until_break_wasm(&mut self, max_instructions: u32) -> Result<JsValue, JsValue> {
+        match self.run_until_break(max_instructions as u64) {
+            StopReason::Error(e) => Err(e.into()),
+            r => Ok(serde_wasm_bindgen::to_value(&r).unwrap()),
         }
     }
 
-    /// execute one instruction and stop with no regard to cycles
-    pub fn run_next(&mut self) {
-        if !self.HLT {
-            self.IR = self.read_8bits();
-            self.decode_and_run();
+    /// keep the last `depth` steps to step back through, 0 turns it off.
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history.set_depth(depth);
+    }
+
+    pub fn get_history_depth(&self) -> usize {
+        self.history.depth()
+    }
+
+    /// number of steps that can be undone.
+    pub fn get_history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// undo the last step, returns false when there is no history left.
+    pub fn step_back(&mut self) -> bool {
+        let entry = match self.history.pop() {
+            Some(e) => e,
+            None => return false,
+        };
+        for &(addr, old) in entry.mem.iter().rev() {
+            self.bus.poke(addr, old);
+        }
+        for (port, old) in entry.io.iter().rev() {
+            let _ = self.bus.io_restore(*port, old);
+        }
+        self.restore(entry.cpu);
+        true
+    }
+
+    /// step back until PC is `pc`, returns false if the history runs out first.
+    pub fn run_back_until(&mut self, pc: u16) -> bool {
+        while self.step_back() {
+            if self.PC == pc {
+                return true;
+            }
         }
+        false
+    }
+
+    /// binary save state of the machine.
+    pub fn save_state_wasm(&self) -> Vec<u8> {
+        self.save_state()
+    }
+
+    pub fn load_state_wasm(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        Ok(self.load_state(data)?)
     }
 
-    pub fn add_io_port(&mut self, addr: u8) {
-        self.io_ports.insert(addr, IoPort::new(addr));
+    /// JSON save state of the machine.
+    pub fn save_state_json_wasm(&self) -> String {
+        self.save_state_json()
     }
 
-    pub fn remove_io_port(&mut self, addr: u8) {
-        self.io_ports.remove(&addr);
+    pub fn load_state_json_wasm(&mut self, json: &str) -> Result<(), JsValue> {
+        Ok(self.load_state_json(json)?)
     }
 
+    /// enable the undocumented instructions (DSUB, ARHL, RDEL, LDHI, LDSI,
+    /// RSTV, SHLX, JNK, LHLX, JK). when disabled their opcodes are not executed.
+    pub fn set_undocumented(&mut self, on: bool) {
+        self.undocumented = on;
+    }
+
+    pub fn get_undocumented(&self) -> bool {
+        self.undocumented
+    }
+
+    /// drive the TRAP pin, TRAP is edge and level sensitive and cannot be masked.
+    pub fn set_trap(&mut self, level: bool) {
+        self.interrupts.set_trap(level);
+    }
+
+    /// drive the RST7.5 pin, a rising edge sets the RST7.5 latch.
+    pub fn set_rst75(&mut self, level: bool) {
+        self.interrupts.set_rst75(level);
+    }
+
+    /// drive the level sensitive RST6.5 pin.
+    pub fn set_rst65(&mut self, level: bool) {
+        self.interrupts.set_rst65(level);
+    }
+
+    /// drive the level sensitive RST5.5 pin.
+    pub fn set_rst55(&mut self, level: bool) {
+        self.interrupts.set_rst55(level);
+    }
+
+    /// drive the INTR pin.
+    pub fn set_intr(&mut self, level: bool) {
+        self.interrupts.set_intr(level);
+    }
+
+    /// wire output `pin` of the I/O device at port `start` to an interrupt
+    /// input, e.g. ("timer out", "rst7.5"), see `connect_interrupt`.
+    pub fn connect_interrupt_wasm(
+        &mut self,
+        start: u8,
+        pin: &str,
+        input: &str,
+        inverted: bool,
+    ) -> Result<(), JsValue> {
+        let input: Interrupt = input.parse()?;
+        self.connect_interrupt(start, pin, input, inverted)?;
+        Ok(())
+    }
+
+    /// remove every wire from the device outputs to the interrupt inputs.
+    pub fn disconnect_interrupts(&mut self) {
+        self.wires.clear();
+    }
+
+    /// RST instruction placed on the data bus when INTR is acknowledged.
+    pub fn set_intr_opcode(&mut self, opcode: u8) {
+        self.interrupts.set_intr_opcode(opcode);
+    }
+
+    pub fn get_ie(&self) -> bool {
+        self.IE
+    }
+
+    /// drive the SID pin, read into bit 7 of A by RIM.
+    pub fn set_sid(&mut self, level: bool) {
+        self.sid = level;
+    }
+
+    /// level of the SOD latch written by SIM.
+    pub fn get_sod(&self) -> bool {
+        self.sod
+    }
+
+    pub fn clear_sod_log(&mut self) {
+        self.sod_log.clear();
+    }
+
+    /// decode the recorded SOD waveform as 8N1 asynchronous serial data.
+    pub fn decode_sod(&self, baud: u32, clock_hz: u32) -> Vec<u8> {
+        serial::decode_async(&self.sod_log, baud, clock_hz)
+    }
+
+    /// run at least `n` T-states, see `run_for_cycles`.
+    pub fn run_for_cycles_wasm(&mut self, n: u64) -> Result<u64, JsValue> {
+        Ok(self.run_for_cycles(n)?)
+    }
+
+    /// run `ns` nanoseconds of emulated time, see `run_for_duration`.
+    pub fn run_for_duration_wasm(&mut self, ns: u64) -> Result<u64, JsValue> {
+        Ok(self.run_for_duration(ns)?)
+    }
+
+    /// T-states elapsed since the last reset.
+    pub fn get_total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    /// emulated time in nanoseconds since the last reset.
+    pub fn get_elapsed_ns(&self) -> u64 {
+        (self.total_cycles as u128 * 1_000_000_000 / self.clock_hz as u128) as u64
+    }
+
+    /// CPU clock in Hz, see `set_clock_hz`.
+    pub fn set_clock_hz_wasm(&mut self, hz: u32) -> Result<(), JsValue> {
+        Ok(self.set_clock_hz(hz)?)
+    }
+
+    pub fn get_clock_hz(&self) -> u32 {
+        self.clock_hz
+    }
+
+    /// a latch at port `addr`, false if another device answers to the port.
+    pub fn add_io_port(&mut self, addr: u8) -> bool {
+        self.devices_mut().is_some_and(|b| b.add_io_port(addr))
+    }
+
+    pub fn remove_io_port(&mut self, addr: u8) -> bool {
+        self.devices_mut().is_some_and(|b| b.remove_io_port(addr))
+    }
+
+    /// set what IN from port `addr` reads, false if there is no port there.
+    pub fn set_io_input(&mut self, addr: u8, data: u8) -> bool {
+        self.devices_mut()
+            .is_some_and(|b| b.set_io_input(addr, data))
+    }
+
+    /// the last byte written to port `addr` by OUT.
+    pub fn get_io_output(&self, addr: u8) -> Option<u8> {
+        self.system_bus()?.io_output(addr)
+    }
+
+    /// replace the memory map with `data` as RAM from address 0. a bus given
+    /// to `set_bus` keeps its map and `data` is poked into it.
     pub fn load_memory(&mut self, data: Memory) {
-        self.memory = data;
+        match self.devices_mut() {
+            Some(bus) => {
+                bus.unmap_all();
+                if data.size() > 0 {
+                    let end = (data.size() - 1) as u16;
+                    bus.map(0, end, "ram", Box::new(data)).unwrap();
+                }
+            }
+            None => {
+                for addr in 0..data.size() {
+                    self.bus.poke(addr as u16, data.read(addr as u16));
+                }
+            }
+        }
+        self.history.clear();
     }
 
-    pub fn read_io(&mut self, addr: u8) -> u8 {
-        match self.io_ports.get(&addr) {
-            Some(d) => d.read(),
-            None => 0,
+    /// map RAM at `start..=end`, it may not overlap what is already mapped.
+    pub fn map_ram(&mut self, start: u16, end: u16) -> Result<(), JsValue> {
+        let size = range_size(start, end)?;
+        self.map_bus()?
+            .map(start, end, "ram", Box::new(Memory::new(size)?))?;
+        Ok(())
+    }
+
+    /// map `data` as ROM from `start`.
+    pub fn map_rom(&mut self, start: u16, data: &[u8]) -> Result<(), JsValue> {
+        let end = match data.len() {
+            0 => return Err(JsValue::from("Empty ROM image")),
+            n if n > 0x10000 - start as usize => {
+                return Err(JsValue::from("ROM image does not fit in memory"))
+            }
+            n => start + (n - 1) as u16,
+        };
+        self.map_bus()?
+            .map(start, end, "rom", Box::new(Rom::new(data.to_vec())))?;
+        Ok(())
+    }
+
+    /// map `banks` banks of RAM at `start..=end`, OUT to `port` selects the bank.
+    pub fn map_banked_ram(
+        &mut self,
+        start: u16,
+        end: u16,
+        banks: u8,
+        port: u8,
+    ) -> Result<(), JsValue> {
+        if banks == 0 {
+            return Err(JsValue::from("A banked memory needs at least one bank"));
+        }
+        let size = range_size(start, end)? as usize;
+        let banked = BankedMemory::ram(size, banks as usize);
+        let select = banked.selector();
+        let bus = self.map_bus()?;
+        bus.add_bank_port(port, select)?;
+        if let Err(e) = bus.map(start, end, "banked ram", Box::new(banked)) {
+            bus.unmap_io(port);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// an 8155 with its RAM at `ram_base` and its ports at `io_base`, see `add_8155`.
+    pub fn add_8155_wasm(&mut self, ram_base: u16, io_base: u8) -> Result<(), JsValue> {
+        self.add_8155(ram_base, io_base)?;
+        Ok(())
+    }
+
+    /// drive the pins of port 0 (A), 1 (B) or 2 (C) of the 8155 at `io_base`,
+    /// false if there is none.
+    pub fn set_8155_input(&mut self, io_base: u8, port: usize, data: u8) -> bool {
+        match self.io_device_mut::<I8155>(io_base) {
+            Some(d) if port < 3 => {
+                d.set_input(port, data);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// what the 8155 at `io_base` drives on port 0 (A), 1 (B) or 2 (C).
+    pub fn get_8155_output(&self, io_base: u8, port: usize) -> Option<u8> {
+        self.io_device::<I8155>(io_base)?.output(port)
+    }
+
+    /// pulse STB of port 0 (A) or 1 (B) of the 8155 at `io_base`.
+    pub fn strobe_8155(&mut self, io_base: u8, port: usize) -> bool {
+        match self.io_device_mut::<I8155>(io_base) {
+            Some(d) if port < 2 => {
+                d.strobe(port);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// T-states per TIMER IN clock of the 8155 at `io_base`.
+    pub fn set_8155_timer_clock(&mut self, io_base: u8, div: u32) -> bool {
+        match self.io_device_mut::<I8155>(io_base) {
+            Some(d) if div > 0 => {
+                d.set_timer_clock(div);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// an 8255 with its ports at `io_base`, see `add_8255`.
+    pub fn add_8255_wasm(&mut self, io_base: u8) -> Result<(), JsValue> {
+        self.add_8255(io_base)?;
+        Ok(())
+    }
+
+    /// drive the pins of port 0 (A), 1 (B) or 2 (C) of the 8255 at `io_base`,
+    /// false if there is none.
+    pub fn set_8255_input(&mut self, io_base: u8, port: usize, data: u8) -> bool {
+        match self.io_device_mut::<I8255>(io_base) {
+            Some(d) if port < 3 => {
+                d.set_input(port, data);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// what the 8255 at `io_base` drives on port 0 (A), 1 (B) or 2 (C).
+    pub fn get_8255_output(&self, io_base: u8, port: usize) -> Option<u8> {
+        self.io_device::<I8255>(io_base)?.output(port)
+    }
+
+    /// pulse STB of port 0 (A) or 1 (B) of the 8255 at `io_base`.
+    pub fn strobe_8255(&mut self, io_base: u8, port: usize) -> bool {
+        match self.io_device_mut::<I8255>(io_base) {
+            Some(d) if port < 2 => {
+                d.strobe(port);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// pulse ACK of port 0 (A) or 1 (B) of the 8255 at `io_base`.
+    pub fn acknowledge_8255(&mut self, io_base: u8, port: usize) -> bool {
+        match self.io_device_mut::<I8255>(io_base) {
+            Some(d) if port < 2 => {
+                d.acknowledge(port);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// an 8254 with its ports at `io_base`, see `add_8254`.
+    pub fn add_8254_wasm(&mut self, io_base: u8) -> Result<(), JsValue> {
+        self.add_8254(io_base)?;
+        Ok(())
+    }
+
+    /// OUT of `counter` of the 8254 at `io_base`.
+    pub fn get_8254_out(&self, io_base: u8, counter: usize) -> Option<bool> {
+        match self.io_device::<I8254>(io_base) {
+            Some(d) if counter < i8254::COUNTERS => Some(d.out(counter)),
+            _ => None,
+        }
+    }
+
+    /// drive GATE of `counter` of the 8254 at `io_base`, false if there is none.
+    pub fn set_8254_gate(&mut self, io_base: u8, counter: usize, level: bool) -> bool {
+        match self.io_device_mut::<I8254>(io_base) {
+            Some(d) if counter < i8254::COUNTERS => {
+                d.set_gate(counter, level);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// T-states per CLK of `counter` of the 8254 at `io_base`.
+    pub fn set_8254_clock(&mut self, io_base: u8, counter: usize, div: u32) -> bool {
+        match self.io_device_mut::<I8254>(io_base) {
+            Some(d) if counter < i8254::COUNTERS && div > 0 => {
+                d.set_clock(counter, div);
+                true
+            }
+            _ => false,
         }
     }
 
+    /// remove what is mapped at `start`, returns false if nothing is.
+    pub fn unmap_memory(&mut self, start: u16) -> bool {
+        self.history.clear();
+        self.devices_mut().is_some_and(|b| b.unmap(start).is_some())
+    }
+
+    /// load `data` from `origin` into what is mapped there, see `load_segments`.
+    pub fn load_segment_wasm(
+        &mut self,
+        origin: u16,
+        data: &[u8],
+        entry: Option<u16>,
+    ) -> Result<(), JsValue> {
+        self.load_segments(&[Segment::new(origin, data.to_vec())], entry)?;
+        Ok(())
+    }
+
+    /// load an array of `{origin, data}` segments, see `load_segments`.
+    pub fn load_segments_wasm(
+        &mut self,
+        segments: JsValue,
+        entry: Option<u16>,
+    ) -> Result<(), JsValue> {
+        let segments: Vec<Segment> = serde_wasm_bindgen::from_value(segments)
+            .map_err(|e| JsValue::from(format!("Bad segments: {}", e)))?;
+        self.load_segments(&segments, entry)?;
+        Ok(())
+    }
+
+    /// load Intel HEX text, see `load_hex`.
+    pub fn load_hex_wasm(&mut self, text: &str) -> Result<(), JsValue> {
+        self.load_hex(text)?;
+        Ok(())
+    }
+
+    /// `start..=end` of memory as Intel HEX.
+    pub fn export_hex(&self, start: u16, end: u16) -> String {
+        ihex::write_range(self.bus.as_ref(), start, end)
+    }
+
+    /// load Motorola S-record text, see `load_srec`.
+    pub fn load_srec_wasm(&mut self, text: &str) -> Result<(), JsValue> {
+        self.load_srec(text)?;
+        Ok(())
+    }
+
+    /// `start..=end` of memory as S19 records.
+    pub fn export_srec(&self, start: u16, end: u16) -> String {
+        srec::write_range(self.bus.as_ref(), start, end)
+    }
+
+    /// what accesses to unmapped addresses do, those of the bus and the
+    /// unmapped regions of a mapped `Memory`. they fault by default.
+    pub fn set_out_of_range(&mut self, out_of_range: OutOfRange) {
+        self.bus.set_out_of_range(out_of_range);
+    }
+
+    /// mark `start..=end` of a mapped `Memory` as RAM, ROM or unmapped.
+    pub fn set_region_wasm(
+        &mut self,
+        start: u16,
+        end: u16,
+        kind: RegionKind,
+    ) -> Result<(), JsValue> {
+        self.set_region(start, end, kind)?;
+        Ok(())
+    }
+
+    /// the mapped address ranges.
+    pub fn get_memory_map(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.memory_map()).unwrap()
+    }
+
+    /// `len` bytes of memory from `start`, read without side effects.
+    pub fn peek_memory(&self, start: u16, len: u16) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.bus.peek(start.wrapping_add(i)))
+            .collect()
+    }
+
+    pub fn read_io(&mut self, addr: u8) -> u8 {
+        self.record_io_device(addr);
+        let data = self.bus.io_read(addr);
+        self.record_access(
+            MachineCycleKind::IoRead,
+            (addr as u16) << 8 | addr as u16,
+            data,
+        );
+        data
+    }
+
     pub fn write_io(&mut self, addr: u8, data: u8) {
-        if let Some(port) = self.io_ports.get_mut(&addr) {
-            port.write(data);
+        self.record_io_device(addr);
+        self.bus.io_write(addr, data);
+        self.record_access(
+            MachineCycleKind::IoWrite,
+            (addr as u16) << 8 | addr as u16,
+            data,
+        );
+    }
+
+    /// break every instruction into machine cycles and T-states.
+    /// the T-states are collected until taken with `get_bus_trace`.
+    pub fn set_bus_mode(&mut self, on: bool) {
+        self.bus_mode = on;
+        self.bus_accesses.clear();
+        self.machine_cycles.clear();
+    }
+
+    pub fn get_bus_mode(&self) -> bool {
+        self.bus_mode
+    }
+
+    /// take the T-states collected since the last call.
+    pub fn get_bus_trace(&mut self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.take_bus_trace()).unwrap()
+    }
+
+    /// machine cycles of the last instruction executed in bus mode.
+    pub fn get_machine_cycles(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.machine_cycles).unwrap()
+    }
+
+    /// trace the last `capacity` instructions into a ring buffer.
+    pub fn enable_trace(&mut self, capacity: usize) {
+        self.tracer = Some(Tracer::ring_buffer(capacity));
+    }
+
+    pub fn disable_trace(&mut self) {
+        self.tracer = None;
+    }
+
+    /// records in the trace ring buffer, oldest first.
+    pub fn get_trace(&self) -> JsValue {
+        let records = match &self.tracer {
+            Some(t) => t.records(),
+            None => Vec::new(),
         };
+        serde_wasm_bindgen::to_value(&records).unwrap()
+    }
+
+    pub fn clear_trace(&mut self) {
+        if let Some(t) = self.tracer.as_mut() {
+            t.clear();
+        }
     }
 
     pub fn get_summary(&self) -> String {
         self.to_string()
     }
 
-    pub fn get_memory_ptr(&self) -> *const u8 {
-        self.memory.get_data()
+    /// instructions that start in `start..=end` of memory.
+    pub fn get_disassembly(&self, start: u16, end: u16) -> JsValue {
+        serde_wasm_bindgen::to_value(&disasm8085::decode_range(
+            self.bus.as_ref(),
+            start,
+            end,
+            None,
+        ))
+        .unwrap()
     }
 
     pub fn get_io_ports(&self) -> JsValue {
-        JsValue::from_serde(&self.io_ports).unwrap()
+        let ports = self.system_bus().map(|b| b.io_ports()).unwrap_or_default();
+        serde_wasm_bindgen::to_value(&ports).unwrap()
     }
 
     pub fn get_a(&self) -> u8 {
@@ -156,6 +820,9 @@ impl PP8085 {
     pub fn get_pc(&self) -> u16 {
         self.PC
     }
+    pub fn set_pc(&mut self, pc: u16) {
+        self.PC = pc;
+    }
     pub fn get_ir(&self) -> u8 {
         self.IR
     }
@@ -177,355 +844,240 @@ impl PP8085 {
         self.IR = 0;
         self.HLT = false;
         self.cycles = 0;
+        self.IE = false;
+        self.ei_delay = false;
+        self.interrupts.reset();
+        self.bus.reset_io();
+        self.total_cycles = 0;
+        self.sod = false;
+        self.sod_log.clear();
+        self.history.clear();
     }
 }
 
 macro_rules! mov_rd_rs {
     ($fn_name: ident, $dest: ident, $source: ident) => {
-        fn $fn_name(&mut self) -> u8 {
+        fn $fn_name(&mut self) {
             self.$dest = self.$source;
-            4
         }
     };
 }
 
 macro_rules! mov_m_rs {
     ($fn_name: ident, $source: ident) => {
-        fn $fn_name(&mut self) -> u8 {
+        fn $fn_name(&mut self) {
             self.write_memory(self.get_addr_hl(), self.$source);
-            7
         }
     };
 }
 
 macro_rules! mov_rd_m {
     ($fn_name: ident, $dest: ident) => {
-        fn $fn_name(&mut self) -> u8 {
+        fn $fn_name(&mut self) {
             self.$dest = self.read_memory(self.get_addr_hl());
-            7
         }
     };
 }
 
 macro_rules! inr_r {
     ($fn_name: ident, $r: ident) => {
-        fn $fn_name(&mut self) -> u8 {
-            if self.$r < 0xff {
-                self.$r += 1;
-            } else {
-                self.$r = 0x00;
-            }
-            let num = self.$r;
-            self.set_sign((num | 1 << 7) != 0);
-            self.set_overflow(num == 0x00);
-            self.set_zero(num == 0x00);
-            if num != 0x00 {
-                self.set_auxiliary_carry((((num - 1) & 0x0f) + 0x01) & 0x10 == 0x10);
-            } else {
-                self.set_auxiliary_carry(true);
-            }
-            self.set_parity(PP8085::find_parity(num));
-            4
+        fn $fn_name(&mut self) {
+            self.$r = self.inr_8bits(self.$r);
         }
     };
 }
 
 macro_rules! dcr_r {
     ($fn_name: ident, $r: ident) => {
-        fn $fn_name(&mut self) -> u8 {
-            if self.$r > 0x00 {
-                self.$r -= 1;
-            } else {
-                self.$r = 0xff;
-            }
-            let num = self.$r;
-            self.set_sign((num | 1 << 7) != 0);
-            self.set_overflow(num == 0xff);
-            self.set_zero(num == 0x00);
-            if num != 0xff {
-                self.set_auxiliary_carry(((num + 1) & 0x0f) < 1);
-            } else {
-                self.set_auxiliary_carry(true);
-            }
-            self.set_parity(PP8085::find_parity(num));
-            4
+        fn $fn_name(&mut self) {
+            self.$r = self.dcr_8bits(self.$r);
         }
     };
 }
 
 macro_rules! add_r {
     ($fn_name: ident, $r: ident) => {
-        fn $fn_name(&mut self) -> u8 {
-            let num = self.$r;
-            self.set_auxiliary_carry(((self.A & 0x0f) + (num & 0x0f)) & 0x10 == 0x10);
-            self.set_carry(0xff - self.A < num);
-            if 0xff - self.A > num {
-                self.A += num;
-            } else {
-                self.A = num - (0xff - self.A + 0x01);
-                self.set_overflow(true);
-            }
-            self.set_sign((self.A | 1 << 7) != 0);
-            self.set_zero(self.A == 0x00);
-            self.set_parity(PP8085::find_parity(self.A));
-            4
+        fn $fn_name(&mut self) {
+            self.A = self.add_8bits(self.$r, false);
         }
     };
 }
 
 macro_rules! adc_r {
     ($fn_name: ident, $r: ident) => {
-        fn $fn_name(&mut self) -> u8 {
-            let num = self.$r + (self.F & 1);
-            self.set_auxiliary_carry(((self.A & 0x0f) + (num & 0x0f)) & 0x10 == 0x10);
-            self.set_carry(0xff - self.A < num);
-            if 0xff - self.A > num {
-                self.A += num;
-            } else {
-                self.A = num - (0xff - self.A + 0x01);
-                self.set_overflow(true);
-            }
-            self.set_sign((self.A | 1 << 7) != 0);
-            self.set_zero(self.A == 0x00);
-            self.set_parity(PP8085::find_parity(self.A));
-            4
+        fn $fn_name(&mut self) {
+            self.A = self.add_8bits(self.$r, self.get_carry());
         }
     };
 }
 
 macro_rules! sub_r {
     ($fn_name: ident, $r: ident) => {
-        fn $fn_name(&mut self) -> u8 {
-            let num = self.$r;
-            self.set_auxiliary_carry((self.A & 0x0f) < (num & 0x0f));
-            if self.A >= num {
-                self.A -= num;
-            } else {
-                self.A = 0xff - (num - self.A - 0x01);
-                self.set_carry(true);
-                self.set_overflow(true);
-            }
-            self.set_sign((self.A | 1 << 7) != 0);
-            self.set_zero(self.A == 0x00);
-            self.set_parity(PP8085::find_parity(self.A));
-            4
+        fn $fn_name(&mut self) {
+            self.A = self.sub_8bits(self.$r, false);
         }
     };
 }
 
 macro_rules! sbb_r {
     ($fn_name: ident, $r: ident) => {
-        fn $fn_name(&mut self) -> u8 {
-            let num = self.$r + (self.F & 1);
-            self.set_auxiliary_carry((self.A & 0x0f) < (num & 0x0f));
-            if self.A >= num {
-                self.A -= num;
-            } else {
-                self.A = 0xff - (num - self.A - 0x01);
-                self.set_carry(true);
-                self.set_overflow(true);
-            }
-            self.set_sign((self.A | 1 << 7) != 0);
-            self.set_zero(self.A == 0x00);
-            self.set_parity(PP8085::find_parity(self.A));
-            4
+        fn $fn_name(&mut self) {
+            self.A = self.sub_8bits(self.$r, self.get_carry());
         }
     };
 }
 
 macro_rules! ana_r {
     ($fn_name: ident, $r: ident) => {
-        fn $fn_name(&mut self) -> u8 {
+        fn $fn_name(&mut self) {
             self.A &= self.$r;
-            self.set_sign((self.A | 1 << 7) != 0);
-            self.set_zero(self.A == 0x00);
-            self.set_parity(PP8085::find_parity(self.A));
-            4
+            self.set_logic_flags(true);
         }
     };
 }
 
 macro_rules! xra_r {
     ($fn_name: ident, $r: ident) => {
-        fn $fn_name(&mut self) -> u8 {
+        fn $fn_name(&mut self) {
             self.A ^= self.$r;
-            self.set_sign((self.A | 1 << 7) != 0);
-            self.set_zero(self.A == 0x00);
-            self.set_parity(PP8085::find_parity(self.A));
-            4
+            self.set_logic_flags(false);
         }
     };
 }
 
 macro_rules! ora_r {
     ($fn_name: ident, $r: ident) => {
-        fn $fn_name(&mut self) -> u8 {
+        fn $fn_name(&mut self) {
             self.A |= self.$r;
-            self.set_sign((self.A | 1 << 7) != 0);
-            self.set_zero(self.A == 0x00);
-            self.set_parity(PP8085::find_parity(self.A));
-            4
+            self.set_logic_flags(false);
         }
     };
 }
 
 macro_rules! cmp_r {
     ($fn_name: ident, $r: ident) => {
-        fn $fn_name(&mut self) -> u8 {
-            match self.$r.cmp(&self.A) {
-                std::cmp::Ordering::Equal => {
-                    self.set_carry(false);
-                    self.set_zero(true);
-                }
-                std::cmp::Ordering::Greater => {
-                    self.set_carry(false);
-                    self.set_zero(false);
-                }
-                std::cmp::Ordering::Less => {
-                    self.set_carry(true);
-                    self.set_zero(false);
-                }
-            }
-            4
+        fn $fn_name(&mut self) {
+            self.sub_8bits(self.$r, false);
         }
     };
 }
 
 macro_rules! rst_seq {
     ($fn_name: ident, $i: expr) => {
-        fn $fn_name(&mut self) -> u8 {
-            self.SP -= 1;
+        fn $fn_name(&mut self) {
+            self.dec_sp();
             self.write_memory(self.SP, (self.PC >> 8) as u8);
-            self.SP -= 1;
-            self.write_memory(self.SP, (self.PC & 0x0f) as u8);
+            self.dec_sp();
+            self.write_memory(self.SP, (self.PC & 0x00ff) as u8);
             self.PC = $i as u16;
-            12
         }
     };
 }
 
 macro_rules! call_seq {
     ($fn_name: ident) => {
-        fn $fn_name(&mut self) -> u8 {
+        fn $fn_name(&mut self) {
             let (opl, oph) = self.read_16bits();
-            self.SP -= 1;
+            self.dec_sp();
             self.write_memory(self.SP, (self.PC >> 8) as u8);
-            self.SP -= 1;
-            self.write_memory(self.SP, (self.PC & 0x0f) as u8);
+            self.dec_sp();
+            self.write_memory(self.SP, (self.PC & 0x00ff) as u8);
             self.PC = ((oph as u16) << 8) | (opl as u16);
-            18
         }
     };
 
     ($fn_name1: ident, $fn_name2: ident, $cond: ident) => {
-        fn $fn_name1(&mut self) -> u8 {
+        fn $fn_name1(&mut self) -> bool {
             let (opl, oph) = self.read_16bits();
             if self.$cond() {
-                self.SP -= 1;
+                self.dec_sp();
                 self.write_memory(self.SP, (self.PC >> 8) as u8);
-                self.SP -= 1;
-                self.write_memory(self.SP, (self.PC & 0x0f) as u8);
+                self.dec_sp();
+                self.write_memory(self.SP, (self.PC & 0x00ff) as u8);
                 self.PC = ((oph as u16) << 8) | (opl as u16);
-                return 18;
+                return true;
             }
-            9
+            false
         }
 
-        fn $fn_name2(&mut self) -> u8 {
+        fn $fn_name2(&mut self) -> bool {
             let (opl, oph) = self.read_16bits();
             if !self.$cond() {
-                self.SP -= 1;
+                self.dec_sp();
                 self.write_memory(self.SP, (self.PC >> 8) as u8);
-                self.SP -= 1;
-                self.write_memory(self.SP, (self.PC & 0x0f) as u8);
+                self.dec_sp();
+                self.write_memory(self.SP, (self.PC & 0x00ff) as u8);
                 self.PC = ((oph as u16) << 8) | (opl as u16);
-                return 18;
+                return true;
             }
-            9
+            false
         }
     };
 }
 
 macro_rules! ret_seq {
     ($fn_name: ident) => {
-        fn $fn_name(&mut self) -> u8 {
+        fn $fn_name(&mut self) {
             let l = self.read_memory(self.SP);
-            self.SP += 1;
+            self.inc_sp();
             let h = self.read_memory(self.SP);
-            self.SP += 1;
+            self.inc_sp();
             self.PC = ((h as u16) << 8) | (l as u16);
-            10
         }
     };
 
     ($fn_name1: ident, $fn_name2: ident, $cond: ident) => {
-        fn $fn_name1(&mut self) -> u8 {
+        fn $fn_name1(&mut self) -> bool {
             if self.$cond() {
                 let l = self.read_memory(self.SP);
-                self.SP += 1;
+                self.inc_sp();
                 let h = self.read_memory(self.SP);
-                self.SP += 1;
+                self.inc_sp();
                 self.PC = ((h as u16) << 8) | (l as u16);
-                return 12;
+                return true;
             }
-            6
+            false
         }
 
-        fn $fn_name2(&mut self) -> u8 {
+        fn $fn_name2(&mut self) -> bool {
             if !self.$cond() {
                 let l = self.read_memory(self.SP);
-                self.SP += 1;
+                self.inc_sp();
                 let h = self.read_memory(self.SP);
-                self.SP += 1;
+                self.inc_sp();
                 self.PC = ((h as u16) << 8) | (l as u16);
-                return 12;
+                return true;
             }
-            6
+            false
         }
     };
 }
 
 macro_rules! dad_p {
     ($fn_name: ident,$a: ident, $b: ident) => {
-        fn $fn_name(&mut self) -> u8 {
-            let mut hl = self.get_addr_hl();
+        fn $fn_name(&mut self) {
             let ab = ((self.$a as u16) << 8) | (self.$b as u16);
-            if 0xffff - hl > ab {
-                hl += ab;
-                self.set_carry(false);
-                self.set_overflow(false);
-            } else {
-                hl = ab - (0xffff - hl + 0x0001);
-                self.set_carry(true);
-                self.set_overflow(true);
-            }
-            self.H = (hl >> 8) as u8;
-            self.L = (hl & 0x0f) as u8;
-            10
+            self.dad_16bits(ab);
         }
     };
 
     ($fn_name: ident) => {
-        fn $fn_name(&mut self) -> u8 {
-            let mut hl = self.get_addr_hl();
-            let ab = self.SP;
-            if 0xffff - hl > ab {
-                hl += ab;
-                self.set_carry(false);
-                self.set_overflow(false);
-            } else {
-                hl = ab - (0xffff - hl + 0x0001);
-                self.set_carry(true);
-                self.set_overflow(true);
-            }
-            self.H = (hl >> 8) as u8;
-            self.L = (hl & 0x0f) as u8;
-            10
+        fn $fn_name(&mut self) {
+            self.dad_16bits(self.SP);
         }
     };
 }
 
 impl PP8085 {
+    /// CPU clock in Hz, used to turn T-states into emulated time.
+    pub fn set_clock_hz(&mut self, hz: u32) -> Result<(), String> {
+        if hz == 0 {
+            return Err("The clock must be at least 1 Hz".to_string());
+        }
+        self.clock_hz = hz;
+        Ok(())
+    }
+
     /// display the contents of all the registers.
     pub fn display(&self) {
         println!("A : {:#02x}\tF : {:#02x}", self.A, self.F);
@@ -537,31 +1089,736 @@ impl PP8085 {
         println!("-----------------------------");
     }
 
-    fn decode_and_run(&mut self) -> u8 {
-        match self.IR {
-            0x00 => self.nop(),
-            0x01 => self.lxi_b(),
-            0x02 => self.stax_b(),
-            0x03 => self.inx_b(),
-            0x04 => self.inr_b(),
-            0x05 => self.dcr_b(),
-            0x06 => self.mvi_b(),
-            0x07 => self.rlc(),
-            0x09 => self.dad_b(),
-            0x0A => self.ldax_b(),
-            0x0B => self.dcx_b(),
-            0x0C => self.inr_c(),
-            0x0D => self.dcr_c(),
-            0x0E => self.mvi_c(),
-            0x0F => self.rrc(),
-            0x11 => self.lxi_d(),
-            0x12 => self.stax_d(),
-            0x13 => self.inx_d(),
-            0x14 => self.inr_d(),
-            0x15 => self.dcr_d(),
-            0x16 => self.mvi_d(),
-            0x17 => self.ral(),
-            0x19 => self.dad_d(),
+    /// take the T-states collected in bus mode since the last call.
+    pub fn take_bus_trace(&mut self) -> Vec<TState> {
+        std::mem::take(&mut self.bus_trace)
+    }
+
+    /// machine cycles of the last instruction executed in bus mode.
+    pub fn machine_cycles(&self) -> &[MachineCycle] {
+        &self.machine_cycles
+    }
+
+    /// call `f` for every T-state in bus mode, instead of collecting them.
+    pub fn set_bus_callback(&mut self, f: Option<BusCallback>) {
+        self.bus_callback = f;
+    }
+
+    /// record every executed instruction with `tracer`, `None` stops tracing.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// load the segments into the mapped memory, ROM included, and set PC to
+    /// `entry`. nothing is loaded if a segment does not fit or they overlap.
+    pub fn load_segments(
+        &mut self,
+        segments: &[Segment],
+        entry: Option<u16>,
+    ) -> Result<(), String> {
+        loader::check_segments(segments)?;
+        for s in segments {
+            let unmapped = (s.origin as u32..s.end()).find(|&a| !self.bus.is_mapped(a as u16));
+            if let Some(a) = unmapped {
+                return Err(format!(
+                    "Segment at 0x{:04X} does not fit in memory, 0x{:04X} is not mapped",
+                    s.origin, a
+                ));
+            }
+        }
+        for s in segments {
+            for (i, &b) in s.data.iter().enumerate() {
+                self.bus.poke(s.origin.wrapping_add(i as u16), b);
+            }
+        }
+        if let Some(pc) = entry {
+            self.PC = pc;
+        }
+        self.history.clear();
+        Ok(())
+    }
+
+    /// load Intel HEX text into the mapped memory, PC is set to its start
+    /// address if it has one.
+    pub fn load_hex(&mut self, text: &str) -> Result<(), String> {
+        let image = ihex::parse(text)?;
+        self.load_segments(&image.segments, image.entry)
+    }
+
+    /// load Motorola S-record text into the mapped memory, PC is set to the
+    /// start address of its S9 record.
+    pub fn load_srec(&mut self, text: &str) -> Result<(), String> {
+        let image = srec::parse(text)?;
+        self.load_segments(&image.segments, image.entry)
+    }
+
+    /// map an 8155, its 256 bytes of RAM at `ram_base` and its ports at
+    /// `io_base..=io_base+5`. its TIMER OUT can then be wired to an interrupt
+    /// input with `connect_interrupt`.
+    pub fn add_8155(&mut self, ram_base: u16, io_base: u8) -> Result<(), String> {
+        if ram_base > 0xffff - (i8155::RAM_SIZE - 1) {
+            return Err("8155 RAM does not fit in memory".to_string());
+        }
+        if io_base > 0xff - (i8155::PORTS - 1) {
+            return Err("8155 ports do not fit in the I/O space".to_string());
+        }
+        let end = io_base + (i8155::PORTS - 1);
+        let bus = self.map_bus()?;
+        bus.map_io(io_base, end, "8155", Box::new(I8155::new()))?;
+        let ram = Box::new(Memory::new(i8155::RAM_SIZE as u32).unwrap());
+        if let Err(e) = bus.map(ram_base, ram_base + (i8155::RAM_SIZE - 1), "8155 ram", ram) {
+            bus.unmap_io(io_base);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// map an 8254 (or 8253) at ports `io_base..=io_base+3`. its OUT pins can
+    /// be wired to interrupt inputs with `connect_interrupt`, e.g. "out0".
+    pub fn add_8254(&mut self, io_base: u8) -> Result<(), String> {
+        if io_base > 0xff - (i8254::PORTS - 1) {
+            return Err("8254 ports do not fit in the I/O space".to_string());
+        }
+        let end = io_base + (i8254::PORTS - 1);
+        self.map_bus()?
+            .map_io(io_base, end, "8254", Box::new(I8254::new()))
+    }
+
+    /// map an 8255 at ports `io_base..=io_base+3`. its INTR A and INTR B can
+    /// be wired to interrupt inputs with `connect_interrupt`.
+    pub fn add_8255(&mut self, io_base: u8) -> Result<(), String> {
+        if io_base > 0xff - (i8255::PORTS - 1) {
+            return Err("8255 ports do not fit in the I/O space".to_string());
+        }
+        let end = io_base + (i8255::PORTS - 1);
+        self.map_bus()?
+            .map_io(io_base, end, "8255", Box::new(I8255::new()))
+    }
+
+    /// wire output `pin` of the I/O device at port `start` to an interrupt input,
+    /// through an inverter if `inverted`. the input follows the pin before every
+    /// instruction and an edge between two samples is not lost.
+    pub fn connect_interrupt(
+        &mut self,
+        start: u8,
+        pin: &str,
+        input: Interrupt,
+        inverted: bool,
+    ) -> Result<(), String> {
+        let line = match self.system_bus().and_then(|b| b.io_line(start, pin)) {
+            Some(l) => l,
+            None => {
+                return Err(format!(
+                    "No output {} on a device at port 0x{:02X}",
+                    pin, start
+                ))
+            }
+        };
+        // edges from before the wire was connected are not seen
+        line.sample();
+        let wire = Wire {
+            input,
+            line,
+            inverted,
+        };
+        wire.sample(&mut self.interrupts);
+        self.wires.push(wire);
+        Ok(())
+    }
+
+    /// replace the `SystemBus` with another implementation of `Bus`. the
+    /// methods that map devices, latches and peripherals need a `SystemBus`
+    /// and fail or do nothing after this. the history is cleared.
+    pub fn set_bus(&mut self, bus: Box<dyn Bus>) {
+        self.bus = bus;
+        self.history.clear();
+    }
+
+    pub fn bus(&self) -> &dyn Bus {
+        self.bus.as_ref()
+    }
+
+    /// the `SystemBus` the devices are mapped on, None after `set_bus`.
+    pub fn system_bus(&self) -> Option<&SystemBus> {
+        (self.bus.as_ref() as &dyn Any).downcast_ref::<SystemBus>()
+    }
+
+    /// to map devices, the history is cleared since it cannot undo the change.
+    pub fn system_bus_mut(&mut self) -> Option<&mut SystemBus> {
+        self.history.clear();
+        self.devices_mut()
+    }
+
+    fn devices_mut(&mut self) -> Option<&mut SystemBus> {
+        (self.bus.as_mut() as &mut dyn Any).downcast_mut::<SystemBus>()
+    }
+
+    /// the `SystemBus` to map a device on.
+    fn map_bus(&mut self) -> Result<&mut SystemBus, String> {
+        self.devices_mut()
+            .ok_or_else(|| "Devices can only be mapped on a SystemBus".to_string())
+    }
+
+    /// the I/O device at port `io_base` if it is a `T`.
+    fn io_device<T: IoDevice>(&self, io_base: u8) -> Option<&T> {
+        self.system_bus()?.io_device::<T>(io_base)
+    }
+
+    fn io_device_mut<T: IoDevice>(&mut self, io_base: u8) -> Option<&mut T> {
+        self.devices_mut()?.io_device_as_mut::<T>(io_base)
+    }
+
+    /// mark `start..=end` as RAM, ROM or unmapped, the range has to be in a
+    /// `Memory` mapped on the `SystemBus`, or be the `Memory` given to `set_bus`.
+    pub fn set_region(&mut self, start: u16, end: u16, kind: RegionKind) -> Result<(), String> {
+        range_size(start, end)?;
+        if let Some(mem) = (self.bus.as_mut() as &mut dyn Any).downcast_mut::<Memory>() {
+            mem.set_region(start, end, kind);
+            return Ok(());
+        }
+        let memory = self
+            .devices_mut()
+            .and_then(|b| b.mapping_mut(start, end))
+            .and_then(|m| {
+                let base = m.start;
+                m.downcast_mut::<Memory>().map(|mem| (mem, base))
+            });
+        match memory {
+            Some((mem, base)) => {
+                mem.set_region(start - base, end - base, kind);
+                Ok(())
+            }
+            None => Err(format!("No memory mapped at {:04X}-{:04X}", start, end)),
+        }
+    }
+
+    /// the mapped address ranges of the `SystemBus`.
+    pub fn memory_map(&self) -> Vec<Region> {
+        self.system_bus().map(|b| b.regions()).unwrap_or_default()
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.A,
+            f: self.F,
+            b: self.B,
+            c: self.C,
+            d: self.D,
+            e: self.E,
+            h: self.H,
+            l: self.L,
+            sp: self.SP,
+            pc: self.PC,
+        }
+    }
+
+    fn record_access(&mut self, kind: MachineCycleKind, address: u16, data: u8) {
+        if self.recording() {
+            self.bus_accesses
+                .push(MachineCycle::new(kind, address, data, 3));
+        }
+    }
+
+    /// emit the machine cycles of the instruction that just finished.
+    fn emit_machine_cycles(&mut self, cycles: Vec<MachineCycle>) {
+        for c in cycles.iter() {
+            for t in c.t_states() {
+                match self.bus_callback.as_mut() {
+                    Some(f) => f(&t),
+                    None => self.bus_trace.push(t),
+                }
+            }
+        }
+        self.machine_cycles = cycles;
+    }
+
+    /// SOD levels written by SIM, each with the cycle count of the write.
+    pub fn sod_log(&self) -> &[(u64, bool)] {
+        &self.sod_log
+    }
+
+    /// stop when `reg cmp value` becomes true, e.g. ("hl", ">=", 0x2000).
+    pub fn add_register_condition(
+        &mut self,
+        reg: &str,
+        cmp: &str,
+        value: u16,
+    ) -> Result<u32, String> {
+        let reg: Register = reg.parse()?;
+        let cmp: Compare = cmp.parse()?;
+        Ok(self
+            .breakpoints
+            .add(Breakpoint::Register { reg, cmp, value }))
+    }
+
+    /// execution cycle
+    pub fn run(&mut self) -> Result<(), CpuError> {
+        while !self.HLT {
+            if self.cycles == 0 {
+                self.cycles += self.step()?.t_states as u32;
+            }
+            self.cycles -= 1;
+        }
+        Ok(())
+    }
+
+    /// execute one instruction and stop with no regard to cycles.
+    /// acknowledging a pending interrupt counts as one step.
+    pub fn run_next(&mut self) -> Result<(), CpuError> {
+        self.step()?;
+        Ok(())
+    }
+
+    /// execute instructions until at least `n` T-states have elapsed.
+    /// a halted cpu idles one T-state at a time waiting for an interrupt.
+    /// returns the number of T-states actually run.
+    pub fn run_for_cycles(&mut self, n: u64) -> Result<u64, CpuError> {
+        let start = self.total_cycles;
+        while self.total_cycles - start < n {
+            self.step()?;
+        }
+        Ok(self.total_cycles - start)
+    }
+
+    /// execute instructions for `ns` nanoseconds of emulated time at the
+    /// configured clock. returns the number of T-states actually run.
+    pub fn run_for_duration(&mut self, ns: u64) -> Result<u64, CpuError> {
+        let n = (ns as u128 * self.clock_hz as u128 / 1_000_000_000) as u64;
+        self.run_for_cycles(n)
+    }
+
+    /// save the I/O device at `port` into the step being recorded, once, before
+    /// a read or write can change it.
+    fn record_io_device(&mut self, port: u8) {
+        if !self.history.recording() {
+            return;
+        }
+        if let Some(start) = self.bus.io_start(port) {
+            if !self.history.has_io(start) {
+                let old = self.bus.io_save(start).unwrap();
+                self.history.record_io(start, old);
+            }
+        }
+    }
+
+    /// clock the I/O devices, the ones the clock changes are saved into the
+    /// step being recorded.
+    fn tick_io(&mut self, cycles: u32) {
+        if !self.history.recording() {
+            self.bus.tick(cycles);
+            return;
+        }
+        let before: Vec<(u8, Vec<u8>)> = self
+            .bus
+            .io_starts()
+            .into_iter()
+            .filter(|s| !self.history.has_io(*s))
+            .filter_map(|s| Some((s, self.bus.io_save(s)?)))
+            .collect();
+        self.bus.tick(cycles);
+        for (start, old) in before {
+            if self.bus.io_save(start).as_ref() != Some(&old) {
+                self.history.record_io(start, old);
+            }
+        }
+    }
+
+    /// service a pending interrupt or execute the next instruction.
+    pub fn step(&mut self) -> Result<StepInfo, CpuError> {
+        self.fault = None;
+        let info = self.execute_next();
+        match self.fault.take() {
+            Some(e) => Err(e),
+            None => Ok(info),
+        }
+    }
+
+    /// step until `stop` returns true, the cpu halts, an error is raised
+    /// or `max_steps` steps have been run.
+    pub fn run_until<F>(&mut self, max_steps: u64, mut stop: F) -> StopReason
+    where
+        F: FnMut(&PP8085) -> bool,
+    {
+        for _ in 0..max_steps {
+            if let Err(e) = self.step() {
+                return StopReason::Error(e);
+            }
+            if stop(self) {
+                return StopReason::Condition;
+            }
+            if self.HLT {
+                return StopReason::Halted;
+            }
+        }
+        StopReason::StepLimit
+    }
+
+    /// step until a breakpoint is hit, the cpu halts, an error is raised
+    /// or `max_instructions` steps have been run.
+    pub fn run_until_break(&mut self, max_instructions: u64) -> StopReason {
+        for _ in 0..max_instructions {
+            let before = self.registers();
+            let info = match self.step() {
+                Ok(info) => info,
+                Err(e) => return StopReason::Error(e),
+            };
+            if let Some(id) = self.check_breakpoints(&before, &info) {
+                return StopReason::Breakpoint(id);
+            }
+            if self.HLT {
+                return StopReason::Halted;
+            }
+        }
+        StopReason::StepLimit
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// id of the first breakpoint hit by the step that just finished.
+    fn check_breakpoints(&self, before: &Registers, info: &StepInfo) -> Option<u32> {
+        if self.breakpoints.is_empty() {
+            return None;
+        }
+        let mut mem = Vec::new();
+        let mut io = Vec::new();
+        let mut fetched = 0;
+        let len = PP8085::instruction_len(info);
+        for a in self.bus_accesses.iter() {
+            match a.kind {
+                MachineCycleKind::MemoryRead if fetched < len => fetched += 1,
+                MachineCycleKind::MemoryRead => mem.push((a.address, false)),
+                MachineCycleKind::MemoryWrite => mem.push((a.address, true)),
+                MachineCycleKind::IoRead => io.push((a.address as u8, false)),
+                MachineCycleKind::IoWrite => io.push((a.address as u8, true)),
+                _ => (),
+            }
+        }
+        self.breakpoints.check(&StepAccesses {
+            before,
+            after: &self.registers(),
+            mem: &mem,
+            io: &io,
+        })
+    }
+
+    /// number of instruction bytes read by the step, zero for an interrupt.
+    fn instruction_len(info: &StepInfo) -> usize {
+        match info.interrupt {
+            Some(_) => 0,
+            None => opcodes::instruction_length(info.opcode),
+        }
+    }
+
+    pub fn to_save_state(&self) -> SaveState {
+        SaveState {
+            version: STATE_VERSION,
+            registers: self.registers(),
+            ir: self.IR,
+            ie: self.IE,
+            hlt: self.HLT,
+            ei_delay: self.ei_delay,
+            cycles: self.cycles,
+            total_cycles: self.total_cycles,
+            clock_hz: self.clock_hz,
+            interrupts: self.interrupts.clone(),
+            sid: self.sid,
+            sod: self.sod,
+            undocumented: self.undocumented,
+            memory: self
+                .system_bus()
+                .map(|b| b.save_devices())
+                .unwrap_or_default(),
+            io_ports: self.system_bus().map(|b| b.io_ports()).unwrap_or_default(),
+            io_devices: self
+                .system_bus()
+                .map(|b| b.save_io_devices())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// resume from `state`, the history is cleared. the machine is left as it
+    /// was if the state cannot be loaded.
+    pub fn restore_save_state(&mut self, state: SaveState) -> Result<(), String> {
+        state.validate()?;
+        match self.devices_mut() {
+            Some(bus) => bus.load_devices(&state.memory, &state.io_devices, state.io_ports)?,
+            // the devices of another bus are not saved
+            None if state.memory.is_empty()
+                && state.io_devices.is_empty()
+                && state.io_ports.is_empty() => {}
+            None => return Err("Save state devices need a SystemBus".to_string()),
+        }
+        let r = state.registers;
+        self.A = r.a;
+        self.F = r.f;
+        self.B = r.b;
+        self.C = r.c;
+        self.D = r.d;
+        self.E = r.e;
+        self.H = r.h;
+        self.L = r.l;
+        self.SP = r.sp;
+        self.PC = r.pc;
+        self.IR = state.ir;
+        self.IE = state.ie;
+        self.HLT = state.hlt;
+        self.ei_delay = state.ei_delay;
+        self.cycles = state.cycles;
+        self.total_cycles = state.total_cycles;
+        self.clock_hz = state.clock_hz;
+        self.interrupts = state.interrupts;
+        self.sid = state.sid;
+        self.sod = state.sod;
+        self.undocumented = state.undocumented;
+        self.sod_log.clear();
+        self.history.clear();
+        Ok(())
+    }
+
+    /// compact binary save state, see `state::SaveState::to_bytes`.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.to_save_state().to_bytes()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        self.restore_save_state(SaveState::from_bytes(data)?)
+    }
+
+    /// human readable JSON save state.
+    pub fn save_state_json(&self) -> String {
+        self.to_save_state().to_json()
+    }
+
+    pub fn load_state_json(&mut self, json: &str) -> Result<(), String> {
+        self.restore_save_state(SaveState::from_json(json)?)
+    }
+
+    fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            regs: self.registers(),
+            ir: self.IR,
+            ie: self.IE,
+            hlt: self.HLT,
+            ei_delay: self.ei_delay,
+            interrupts: self.interrupts.clone(),
+            cycles: self.cycles,
+            total_cycles: self.total_cycles,
+            sod: self.sod,
+            sod_log_len: self.sod_log.len(),
+        }
+    }
+
+    fn restore(&mut self, s: CpuSnapshot) {
+        self.A = s.regs.a;
+        self.F = s.regs.f;
+        self.B = s.regs.b;
+        self.C = s.regs.c;
+        self.D = s.regs.d;
+        self.E = s.regs.e;
+        self.H = s.regs.h;
+        self.L = s.regs.l;
+        self.SP = s.regs.sp;
+        self.PC = s.regs.pc;
+        self.IR = s.ir;
+        self.IE = s.ie;
+        self.HLT = s.hlt;
+        self.ei_delay = s.ei_delay;
+        self.interrupts = s.interrupts;
+        self.cycles = s.cycles;
+        self.total_cycles = s.total_cycles;
+        self.sod = s.sod;
+        self.sod_log.truncate(s.sod_log_len);
+    }
+
+    /// accesses are collected for bus mode, the tracer and the breakpoints.
+    fn recording(&self) -> bool {
+        self.bus_mode || self.tracer.is_some() || !self.breakpoints.is_empty()
+    }
+
+    /// record an error, only the first one of an instruction is kept.
+    fn raise(&mut self, e: CpuError) {
+        if self.fault.is_none() {
+            self.fault = Some(e);
+        }
+    }
+
+    /// a halted cpu spends one cycle.
+    fn execute_next(&mut self) -> StepInfo {
+        if self.history.depth() > 0 {
+            self.history.begin(self.snapshot());
+        }
+        self.bus_accesses.clear();
+        self.instr_pc = self.PC;
+        let before = self.registers();
+        for w in self.wires.iter() {
+            w.sample(&mut self.interrupts);
+        }
+        let (opcode, cycles, interrupt) = if let Some(int) = self.service_interrupts() {
+            let op = match int {
+                Interrupt::Intr => self.interrupts.intr_opcode(),
+                _ => 0x00,
+            };
+            (op, 12, Some(int))
+        } else if !self.HLT {
+            self.IR = self.read_8bits();
+            let c = self.decode_and_run();
+            if self.bus_mode {
+                let mc = machine_cycle::compose(&self.bus_accesses, c);
+                self.emit_machine_cycles(mc);
+            }
+            (self.IR, c, None)
+        } else {
+            if self.bus_mode {
+                let mc = vec![MachineCycle::new(MachineCycleKind::Halt, self.PC, 0, 1)];
+                self.emit_machine_cycles(mc);
+            }
+            (0x76, 1, None)
+        };
+        self.total_cycles += cycles as u64;
+        self.tick_io(cycles as u32);
+        let info = StepInfo {
+            pc: self.instr_pc,
+            opcode,
+            t_states: cycles,
+            interrupt,
+            halted: self.HLT,
+        };
+        if self.tracer.is_some() && !self.bus_accesses.is_empty() {
+            self.trace(before, &info);
+        }
+        self.history.end();
+        info
+    }
+
+    /// build the trace record of the step that just finished.
+    /// a halted cpu makes no bus accesses and is not traced.
+    fn trace(&mut self, before: Registers, info: &StepInfo) {
+        let len = PP8085::instruction_len(info);
+        let mut rec = TraceRecord {
+            pc: info.pc,
+            bytes: Vec::new(),
+            mnemonic: String::new(),
+            interrupt: info.interrupt,
+            before,
+            after: self.registers(),
+            mem_reads: Vec::new(),
+            mem_writes: Vec::new(),
+            io_reads: Vec::new(),
+            io_writes: Vec::new(),
+            cycles: info.t_states,
+        };
+        for a in self.bus_accesses.iter() {
+            let access = Access {
+                address: a.address,
+                data: a.data,
+            };
+            let port = Access {
+                address: a.address & 0x00ff,
+                data: a.data,
+            };
+            match a.kind {
+                MachineCycleKind::MemoryRead if rec.bytes.len() < len => rec.bytes.push(a.data),
+                MachineCycleKind::MemoryRead => rec.mem_reads.push(access),
+                MachineCycleKind::MemoryWrite => rec.mem_writes.push(access),
+                MachineCycleKind::IoRead => rec.io_reads.push(port),
+                MachineCycleKind::IoWrite => rec.io_writes.push(port),
+                _ => (),
+            }
+        }
+        rec.mnemonic = match info.interrupt {
+            Some(Interrupt::Trap) => "trap".to_string(),
+            Some(Interrupt::Rst75) => "rst 7.5".to_string(),
+            Some(Interrupt::Rst65) => "rst 6.5".to_string(),
+            Some(Interrupt::Rst55) => "rst 5.5".to_string(),
+            Some(Interrupt::Intr) => format!("intr ({})", opcodes::info(info.opcode).format(&[])),
+            None => match disasm8085::decode(&rec.bytes, rec.pc, None) {
+                Some(ins) => ins.to_string(),
+                None => String::new(),
+            },
+        };
+        if let Some(t) = self.tracer.as_mut() {
+            t.record(rec);
+        }
+    }
+
+    /// acknowledge the highest priority pending interrupt, if any.
+    /// the acknowledge sequence takes 12 cycles.
+    fn service_interrupts(&mut self) -> Option<Interrupt> {
+        let ie = self.IE && !self.ei_delay;
+        self.ei_delay = false;
+        let int = self.interrupts.pending(ie)?;
+        self.interrupts.acknowledge(int, self.IE);
+        self.IE = false;
+        self.HLT = false;
+        let ack = if int == Interrupt::Intr {
+            let op = self.interrupts.intr_opcode();
+            MachineCycle::new(MachineCycleKind::InterruptAck, self.PC, op, 6)
+        } else {
+            MachineCycle::new(MachineCycleKind::RestartAck, self.PC, 0, 6)
+        };
+        self.dec_sp();
+        self.write_memory(self.SP, (self.PC >> 8) as u8);
+        self.dec_sp();
+        self.write_memory(self.SP, (self.PC & 0x00ff) as u8);
+        self.PC = int.vector(self.interrupts.intr_opcode());
+        if self.bus_mode {
+            let mut mc = vec![ack];
+            mc.extend_from_slice(&self.bus_accesses);
+            self.emit_machine_cycles(mc);
+        }
+        Some(int)
+    }
+
+    /// execute the instruction in IR, its T-states come from `OPCODES`.
+    /// conditional jumps, calls and returns tell whether they were taken.
+    fn decode_and_run(&mut self) -> u8 {
+        let info = &OPCODES[self.IR as usize];
+        if info.undocumented && !self.undocumented {
+            self.illegal_opcode();
+            return 4;
+        }
+        let mut taken = true;
+        match self.IR {
+            0x00 => self.nop(),
+            0x01 => self.lxi_b(),
+            0x02 => self.stax_b(),
+            0x03 => self.inx_b(),
+            0x04 => self.inr_b(),
+            0x05 => self.dcr_b(),
+            0x06 => self.mvi_b(),
+            0x07 => self.rlc(),
+            0x08 => self.dsub(),
+            0x09 => self.dad_b(),
+            0x0A => self.ldax_b(),
+            0x0B => self.dcx_b(),
+            0x0C => self.inr_c(),
+            0x0D => self.dcr_c(),
+            0x0E => self.mvi_c(),
+            0x0F => self.rrc(),
+            0x10 => self.arhl(),
+            0x11 => self.lxi_d(),
+            0x12 => self.stax_d(),
+            0x13 => self.inx_d(),
+            0x14 => self.inr_d(),
+            0x15 => self.dcr_d(),
+            0x16 => self.mvi_d(),
+            0x17 => self.ral(),
+            0x18 => self.rdel(),
+            0x19 => self.dad_d(),
             0x1A => self.ldax_d(),
             0x1B => self.dcx_d(),
             0x1C => self.inr_e(),
@@ -576,6 +1833,7 @@ impl PP8085 {
             0x25 => self.dcr_h(),
             0x26 => self.mvi_h(),
             0x27 => self.daa(),
+            0x28 => self.ldhi(),
             0x29 => self.dad_h(),
             0x2A => self.lhld(),
             0x2B => self.dcx_h(),
@@ -591,6 +1849,7 @@ impl PP8085 {
             0x35 => self.dcr_m(),
             0x36 => self.mvi_m(),
             0x37 => self.stc(),
+            0x38 => self.ldsi(),
             0x39 => self.dad_sp(),
             0x3A => self.lda(),
             0x3B => self.dcx_sp(),
@@ -726,77 +1985,140 @@ impl PP8085 {
             0xBD => self.cmp_l(),
             0xBE => self.cmp_m(),
             0xBF => self.cmp_a(),
-            0xC0 => self.rnz(),
+            0xC0 => taken = self.rnz(),
             0xC1 => self.pop_b(),
-            0xC2 => self.jnz(),
+            0xC2 => taken = self.jnz(),
             0xC3 => self.jmp(),
-            0xC4 => self.cnz(),
+            0xC4 => taken = self.cnz(),
             0xC5 => self.push_b(),
             0xC6 => self.adi(),
             0xC7 => self.rst_0(),
-            0xC8 => self.rz(),
+            0xC8 => taken = self.rz(),
             0xC9 => self.ret(),
-            0xCA => self.jz(),
-            0xCC => self.cz(),
+            0xCA => taken = self.jz(),
+            0xCB => taken = self.rstv(),
+            0xCC => taken = self.cz(),
             0xCD => self.call(),
             0xCE => self.aci(),
             0xCF => self.rst_1(),
-            0xD0 => self.rnc(),
+            0xD0 => taken = self.rnc(),
             0xD1 => self.pop_d(),
-            0xD2 => self.jnc(),
+            0xD2 => taken = self.jnc(),
             0xD3 => self.out(),
-            0xD4 => self.cnc(),
+            0xD4 => taken = self.cnc(),
             0xD5 => self.push_d(),
             0xD6 => self.sui(),
             0xD7 => self.rst_2(),
-            0xD8 => self.rc(),
-            0xDA => self.jc(),
+            0xD8 => taken = self.rc(),
+            0xD9 => self.shlx(),
+            0xDA => taken = self.jc(),
             0xDB => self.i_n(),
-            0xDC => self.cc(),
+            0xDC => taken = self.cc(),
+            0xDD => taken = self.jnk(),
             0xDE => self.sbi(),
             0xDF => self.rst_3(),
-            0xE0 => self.rpo(),
+            0xE0 => taken = self.rpo(),
             0xE1 => self.pop_h(),
-            0xE2 => self.jpo(),
+            0xE2 => taken = self.jpo(),
             0xE3 => self.xthl(),
-            0xE4 => self.cpo(),
+            0xE4 => taken = self.cpo(),
             0xE5 => self.push_h(),
             0xE6 => self.ani(),
             0xE7 => self.rst_4(),
-            0xE8 => self.rpe(),
+            0xE8 => taken = self.rpe(),
             0xE9 => self.pchl(),
-            0xEA => self.jpe(),
+            0xEA => taken = self.jpe(),
             0xEB => self.xchg(),
-            0xEC => self.cpe(),
+            0xEC => taken = self.cpe(),
+            0xED => self.lhlx(),
             0xEE => self.xri(),
             0xEF => self.rst_5(),
-            0xF0 => self.rp(),
+            0xF0 => taken = self.rp(),
             0xF1 => self.pop_psw(),
-            0xF2 => self.jp(),
+            0xF2 => taken = self.jp(),
             0xF3 => self.di(),
-            0xF4 => self.cp(),
+            0xF4 => taken = self.cp(),
             0xF5 => self.push_psw(),
             0xF6 => self.ori(),
             0xF7 => self.rst_6(),
-            0xF8 => self.rm(),
+            0xF8 => taken = self.rm(),
             0xF9 => self.sphl(),
-            0xFA => self.jm(),
+            0xFA => taken = self.jm(),
             0xFB => self.ei(),
-            0xFC => self.cm(),
+            0xFC => taken = self.cm(),
+            0xFD => taken = self.jk(),
             0xFE => self.cpi(),
             0xFF => self.rst_7(),
-            _ => {
-                panic!("{:#02x} is unimplemented", self.IR)
-            }
+        }
+        let (t_taken, t_not_taken) = info.t_states;
+        if taken {
+            t_taken
+        } else {
+            t_not_taken
         }
     }
 
+    /// the opcode is not executed, PC is left on it.
+    fn illegal_opcode(&mut self) {
+        self.raise(CpuError::IllegalOpcode {
+            pc: self.instr_pc,
+            opcode: self.IR,
+        });
+        self.PC = self.instr_pc;
+    }
+
     fn write_memory(&mut self, addr: u16, content: u8) {
-        self.memory.write(addr, content);
+        if self.history.recording() {
+            self.history.record_mem(addr, self.bus.peek(addr));
+        }
+        self.bus.write(addr, content);
+        self.check_fault();
+        self.record_access(MachineCycleKind::MemoryWrite, addr, content);
     }
 
     pub fn read_memory(&mut self, addr: u16) -> u8 {
-        self.memory.read(addr)
+        let data = self.bus.read(addr);
+        self.check_fault();
+        self.record_access(MachineCycleKind::MemoryRead, addr, data);
+        data
+    }
+
+    fn check_fault(&mut self) {
+        match self.bus.take_fault() {
+            Some(Fault::OutOfRange(addr)) => self.raise(CpuError::MemoryOutOfRange {
+                pc: self.instr_pc,
+                addr,
+            }),
+            Some(Fault::RomWrite(addr)) => self.raise(CpuError::RomWrite {
+                pc: self.instr_pc,
+                addr,
+            }),
+            None => (),
+        }
+    }
+
+    /// SP - 1 before a push, wrapping below 0x0000 is an error
+    /// unless there is memory at 0xffff.
+    fn dec_sp(&mut self) {
+        if self.SP == 0x0000 && !self.bus.is_mapped(0xffff) {
+            self.raise(CpuError::StackWrap {
+                pc: self.instr_pc,
+                sp: 0xffff,
+            });
+        }
+        self.SP = self.SP.wrapping_sub(1);
+    }
+
+    /// SP + 1 after a pop, wrapping above 0xffff is an error
+    /// unless there is memory at 0x0000.
+    fn inc_sp(&mut self) {
+        if self.SP == 0xffff && !self.bus.is_mapped(0x0000) {
+            self.raise(CpuError::StackWrap {
+                pc: self.instr_pc,
+                sp: 0x0000,
+            });
+        }
+        self.SP = self.SP.wrapping_add(1);
     }
 
     /// return parity flag
@@ -817,6 +2139,8 @@ impl PP8085 {
     // utility functions to set flags according to the results.
     // 7  6  5  4  3  2  1  0
     // S  Z (K) A  0  P (V) C
+    // K and V are undocumented. V is the two's complement overflow and K is
+    // V xor S after arithmetic, INX and DCX set K when the register pair wraps.
     fn set_sign(&mut self, b: bool) {
         if b {
             self.F |= 1 << 7;
@@ -843,16 +2167,26 @@ impl PP8085 {
 
     fn set_overflow(&mut self, b: bool) {
         if b {
-            self.F |= 1 << 5;
             self.F |= 1 << 1;
         } else {
-            self.F &= !(1 << 5);
             self.F &= !(1 << 1);
         }
     }
 
     fn get_overflow(&self) -> bool {
-        return (self.F & 1 << 5) != 0x0;
+        (self.F & 1 << 1) != 0x0
+    }
+
+    fn set_k(&mut self, b: bool) {
+        if b {
+            self.F |= 1 << 5;
+        } else {
+            self.F &= !(1 << 5);
+        }
+    }
+
+    fn get_k(&self) -> bool {
+        (self.F & 1 << 5) != 0x0
     }
 
     fn set_auxiliary_carry(&mut self, b: bool) {
@@ -891,6 +2225,83 @@ impl PP8085 {
         return (self.F & 1 << 0) != 0x0;
     }
 
+    /// set S, Z and P from a result.
+    fn set_zsp(&mut self, num: u8) {
+        self.set_sign(num & 1 << 7 != 0);
+        self.set_zero(num == 0x00);
+        self.set_parity(PP8085::find_parity(num));
+    }
+
+    /// K follows V xor S after arithmetic.
+    fn set_k_from_vs(&mut self) {
+        self.set_k(self.get_overflow() ^ self.get_sign());
+    }
+
+    /// return A + num + carry and set all flags.
+    fn add_8bits(&mut self, num: u8, carry: bool) -> u8 {
+        let c = carry as u8;
+        let res = self.A.wrapping_add(num).wrapping_add(c);
+        self.set_carry(self.A as u16 + num as u16 + c as u16 > 0xff);
+        self.set_auxiliary_carry((self.A & 0x0f) + (num & 0x0f) + c > 0x0f);
+        self.set_overflow((self.A ^ res) & (num ^ res) & 1 << 7 != 0);
+        self.set_zsp(res);
+        self.set_k_from_vs();
+        res
+    }
+
+    /// return A - num - borrow and set all flags.
+    fn sub_8bits(&mut self, num: u8, borrow: bool) -> u8 {
+        let b = borrow as u8;
+        let res = self.A.wrapping_sub(num).wrapping_sub(b);
+        self.set_carry((self.A as u16) < num as u16 + b as u16);
+        // AC is the carry out of bit 3 of A + !num + !borrow, not a borrow
+        self.set_auxiliary_carry((self.A & 0x0f) >= (num & 0x0f) + b);
+        self.set_overflow((self.A ^ num) & (self.A ^ res) & 1 << 7 != 0);
+        self.set_zsp(res);
+        self.set_k_from_vs();
+        res
+    }
+
+    /// return num + 1, carry is not affected.
+    fn inr_8bits(&mut self, num: u8) -> u8 {
+        let res = num.wrapping_add(1);
+        self.set_auxiliary_carry(num & 0x0f == 0x0f);
+        self.set_overflow(num == 0x7f);
+        self.set_zsp(res);
+        self.set_k_from_vs();
+        res
+    }
+
+    /// return num - 1, carry is not affected.
+    fn dcr_8bits(&mut self, num: u8) -> u8 {
+        let res = num.wrapping_sub(1);
+        // num + 0xff carries out of bit 3 unless the low nibble is 0
+        self.set_auxiliary_carry(num & 0x0f != 0x00);
+        self.set_overflow(num == 0x80);
+        self.set_zsp(res);
+        self.set_k_from_vs();
+        res
+    }
+
+    /// flags after ANA, XRA and ORA. carry and V are cleared,
+    /// AC is set by ANA and cleared by the others.
+    fn set_logic_flags(&mut self, ac: bool) {
+        self.set_carry(false);
+        self.set_auxiliary_carry(ac);
+        self.set_overflow(false);
+        self.set_zsp(self.A);
+        self.set_k_from_vs();
+    }
+
+    /// HL = HL + num, only carry is affected.
+    fn dad_16bits(&mut self, num: u16) {
+        let hl = self.get_addr_hl();
+        let res = hl.wrapping_add(num);
+        self.set_carry(hl as u32 + num as u32 > 0xffff);
+        self.H = (res >> 8) as u8;
+        self.L = (res & 0x00ff) as u8;
+    }
+
     /// return the address stored in HL register pair as a u16.
     fn get_addr_hl(&self) -> u16 {
         (self.H as u16) << 8 | self.L as u16
@@ -908,39 +2319,35 @@ impl PP8085 {
 
     fn read_8bits(&mut self) -> u8 {
         let r = self.read_memory(self.PC);
-        self.PC += 1;
+        self.PC = self.PC.wrapping_add(1);
         r
     }
 
     fn read_16bits(&mut self) -> (u8, u8) {
         let l = self.read_memory(self.PC);
-        self.PC += 1;
+        self.PC = self.PC.wrapping_add(1);
         let h = self.read_memory(self.PC);
-        self.PC += 1;
+        self.PC = self.PC.wrapping_add(1);
         (l, h)
     }
 
     /// NOP
-    fn nop(&mut self) -> u8 {
-        4
-    }
+    fn nop(&mut self) {}
 
     // EI
-    fn ei(&mut self) -> u8 {
+    fn ei(&mut self) {
         self.IE = true;
-        4
+        self.ei_delay = true;
     }
 
     // DI
-    fn di(&mut self) -> u8 {
+    fn di(&mut self) {
         self.IE = false;
-        4
     }
 
     // HLT
-    fn hlt(&mut self) -> u8 {
+    fn hlt(&mut self) {
         self.HLT = true;
-        5
     }
 
     // MOV Rs, RD instructions
@@ -1019,389 +2426,300 @@ impl PP8085 {
     mov_rd_m!(mov_l_m, L);
 
     /// MVI B
-    fn mvi_b(&mut self) -> u8 {
+    fn mvi_b(&mut self) {
         let op = self.read_8bits();
         self.B = op;
-        7
     }
 
     /// MVI C
-    fn mvi_c(&mut self) -> u8 {
+    fn mvi_c(&mut self) {
         let op = self.read_8bits();
         self.C = op;
-        7
     }
 
     /// MVI D
-    fn mvi_d(&mut self) -> u8 {
+    fn mvi_d(&mut self) {
         let op = self.read_8bits();
         self.D = op;
-        7
     }
 
     /// MVI E
-    fn mvi_e(&mut self) -> u8 {
+    fn mvi_e(&mut self) {
         let op = self.read_8bits();
         self.E = op;
-        7
     }
 
     /// MVI H
-    fn mvi_h(&mut self) -> u8 {
+    fn mvi_h(&mut self) {
         let op = self.read_8bits();
         self.H = op;
-        7
     }
 
     /// MVI L
-    fn mvi_l(&mut self) -> u8 {
+    fn mvi_l(&mut self) {
         let op = self.read_8bits();
         self.L = op;
-        7
     }
 
     /// MVI A
-    fn mvi_a(&mut self) -> u8 {
+    fn mvi_a(&mut self) {
         let op = self.read_8bits();
         self.A = op;
-        7
     }
 
     /// MVI M
-    fn mvi_m(&mut self) -> u8 {
+    fn mvi_m(&mut self) {
         let op = self.read_8bits();
         self.write_memory(self.get_addr_hl(), op);
-        10
     }
 
     /// LXI B
-    fn lxi_b(&mut self) -> u8 {
+    fn lxi_b(&mut self) {
         let (opl, oph) = self.read_16bits();
         self.B = oph;
         self.C = opl;
-        3
     }
 
     /// LXI D
-    fn lxi_d(&mut self) -> u8 {
+    fn lxi_d(&mut self) {
         let (opl, oph) = self.read_16bits();
         self.D = oph;
         self.E = opl;
-        3
     }
 
     /// LXI H
-    fn lxi_h(&mut self) -> u8 {
+    fn lxi_h(&mut self) {
         let (opl, oph) = self.read_16bits();
         self.H = oph;
         self.L = opl;
-        3
     }
 
     /// LXI SP
-    fn lxi_sp(&mut self) -> u8 {
+    fn lxi_sp(&mut self) {
         let (opl, oph) = self.read_16bits();
         self.SP = (oph as u16) << 8 | opl as u16;
-        3
     }
 
     /// STAX B
     /// store A indirect
-    fn stax_b(&mut self) -> u8 {
+    fn stax_b(&mut self) {
         self.write_memory(self.get_addr_bc(), self.A);
-        7
     }
 
     /// STAX D
     /// store A indirect
-    fn stax_d(&mut self) -> u8 {
+    fn stax_d(&mut self) {
         self.write_memory(self.get_addr_de(), self.A);
-        7
     }
 
     /// LDAX B
     /// load A indirect
-    fn ldax_b(&mut self) -> u8 {
+    fn ldax_b(&mut self) {
         self.A = self.read_memory(self.get_addr_bc());
-        7
     }
 
     /// LDAX D
     /// load A indirect
-    fn ldax_d(&mut self) -> u8 {
+    fn ldax_d(&mut self) {
         self.A = self.read_memory(self.get_addr_de());
-        7
     }
 
     /// STA XXXX
     /// store A direct
-    fn sta(&mut self) -> u8 {
+    fn sta(&mut self) {
         let (opl, oph) = self.read_16bits();
         self.write_memory((oph as u16) << 8 | opl as u16, self.A);
-        13
     }
 
     /// LDA XXXX
     /// load A direct
-    fn lda(&mut self) -> u8 {
+    fn lda(&mut self) {
         let (opl, oph) = self.read_16bits();
         self.A = self.read_memory((oph as u16) << 8 | opl as u16);
-        13
     }
 
     /// SHLD XXXX
     /// store HL direct
-    fn shld(&mut self) -> u8 {
+    fn shld(&mut self) {
         let (opl, oph) = self.read_16bits();
         let addr = (oph as u16) << 8 | opl as u16;
-        self.write_memory(addr, self.H);
-        self.write_memory(addr + 1, self.L);
-        16
+        self.write_memory(addr, self.L);
+        self.write_memory(addr.wrapping_add(1), self.H);
     }
 
     /// LHLD XXXX
     /// load HL direct
-    fn lhld(&mut self) -> u8 {
+    fn lhld(&mut self) {
         let (opl, oph) = self.read_16bits();
         let addr = (oph as u16) << 8 | opl as u16;
         self.L = self.read_memory(addr);
-        self.H = self.read_memory(addr + 1);
-        16
+        self.H = self.read_memory(addr.wrapping_add(1));
     }
 
     /// XCHG
     /// exchange DE and HL registers
-    fn xchg(&mut self) -> u8 {
+    fn xchg(&mut self) {
         std::mem::swap(&mut self.H, &mut self.D);
         std::mem::swap(&mut self.L, &mut self.E);
-        4
     }
 
     /// PUSH B
     /// push BC on stack
-    fn push_b(&mut self) -> u8 {
-        self.SP -= 1;
+    fn push_b(&mut self) {
+        self.dec_sp();
         self.write_memory(self.SP, self.B);
-        self.SP -= 1;
+        self.dec_sp();
         self.write_memory(self.SP, self.C);
-        12
     }
 
     /// PUSH D
     /// push DE on stack
-    fn push_d(&mut self) -> u8 {
-        self.SP -= 1;
+    fn push_d(&mut self) {
+        self.dec_sp();
         self.write_memory(self.SP, self.D);
-        self.SP -= 1;
+        self.dec_sp();
         self.write_memory(self.SP, self.E);
-        12
     }
 
     /// PUSH H
     /// push HL on stack
-    fn push_h(&mut self) -> u8 {
-        self.SP -= 1;
+    fn push_h(&mut self) {
+        self.dec_sp();
         self.write_memory(self.SP, self.H);
-        self.SP -= 1;
+        self.dec_sp();
         self.write_memory(self.SP, self.L);
-        12
     }
 
     /// PUSH PSW
     /// push A and F on stack
-    fn push_psw(&mut self) -> u8 {
-        self.SP -= 1;
+    fn push_psw(&mut self) {
+        self.dec_sp();
         self.write_memory(self.SP, self.A);
-        self.SP -= 1;
+        self.dec_sp();
         self.write_memory(self.SP, self.F);
-        12
     }
 
     /// POP B
     /// pop BC from stack
-    fn pop_b(&mut self) -> u8 {
+    fn pop_b(&mut self) {
         self.C = self.read_memory(self.SP);
-        self.SP += 1;
+        self.inc_sp();
         self.B = self.read_memory(self.SP);
-        self.SP += 1;
-        10
+        self.inc_sp();
     }
 
     /// POP D
     /// pop DE from stack
-    fn pop_d(&mut self) -> u8 {
+    fn pop_d(&mut self) {
         self.E = self.read_memory(self.SP);
-        self.SP += 1;
+        self.inc_sp();
         self.D = self.read_memory(self.SP);
-        self.SP += 1;
-        10
+        self.inc_sp();
     }
 
     /// POP H
     /// pop HL from stack
-    fn pop_h(&mut self) -> u8 {
+    fn pop_h(&mut self) {
         self.L = self.read_memory(self.SP);
-        self.SP += 1;
+        self.inc_sp();
         self.H = self.read_memory(self.SP);
-        self.SP += 1;
-        10
+        self.inc_sp();
     }
 
     /// POP PSW
     /// pop A and F from stack
-    fn pop_psw(&mut self) -> u8 {
+    fn pop_psw(&mut self) {
         self.F = self.read_memory(self.SP);
-        self.SP += 1;
+        self.inc_sp();
         self.A = self.read_memory(self.SP);
-        self.SP += 1;
-        10
+        self.inc_sp();
     }
 
     /// XTHL
     /// exchange stack with HL
-    fn xthl(&mut self) -> u8 {
+    fn xthl(&mut self) {
         let top = self.read_memory(self.SP);
-        let bottom = self.read_memory(self.SP + 1);
+        let bottom = self.read_memory(self.SP.wrapping_add(1));
 
         self.write_memory(self.SP, self.L);
-        self.write_memory(self.SP + 1, self.H);
+        self.write_memory(self.SP.wrapping_add(1), self.H);
 
         self.L = top;
         self.H = bottom;
-        16
     }
 
     /// SPHL
     /// initialize SP with HL
-    fn sphl(&mut self) -> u8 {
+    fn sphl(&mut self) {
         self.SP = self.get_addr_hl();
-        6
     }
 
     /// INX SP
     /// increment SP by 1
-    fn inx_sp(&mut self) -> u8 {
-        if self.SP < 0xffff {
-            self.SP += 1;
-        } else {
-            self.F = self.F | 1 << 5;
-            self.SP = 0x0000;
-        }
-        6
+    fn inx_sp(&mut self) {
+        self.SP = self.SP.wrapping_add(1);
+        self.set_k(self.SP == 0x0000);
     }
 
     /// INX B
     /// increment register pair BC
-    fn inx_b(&mut self) -> u8 {
-        let mut t = self.get_addr_bc();
-        if t < 0xffff {
-            t += 1;
-        } else {
-            self.F = self.F | 1 << 5;
-            self.B = 0x00;
-            self.C = 0x00;
-            return 6;
-        }
+    fn inx_b(&mut self) {
+        let t = self.get_addr_bc().wrapping_add(1);
+        self.set_k(t == 0x0000);
         self.B = (t >> 8) as u8;
         self.C = (t & 0x00ff) as u8;
-        6
     }
 
     /// INX D
     /// increment register pair DE
-    fn inx_d(&mut self) -> u8 {
-        let mut t = self.get_addr_de();
-        if t < 0xffff {
-            t += 1;
-        } else {
-            self.F = self.F | 1 << 5;
-            self.D = 0x00;
-            self.E = 0x00;
-            return 6;
-        }
+    fn inx_d(&mut self) {
+        let t = self.get_addr_de().wrapping_add(1);
+        self.set_k(t == 0x0000);
         self.D = (t >> 8) as u8;
         self.E = (t & 0x00ff) as u8;
-        6
     }
 
     /// INX H
     /// increment register pair HL
-    fn inx_h(&mut self) -> u8 {
-        let mut t = self.get_addr_hl();
-        if t < 0xffff {
-            t += 1;
-        } else {
-            self.F = self.F | 1 << 5;
-            self.H = 0x00;
-            self.L = 0x00;
-            return 6;
-        }
+    fn inx_h(&mut self) {
+        let t = self.get_addr_hl().wrapping_add(1);
+        self.set_k(t == 0x0000);
         self.H = (t >> 8) as u8;
         self.L = (t & 0x00ff) as u8;
-        6
     }
 
     /// DCX SP
     /// decrement SP by 1
-    fn dcx_sp(&mut self) -> u8 {
-        if self.SP > 0x0000 {
-            self.SP -= 1;
-        } else {
-            self.F = self.F | 1 << 5;
-            self.SP = 0xffff;
-        }
-        6
+    fn dcx_sp(&mut self) {
+        self.SP = self.SP.wrapping_sub(1);
+        self.set_k(self.SP == 0xffff);
     }
 
     /// DCX B
     /// decrement BC by 1
-    fn dcx_b(&mut self) -> u8 {
-        let mut t = self.get_addr_bc();
-        if t > 0 {
-            t -= 1;
-        } else {
-            self.F = self.F | 1 << 5;
-            self.B = 0xff;
-            self.C = 0xff;
-            return 6;
-        }
+    fn dcx_b(&mut self) {
+        let t = self.get_addr_bc().wrapping_sub(1);
+        self.set_k(t == 0xffff);
         self.B = (t >> 8) as u8;
         self.C = (t & 0x00ff) as u8;
-        6
     }
 
     /// DCX D
     /// decrement DE by 1
-    fn dcx_d(&mut self) -> u8 {
-        let mut t = self.get_addr_de();
-        if t > 0 {
-            t -= 1;
-        } else {
-            self.F = self.F | 1 << 5;
-            self.D = 0xff;
-            self.E = 0xff;
-            return 6;
-        }
+    fn dcx_d(&mut self) {
+        let t = self.get_addr_de().wrapping_sub(1);
+        self.set_k(t == 0xffff);
         self.D = (t >> 8) as u8;
         self.E = (t & 0x00ff) as u8;
-        6
     }
 
     /// DCX H
     /// decrement HL by 1
-    fn dcx_h(&mut self) -> u8 {
-        let mut t = self.get_addr_hl();
-        if t > 0 {
-            t -= 1;
-        } else {
-            self.F = self.F | 1 << 5;
-            self.H = 0xff;
-            self.L = 0xff;
-            return 6;
-        }
+    fn dcx_h(&mut self) {
+        let t = self.get_addr_hl().wrapping_sub(1);
+        self.set_k(t == 0xffff);
         self.H = (t >> 8) as u8;
         self.L = (t & 0x00ff) as u8;
-        6
     }
 
     inr_r!(inr_a, A);
@@ -1414,25 +2732,10 @@ impl PP8085 {
 
     /// INR M
     /// inrement M by 1
-    fn inr_m(&mut self) -> u8 {
-        let mut num = self.read_memory(self.get_addr_hl());
-        if num < 0xff {
-            num += 1;
-            self.write_memory(self.get_addr_hl(), num);
-        } else {
-            num = 0x00;
-            self.write_memory(self.get_addr_hl(), num);
-        }
-        self.set_sign((num | 1 << 7) != 0);
-        self.set_overflow(num == 0x00);
-        self.set_zero(num == 0x00);
-        if num != 0x00 {
-            self.set_auxiliary_carry((((num - 1) & 0x0f) + 0x01) & 0x10 == 0x10);
-        } else {
-            self.set_auxiliary_carry(true);
-        }
-        self.set_parity(PP8085::find_parity(num));
-        10
+    fn inr_m(&mut self) {
+        let num = self.read_memory(self.get_addr_hl());
+        let num = self.inr_8bits(num);
+        self.write_memory(self.get_addr_hl(), num);
     }
 
     dcr_r!(dcr_a, A);
@@ -1445,25 +2748,10 @@ impl PP8085 {
 
     /// DCR M
     /// decrement M by 1
-    fn dcr_m(&mut self) -> u8 {
-        let mut num = self.read_memory(self.get_addr_hl());
-        if num > 0x00 {
-            num -= 1;
-            self.write_memory(self.get_addr_hl(), num);
-        } else {
-            num = 0xff;
-            self.write_memory(self.get_addr_hl(), num);
-        }
-        self.set_sign((num | 1 << 7) != 0);
-        self.set_overflow(num == 0xff);
-        self.set_zero(num == 0x00);
-        if num != 0xff {
-            self.set_auxiliary_carry((((num + 1) & 0x0f) - 0x01) & 0x10 == 0x10);
-        } else {
-            self.set_auxiliary_carry(true);
-        }
-        self.set_parity(PP8085::find_parity(num));
-        10
+    fn dcr_m(&mut self) {
+        let num = self.read_memory(self.get_addr_hl());
+        let num = self.dcr_8bits(num);
+        self.write_memory(self.get_addr_hl(), num);
     }
 
     add_r!(add_a, A);
@@ -1483,71 +2771,27 @@ impl PP8085 {
     adc_r!(adc_l, L);
 
     /// ADD M
-    fn add_m(&mut self) -> u8 {
+    fn add_m(&mut self) {
         let num = self.read_memory(self.get_addr_hl());
-        self.set_auxiliary_carry(((self.A & 0x0f) + (num & 0x0f)) & 0x10 == 0x10);
-        self.set_carry(0xff - self.A < num);
-        if 0xff - self.A > num {
-            self.A += num;
-        } else {
-            self.A = num - (0xff - self.A + 0x01);
-            self.set_overflow(true);
-        }
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+        self.A = self.add_8bits(num, false);
     }
 
     /// ADC M
-    fn adc_m(&mut self) -> u8 {
-        let num = self.read_memory(self.get_addr_hl()) + (self.F & 1);
-        self.set_auxiliary_carry(((self.A & 0x0f) + (num & 0x0f)) & 0x10 == 0x10);
-        self.set_carry(0xff - self.A < num);
-        if 0xff - self.A > num {
-            self.A += num;
-        } else {
-            self.A = num - (0xff - self.A + 0x01);
-            self.set_overflow(true);
-        }
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+    fn adc_m(&mut self) {
+        let num = self.read_memory(self.get_addr_hl());
+        self.A = self.add_8bits(num, self.get_carry());
     }
 
     /// ADI
-    fn adi(&mut self) -> u8 {
+    fn adi(&mut self) {
         let num = self.read_8bits();
-        self.set_auxiliary_carry(((self.A & 0x0f) + (num & 0x0f)) & 0x10 == 0x10);
-        self.set_carry(0xff - self.A < num);
-        if 0xff - self.A > num {
-            self.A += num;
-        } else {
-            self.A = num - (0xff - self.A + 0x01);
-            self.set_overflow(true);
-        }
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+        self.A = self.add_8bits(num, false);
     }
 
     /// ACI
-    fn aci(&mut self) -> u8 {
-        let num = self.read_8bits() + (self.F & 1);
-        self.set_auxiliary_carry(((self.A & 0x0f) + (num & 0x0f)) & 0x10 == 0x10);
-        self.set_carry(0xff - self.A < num);
-        if 0xff - self.A > num {
-            self.A += num;
-        } else {
-            self.A = num - (0xff - self.A + 0x01);
-            self.set_overflow(true);
-        }
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+    fn aci(&mut self) {
+        let num = self.read_8bits();
+        self.A = self.add_8bits(num, self.get_carry());
     }
 
     dad_p!(dad_b, B, C);
@@ -1572,71 +2816,27 @@ impl PP8085 {
     sbb_r!(sbb_l, L);
 
     /// SUB M
-    fn sub_m(&mut self) -> u8 {
+    fn sub_m(&mut self) {
         let num = self.read_memory(self.get_addr_hl());
-        self.set_auxiliary_carry((self.A & 0x0f) < (num & 0x0f));
-        if self.A >= num {
-            self.A -= num;
-        } else {
-            self.A = 0xff - (num - self.A - 0x01);
-            self.set_carry(true);
-            self.set_overflow(true);
-        }
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+        self.A = self.sub_8bits(num, false);
     }
 
     /// SBB M
-    fn sbb_m(&mut self) -> u8 {
-        let num = self.read_memory(self.get_addr_hl()) + (self.F & 1);
-        self.set_auxiliary_carry((self.A & 0x0f) < (num & 0x0f));
-        if self.A >= num {
-            self.A -= num;
-        } else {
-            self.A = 0xff - (num - self.A - 0x01);
-            self.set_carry(true);
-            self.set_overflow(true);
-        }
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+    fn sbb_m(&mut self) {
+        let num = self.read_memory(self.get_addr_hl());
+        self.A = self.sub_8bits(num, self.get_carry());
     }
 
     /// SUI XX
-    fn sui(&mut self) -> u8 {
+    fn sui(&mut self) {
         let num = self.read_8bits();
-        self.set_auxiliary_carry((self.A & 0x0f) < (num & 0x0f));
-        if self.A >= num {
-            self.A -= num;
-        } else {
-            self.A = 0xff - (num - self.A - 0x01);
-            self.set_carry(true);
-            self.set_overflow(true);
-        }
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+        self.A = self.sub_8bits(num, false);
     }
 
     /// SBI XX
-    fn sbi(&mut self) -> u8 {
-        let num = self.read_8bits() + (self.F & 1);
-        self.set_auxiliary_carry((self.A & 0x0f) < (num & 0x0f));
-        if self.A >= num {
-            self.A -= num;
-        } else {
-            self.A = 0xff - (num - self.A - 0x01);
-            self.set_carry(true);
-            self.set_overflow(true);
-        }
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+    fn sbi(&mut self) {
+        let num = self.read_8bits();
+        self.A = self.sub_8bits(num, self.get_carry());
     }
 
     ana_r!(ana_a, A);
@@ -1647,20 +2847,14 @@ impl PP8085 {
     ana_r!(ana_h, H);
     ana_r!(ana_l, L);
 
-    fn ana_m(&mut self) -> u8 {
+    fn ana_m(&mut self) {
         self.A &= self.read_memory(self.get_addr_hl());
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+        self.set_logic_flags(true);
     }
 
-    fn ani(&mut self) -> u8 {
+    fn ani(&mut self) {
         self.A &= self.read_8bits();
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+        self.set_logic_flags(true);
     }
 
     xra_r!(xra_a, A);
@@ -1671,20 +2865,14 @@ impl PP8085 {
     xra_r!(xra_h, H);
     xra_r!(xra_l, L);
 
-    fn xra_m(&mut self) -> u8 {
+    fn xra_m(&mut self) {
         self.A ^= self.read_memory(self.get_addr_hl());
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+        self.set_logic_flags(false);
     }
 
-    fn xri(&mut self) -> u8 {
+    fn xri(&mut self) {
         self.A ^= self.read_8bits();
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+        self.set_logic_flags(false);
     }
 
     ora_r!(ora_a, A);
@@ -1695,20 +2883,14 @@ impl PP8085 {
     ora_r!(ora_h, H);
     ora_r!(ora_l, L);
 
-    fn ora_m(&mut self) -> u8 {
+    fn ora_m(&mut self) {
         self.A |= self.read_memory(self.get_addr_hl());
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+        self.set_logic_flags(false);
     }
 
-    fn ori(&mut self) -> u8 {
+    fn ori(&mut self) {
         self.A |= self.read_8bits();
-        self.set_sign((self.A | 1 << 7) != 0);
-        self.set_zero(self.A == 0x00);
-        self.set_parity(PP8085::find_parity(self.A));
-        7
+        self.set_logic_flags(false);
     }
 
     cmp_r!(cmp_a, A);
@@ -1720,156 +2902,125 @@ impl PP8085 {
     cmp_r!(cmp_l, L);
 
     /// CMP M
-    fn cmp_m(&mut self) -> u8 {
-        match self.read_memory(self.get_addr_hl()).cmp(&self.A) {
-            std::cmp::Ordering::Equal => {
-                self.set_carry(false);
-                self.set_zero(true);
-            }
-            std::cmp::Ordering::Greater => {
-                self.set_carry(false);
-                self.set_zero(false);
-            }
-            std::cmp::Ordering::Less => {
-                self.set_carry(true);
-                self.set_zero(false);
-            }
-        }
-        7
+    fn cmp_m(&mut self) {
+        let num = self.read_memory(self.get_addr_hl());
+        self.sub_8bits(num, false);
     }
 
     /// CPI XX
-    fn cpi(&mut self) -> u8 {
-        match self.read_8bits().cmp(&self.A) {
-            std::cmp::Ordering::Equal => {
-                self.set_carry(false);
-                self.set_zero(true);
-            }
-            std::cmp::Ordering::Greater => {
-                self.set_carry(false);
-                self.set_zero(false);
-            }
-            std::cmp::Ordering::Less => {
-                self.set_carry(true);
-                self.set_zero(false);
-            }
-        }
-        4
+    fn cpi(&mut self) {
+        let num = self.read_8bits();
+        self.sub_8bits(num, false);
     }
 
     /// JMP XXXX
-    fn jmp(&mut self) -> u8 {
+    fn jmp(&mut self) {
         let (opl, oph) = self.read_16bits();
         self.PC = ((oph as u16) << 8) | (opl as u16);
-        10
     }
 
     /// JC XXXX
-    fn jc(&mut self) -> u8 {
+    fn jc(&mut self) -> bool {
         let (opl, oph) = self.read_16bits();
         if self.get_carry() {
             self.PC = ((oph as u16) << 8) | (opl as u16);
-            return 10;
+            return true;
         }
-        7
+        false
     }
 
     /// JNC XXXX
-    fn jnc(&mut self) -> u8 {
+    fn jnc(&mut self) -> bool {
         let (opl, oph) = self.read_16bits();
         if !self.get_carry() {
             self.PC = ((oph as u16) << 8) | (opl as u16);
-            return 10;
+            return true;
         }
-        7
+        false
     }
 
     /// JZ XXXX
-    fn jz(&mut self) -> u8 {
+    fn jz(&mut self) -> bool {
         let (opl, oph) = self.read_16bits();
         if self.get_zero() {
             self.PC = ((oph as u16) << 8) | (opl as u16);
-            return 10;
+            return true;
         }
-        7
+        false
     }
 
     /// JNZ XXXX
-    fn jnz(&mut self) -> u8 {
+    fn jnz(&mut self) -> bool {
         let (opl, oph) = self.read_16bits();
         if !self.get_zero() {
             self.PC = ((oph as u16) << 8) | (opl as u16);
-            return 10;
+            return true;
         }
-        7
+        false
     }
 
     /// JP XXXX
-    fn jp(&mut self) -> u8 {
+    fn jp(&mut self) -> bool {
         let (opl, oph) = self.read_16bits();
         if !self.get_sign() {
             self.PC = ((oph as u16) << 8) | (opl as u16);
-            return 10;
+            return true;
         }
-        7
+        false
     }
 
     /// JM XXXX
-    fn jm(&mut self) -> u8 {
+    fn jm(&mut self) -> bool {
         let (opl, oph) = self.read_16bits();
         if self.get_sign() {
             self.PC = ((oph as u16) << 8) | (opl as u16);
-            return 10;
+            return true;
         }
-        7
+        false
     }
 
     /// JPE XXXX
-    fn jpe(&mut self) -> u8 {
+    fn jpe(&mut self) -> bool {
         let (opl, oph) = self.read_16bits();
         if self.get_parity() {
             self.PC = ((oph as u16) << 8) | (opl as u16);
-            return 10;
+            return true;
         }
-        7
+        false
     }
 
     /// JPO XXXX
-    fn jpo(&mut self) -> u8 {
+    fn jpo(&mut self) -> bool {
         let (opl, oph) = self.read_16bits();
         if !self.get_parity() {
             self.PC = ((oph as u16) << 8) | (opl as u16);
-            return 10;
+            return true;
         }
-        7
+        false
     }
 
     /// PCHL
-    fn pchl(&mut self) -> u8 {
+    fn pchl(&mut self) {
         self.PC = self.get_addr_hl();
-        6
     }
 
     /// CMA
-    fn cma(&mut self) -> u8 {
+    fn cma(&mut self) {
         self.A = !self.A;
-        4
     }
 
     /// STC
-    fn stc(&mut self) -> u8 {
+    fn stc(&mut self) {
         self.set_carry(true);
-        4
     }
 
     /// CMC
-    fn cmc(&mut self) -> u8 {
+    fn cmc(&mut self) {
         self.set_carry(!self.get_carry());
-        4
     }
 
     /// DAA
-    fn daa(&mut self) -> u8 {
+    fn daa(&mut self) {
         let mut num: u8 = self.A;
         if num & 0x0f > 0x09 || self.get_auxiliary_carry() {
             num += 0x06;
@@ -1878,37 +3029,32 @@ impl PP8085 {
             num += 0x60;
         }
         self.A = num;
-        4
     }
 
     /// RLC
-    fn rlc(&mut self) -> u8 {
+    fn rlc(&mut self) {
         self.set_carry(self.A & (1 << 7) != 0);
         self.A = (self.A << 1) | ((self.A & (1 << 7)) >> 7);
-        4
     }
 
     /// RRC
-    fn rrc(&mut self) -> u8 {
+    fn rrc(&mut self) {
         self.set_carry(self.A & 1 != 0);
         self.A = (self.A >> 1) | ((self.A & 1) << 7);
-        4
     }
 
     /// RAL
-    fn ral(&mut self) -> u8 {
+    fn ral(&mut self) {
         let n = (self.A & (1 << 7)) >> 7;
         self.A = (self.A << 1) | self.get_carry() as u8;
         self.set_carry(n == 1);
-        4
     }
 
     /// RAR
-    fn rar(&mut self) -> u8 {
+    fn rar(&mut self) {
         let n = self.A & 1;
         self.A = (self.A >> 1) | (self.get_carry() as u8) << 7;
         self.set_carry(n == 1);
-        4
     }
 
     rst_seq!(rst_0, 0x0000);
@@ -1920,6 +3066,114 @@ impl PP8085 {
     rst_seq!(rst_6, 0x0030);
     rst_seq!(rst_7, 0x0038);
 
+    // undocumented instructions
+
+    /// DSUB
+    /// HL = HL - BC
+    fn dsub(&mut self) {
+        let hl = self.get_addr_hl();
+        let bc = self.get_addr_bc();
+        let res = hl.wrapping_sub(bc);
+        self.set_carry(hl < bc);
+        self.set_auxiliary_carry((hl & 0x0fff) >= (bc & 0x0fff));
+        self.set_overflow((hl ^ bc) & (hl ^ res) & 1 << 15 != 0);
+        self.set_sign(res & 1 << 15 != 0);
+        self.set_zero(res == 0x0000);
+        self.set_parity(PP8085::find_parity((res >> 8) as u8));
+        self.set_k_from_vs();
+        self.H = (res >> 8) as u8;
+        self.L = (res & 0x00ff) as u8;
+    }
+
+    /// ARHL
+    /// arithmetic shift right HL, bit 0 goes to carry
+    fn arhl(&mut self) {
+        let hl = self.get_addr_hl();
+        self.set_carry(hl & 1 != 0);
+        let res = (hl >> 1) | (hl & 1 << 15);
+        self.H = (res >> 8) as u8;
+        self.L = (res & 0x00ff) as u8;
+    }
+
+    /// RDEL
+    /// rotate DE left through carry
+    fn rdel(&mut self) {
+        let de = self.get_addr_de();
+        let res = (de << 1) | self.get_carry() as u16;
+        self.set_carry(de & 1 << 15 != 0);
+        self.set_overflow((de ^ (de << 1)) & 1 << 15 != 0);
+        self.D = (res >> 8) as u8;
+        self.E = (res & 0x00ff) as u8;
+    }
+
+    /// LDHI XX
+    /// DE = HL + XX
+    fn ldhi(&mut self) {
+        let num = self.read_8bits();
+        let res = self.get_addr_hl().wrapping_add(num as u16);
+        self.D = (res >> 8) as u8;
+        self.E = (res & 0x00ff) as u8;
+    }
+
+    /// LDSI XX
+    /// DE = SP + XX
+    fn ldsi(&mut self) {
+        let num = self.read_8bits();
+        let res = self.SP.wrapping_add(num as u16);
+        self.D = (res >> 8) as u8;
+        self.E = (res & 0x00ff) as u8;
+    }
+
+    /// RSTV
+    /// restart at 0x0040 on overflow
+    fn rstv(&mut self) -> bool {
+        if self.get_overflow() {
+            self.dec_sp();
+            self.write_memory(self.SP, (self.PC >> 8) as u8);
+            self.dec_sp();
+            self.write_memory(self.SP, (self.PC & 0x00ff) as u8);
+            self.PC = 0x0040;
+            return true;
+        }
+        false
+    }
+
+    /// SHLX
+    /// store HL at the address in DE
+    fn shlx(&mut self) {
+        let addr = self.get_addr_de();
+        self.write_memory(addr, self.L);
+        self.write_memory(addr.wrapping_add(1), self.H);
+    }
+
+    /// LHLX
+    /// load HL from the address in DE
+    fn lhlx(&mut self) {
+        let addr = self.get_addr_de();
+        self.L = self.read_memory(addr);
+        self.H = self.read_memory(addr.wrapping_add(1));
+    }
+
+    /// JNK XXXX
+    fn jnk(&mut self) -> bool {
+        let (opl, oph) = self.read_16bits();
+        if !self.get_k() {
+            self.PC = ((oph as u16) << 8) | (opl as u16);
+            return true;
+        }
+        false
+    }
+
+    /// JK XXXX
+    fn jk(&mut self) -> bool {
+        let (opl, oph) = self.read_16bits();
+        if self.get_k() {
+            self.PC = ((oph as u16) << 8) | (opl as u16);
+            return true;
+        }
+        false
+    }
+
     call_seq!(call);
     call_seq!(cc, cnc, get_carry);
     call_seq!(cz, cnz, get_zero);
@@ -1933,28 +3187,40 @@ impl PP8085 {
     ret_seq!(rpe, rpo, get_parity);
 
     // IN
-    fn i_n(&mut self) -> u8 {
+    fn i_n(&mut self) {
         let addr = self.read_8bits();
         self.A = self.read_io(addr);
-        10
     }
 
     // OUT
-    fn out(&mut self) -> u8 {
+    fn out(&mut self) {
         let addr = self.read_8bits();
         self.write_io(addr, self.A);
-        10
     }
 
     /// RIM
-    fn rim(&mut self) -> u8 {
-        4
+    /// read interrupt masks, pending interrupts and IE into A
+    fn rim(&mut self) {
+        self.A = self.interrupts.rim(self.IE) | (self.sid as u8) << 7;
     }
 
     /// SIM
-    fn sim(&mut self) -> u8 {
-        4
+    /// set interrupt masks, reset the RST7.5 latch and write SOD from A
+    fn sim(&mut self) {
+        self.interrupts.sim(self.A);
+        if self.A & (1 << 6) != 0 {
+            self.sod = self.A & (1 << 7) != 0;
+            self.sod_log.push((self.total_cycles, self.sod));
+        }
+    }
+}
+
+/// bytes in `start..=end`, an error if `start` is after `end`.
+fn range_size(start: u16, end: u16) -> Result<u32, String> {
+    if start > end {
+        return Err(format!("Empty range {:04X}-{:04X}", start, end));
     }
+    Ok((end - start) as u32 + 1)
 }
 
 // -----------------------TESTS----------------------------------
@@ -1991,7 +3257,7 @@ mod tests {
         cpu.add_l();
         assert_eq!(cpu.A, res);
         assert!(cpu.get_carry());
-        assert!(cpu.get_overflow());
+        assert!(!cpu.get_overflow());
         assert!(cpu.get_zero());
         assert!(cpu.get_parity());
 
@@ -2003,7 +3269,7 @@ mod tests {
         cpu.add_h();
         assert_eq!(cpu.A, res);
         assert!(cpu.get_carry());
-        assert!(cpu.get_overflow());
+        assert!(!cpu.get_overflow());
         assert!(!cpu.get_zero());
         assert!(!cpu.get_parity());
 
@@ -2018,6 +3284,17 @@ mod tests {
         assert!(cpu.get_overflow());
         assert!(!cpu.get_zero());
         assert!(!cpu.get_parity());
+
+        // 0x7f + 0x01 overflows into the sign bit
+        cpu.A = 0x7f;
+        cpu.E = 0x01;
+        cpu.add_e();
+        assert_eq!(cpu.A, 0x80);
+        assert!(!cpu.get_carry());
+        assert!(cpu.get_overflow());
+        assert!(cpu.get_sign());
+        assert!(!cpu.get_k());
+        assert!(cpu.get_auxiliary_carry());
     }
 
     #[test]
@@ -2059,7 +3336,7 @@ mod tests {
         cpu.adc_d();
         assert_eq!(cpu.A, res);
         assert!(cpu.get_carry());
-        assert!(cpu.get_overflow());
+        assert!(!cpu.get_overflow());
         assert!(!cpu.get_zero());
         assert!(cpu.get_parity());
     }
@@ -2087,9 +3364,45 @@ mod tests {
         cpu.sub_b();
         assert_eq!(cpu.A, res);
         assert!(cpu.get_carry());
-        assert!(cpu.get_overflow());
+        assert!(!cpu.get_overflow());
         assert!(!cpu.get_zero());
         assert!(!cpu.get_parity());
+
+        // 0x80 - 0x01 overflows out of the sign bit
+        cpu.A = 0x80;
+        cpu.B = 0x01;
+        cpu.sub_b();
+        assert_eq!(cpu.A, 0x7f);
+        assert!(!cpu.get_carry());
+        assert!(cpu.get_overflow());
+        assert!(!cpu.get_sign());
+        assert!(cpu.get_k());
+
+        // AC is the carry out of bit 3 of A + !B + 1, set when the low
+        // nibble does not borrow
+        cpu.A = 0x3e;
+        cpu.B = 0x3e;
+        cpu.sub_b();
+        assert!(cpu.get_auxiliary_carry());
+        cpu.A = 0x10;
+        cpu.B = 0x01;
+        cpu.sub_b();
+        assert_eq!(cpu.A, 0x0f);
+        assert!(!cpu.get_auxiliary_carry());
+
+        // SBB adds !CY in place of the 1
+        cpu.A = 0x15;
+        cpu.B = 0x05;
+        cpu.set_carry(true);
+        cpu.sbb_b();
+        assert_eq!(cpu.A, 0x0f);
+        assert!(!cpu.get_auxiliary_carry());
+        cpu.A = 0x16;
+        cpu.B = 0x05;
+        cpu.set_carry(true);
+        cpu.sbb_b();
+        assert_eq!(cpu.A, 0x10);
+        assert!(cpu.get_auxiliary_carry());
     }
 
     #[test]
@@ -2137,13 +3450,13 @@ mod tests {
         cpu.A = 0x45;
         cpu.B = 0x55;
         cpu.cmp_b();
-        assert!(!cpu.get_carry());
+        assert!(cpu.get_carry());
         assert!(!cpu.get_zero());
 
         cpu.A = 0x45;
         cpu.B = 0x05;
         cpu.cmp_b();
-        assert!(cpu.get_carry());
+        assert!(!cpu.get_carry());
         assert!(!cpu.get_zero());
 
         cpu.A = 0x45;
@@ -2151,6 +3464,12 @@ mod tests {
         cpu.cmp_b();
         assert!(!cpu.get_carry());
         assert!(cpu.get_zero());
+        assert!(cpu.get_auxiliary_carry());
+
+        cpu.A = 0x40;
+        cpu.B = 0x05;
+        cpu.cmp_b();
+        assert!(!cpu.get_auxiliary_carry());
     }
 
     #[test]
@@ -2233,7 +3552,8 @@ mod tests {
         let mut cpu = PP8085::new();
         cpu.SP = 0x19ff;
         cpu.PC = 0x0102;
-        let c = cpu.rst_1();
+        cpu.IR = 0xcf; // RST 1
+        let c = cpu.decode_and_run();
         assert_eq!(c, 12);
         assert_eq!(cpu.PC, 0x0008);
         assert_eq!(cpu.read_memory(cpu.SP), 0x02);
@@ -2248,7 +3568,8 @@ mod tests {
         cpu.PC = 0x0000;
         cpu.write_memory(0x0000, 0x22);
         cpu.write_memory(0x0001, 0xaa);
-        let c = cpu.cc();
+        cpu.IR = 0xdc; // CC
+        let c = cpu.decode_and_run();
         assert_eq!(c, 18);
         assert_eq!(cpu.PC, 0xaa22);
         assert_eq!(cpu.read_memory(cpu.SP), 0x02);
@@ -2257,7 +3578,8 @@ mod tests {
         cpu.set_carry(false);
         cpu.SP = 0x19ff;
         cpu.PC = 0x0000;
-        let c = cpu.cc();
+        cpu.IR = 0xdc; // CC
+        let c = cpu.decode_and_run();
         assert_eq!(c, 9);
         assert_eq!(cpu.PC, 0x0002);
     }
@@ -2270,7 +3592,8 @@ mod tests {
         cpu.PC = 0x0000;
         cpu.write_memory(0x0000, 0x22);
         cpu.write_memory(0x0001, 0xaa);
-        let c = cpu.cnc();
+        cpu.IR = 0xd4; // CNC
+        let c = cpu.decode_and_run();
         assert_eq!(c, 18);
         assert_eq!(cpu.PC, 0xaa22);
         assert_eq!(cpu.read_memory(cpu.SP), 0x02);
@@ -2279,7 +3602,8 @@ mod tests {
         cpu.set_carry(true);
         cpu.SP = 0x19ff;
         cpu.PC = 0x0000;
-        let c = cpu.cnc();
+        cpu.IR = 0xd4; // CNC
+        let c = cpu.decode_and_run();
         assert_eq!(c, 9);
         assert_eq!(cpu.PC, 0x0002);
     }
@@ -2292,7 +3616,8 @@ mod tests {
         cpu.PC = 0x0000;
         cpu.write_memory(cpu.SP, 0x02);
         cpu.write_memory(cpu.SP + 1, 0xaa);
-        let c = cpu.rc();
+        cpu.IR = 0xd8; // RC
+        let c = cpu.decode_and_run();
         assert_eq!(c, 12);
         assert_eq!(cpu.PC, 0xaa02);
         assert_eq!(cpu.SP, 0x19ff);
@@ -2300,7 +3625,8 @@ mod tests {
         cpu.set_carry(false);
         cpu.SP = 0x19fd;
         cpu.PC = 0x0000;
-        let c = cpu.rc();
+        cpu.IR = 0xd8; // RC
+        let c = cpu.decode_and_run();
         assert_eq!(c, 6);
         assert_eq!(cpu.PC, 0x0000);
         assert_eq!(cpu.SP, 0x19fd);
@@ -2314,7 +3640,8 @@ mod tests {
         cpu.PC = 0x0000;
         cpu.write_memory(cpu.SP, 0x02);
         cpu.write_memory(cpu.SP + 1, 0x12);
-        let c = cpu.rnc();
+        cpu.IR = 0xd0; // RNC
+        let c = cpu.decode_and_run();
         assert_eq!(c, 12);
         assert_eq!(cpu.PC, 0x1202);
         assert_eq!(cpu.SP, 0x2000);
@@ -2322,7 +3649,8 @@ mod tests {
         cpu.set_carry(true);
         cpu.SP = 0x19fd;
         cpu.PC = 0x0000;
-        let c = cpu.rnc();
+        cpu.IR = 0xd0; // RNC
+        let c = cpu.decode_and_run();
         assert_eq!(c, 6);
         assert_eq!(cpu.PC, 0x0000);
         assert_eq!(cpu.SP, 0x19fd);
@@ -2352,19 +3680,47 @@ mod tests {
     fn test_io_connections() {
         let mut cpu = PP8085::new();
         cpu.add_io_port(0x05);
+        assert!(cpu.set_io_input(0x05, 0x3c));
+        assert_eq!(cpu.read_io(0x05), 0x3c);
         cpu.write_io(0x05, 0xaf);
-        assert_eq!(cpu.read_io(0x05), 0xaf);
+        assert_eq!(cpu.get_io_output(0x05), Some(0xaf));
+        assert_eq!(cpu.read_io(0x05), 0x3c); // the latches are separate
 
         cpu.remove_io_port(0x05);
         cpu.write_io(0x05, 0xaf);
         assert_eq!(cpu.read_io(0x05), 0);
+        assert_eq!(cpu.get_io_output(0x05), None);
+        assert!(!cpu.set_io_input(0x05, 0x3c));
+    }
+
+    #[test]
+    fn test_io_port_latches() {
+        // OUT 05h then IN 05h reads the switches, not what was written
+        let mut cpu = PP8085::new();
+        cpu.add_io_port(0x05);
+        cpu.set_io_input(0x05, 0x3c);
+        let (bin, _) = crate::asm8085::assemble("mvi a,0afh\nout 05h\nin 05h\nhlt").unwrap();
+        for (i, b) in bin.iter().enumerate() {
+            cpu.write_memory(i as u16, *b);
+        }
+        assert_eq!(cpu.run_until_break(10), StopReason::Halted);
+        assert_eq!(cpu.A, 0x3c);
+        assert_eq!(cpu.get_io_output(0x05), Some(0xaf));
+
+        // get_io_ports lists both latches
+        let ports = cpu.system_bus().unwrap().io_ports();
+        assert_eq!(ports.len(), 1);
+        assert_eq!((ports[0].input(), ports[0].output()), (0x3c, 0xaf));
+        let json = serde_json::to_value(&ports).unwrap();
+        assert_eq!(json[0]["input"], 0x3c);
+        assert_eq!(json[0]["output"], 0xaf);
     }
 
     #[test]
     fn test_in() {
         let mut cpu = PP8085::new();
         cpu.add_io_port(0x05);
-        cpu.write_io(0x05, 0xaf);
+        cpu.set_io_input(0x05, 0xaf);
         cpu.PC = 0x000a;
         cpu.write_memory(0x000a, 0x05);
         cpu.i_n();
@@ -2379,7 +3735,360 @@ mod tests {
         cpu.write_memory(0x000a, 0x05);
         cpu.A = 0xaf;
         cpu.out();
-        assert_eq!(cpu.read_io(0x05), 0xaf);
+        assert_eq!(cpu.get_io_output(0x05), Some(0xaf));
+        assert_eq!(cpu.read_io(0x05), 0x00);
+    }
+
+    #[test]
+    fn test_i8155() {
+        // the device itself is tested in i8155.rs, these are the cpu helpers
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new(0x1000).unwrap());
+        cpu.add_8155(0x2000, 0x20).unwrap();
+        cpu.write_memory(0x20ff, 0x55);
+        assert_eq!(cpu.read_memory(0x20ff), 0x55);
+
+        // port A output, port B input
+        cpu.write_io(0x20, 0x01);
+        cpu.write_io(0x21, 0xa5);
+        assert_eq!(cpu.get_8155_output(0x20, 0), Some(0xa5));
+        assert_eq!(cpu.get_8155_output(0x20, 1), None);
+        assert!(cpu.set_8155_input(0x20, 1, 0x3c));
+        assert!(!cpu.set_8155_input(0x21, 1, 0x3c));
+        assert_eq!(cpu.read_io(0x22), 0x3c);
+
+        // ALT 4, STB A latches port A
+        cpu.write_io(0x20, 0x08);
+        cpu.set_8155_input(0x20, 0, 0x77);
+        assert!(cpu.strobe_8155(0x20, 0));
+        assert!(!cpu.strobe_8155(0x21, 0));
+        assert_eq!(cpu.read_io(0x21), 0x77);
+        assert!(cpu.set_8155_timer_clock(0x20, 2));
+        assert!(!cpu.set_8155_timer_clock(0x21, 2));
+
+        // the TIMER OUT pulse, inverted, raises RST7.5
+        let code = "lxi sp,1000h\nmvi a,08h\nsim\nei\nmvi a,64h\nout 24h\n\
+                    mvi a,80h\nout 25h\nmvi a,0c0h\nout 20h\nhlt";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        cpu.reset();
+        cpu.load_segments(
+            &[Segment::new(0, bin), Segment::new(0x3c, vec![0x76])],
+            None,
+        )
+        .unwrap();
+        assert!(cpu
+            .connect_interrupt(0x20, "timer in", Interrupt::Rst75, true)
+            .is_err());
+        cpu.connect_interrupt(0x20, "timer out", Interrupt::Rst75, true)
+            .unwrap();
+        let mut taken = None;
+        for _ in 0..200 {
+            let info = cpu.step().unwrap();
+            if info.interrupt.is_some() {
+                taken = info.interrupt;
+                break;
+            }
+        }
+        assert_eq!(taken, Some(Interrupt::Rst75));
+        assert_eq!(cpu.PC, 0x003c);
+        // 100 clocks of 2 T-states from the end of OUT 20h
+        assert!(cpu.total_cycles >= 200);
+    }
+
+    #[test]
+    fn test_i8254() {
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new(0x1000).unwrap());
+        cpu.add_8254(0x40).unwrap();
+        assert!(cpu.add_8254(0x43).is_err());
+        assert!(cpu.add_8254(0xfd).is_err());
+        let out = |cpu: &PP8085, i| cpu.get_8254_out(0x40, i).unwrap();
+        let tick = |cpu: &mut PP8085, n| cpu.system_bus_mut().unwrap().tick(n);
+        assert_eq!(cpu.get_8254_out(0x40, 3), None);
+
+        // mode 0, OUT rises N clocks after the count is loaded
+        cpu.write_io(0x43, 0x30);
+        assert!(!out(&cpu, 0));
+        cpu.write_io(0x40, 5);
+        cpu.write_io(0x40, 0);
+        tick(&mut cpu, 3);
+        cpu.write_io(0x43, 0x00); // latch counter 0
+        tick(&mut cpu, 2);
+        assert!(!out(&cpu, 0));
+        assert_eq!(cpu.read_io(0x40), 3);
+        assert_eq!(cpu.read_io(0x40), 0);
+        assert_eq!(cpu.read_io(0x40), 1);
+        assert_eq!(cpu.read_io(0x40), 0);
+        tick(&mut cpu, 1);
+        assert!(out(&cpu, 0));
+        // BCD, the full count is 10000
+        cpu.write_io(0x43, 0x31);
+        cpu.write_io(0x40, 0x12);
+        cpu.write_io(0x40, 0x00);
+        tick(&mut cpu, 4);
+        assert_eq!(cpu.read_io(0x40), 0x09);
+        assert_eq!(cpu.read_io(0x40), 0x00);
+        cpu.write_io(0x40, 0x00);
+        cpu.write_io(0x40, 0x00);
+        tick(&mut cpu, 2);
+        assert_eq!(cpu.read_io(0x40), 0x99);
+        assert_eq!(cpu.read_io(0x40), 0x99);
+
+        // mode 2 on counter 1, OUT low one clock in 3, held high by GATE
+        cpu.write_io(0x43, 0x74);
+        cpu.write_io(0x41, 3);
+        cpu.write_io(0x41, 0);
+        tick(&mut cpu, 2);
+        assert!(out(&cpu, 1));
+        tick(&mut cpu, 1);
+        assert!(!out(&cpu, 1));
+        tick(&mut cpu, 1);
+        assert!(out(&cpu, 1));
+        tick(&mut cpu, 2);
+        assert!(!out(&cpu, 1));
+        assert!(cpu.set_8254_gate(0x40, 1, false));
+        assert!(out(&cpu, 1));
+        tick(&mut cpu, 10);
+        assert!(out(&cpu, 1));
+
+        // mode 1, a one-shot of 3 clocks started by GATE
+        cpu.write_io(0x43, 0x72);
+        cpu.write_io(0x41, 3);
+        cpu.write_io(0x41, 0);
+        tick(&mut cpu, 5);
+        assert!(out(&cpu, 1));
+        cpu.set_8254_gate(0x40, 1, true);
+        tick(&mut cpu, 3);
+        assert!(!out(&cpu, 1));
+        tick(&mut cpu, 1);
+        assert!(out(&cpu, 1));
+
+        // mode 3 in BCD, 5 clocks: 3 high and 2 low, CLK every 2 T-states
+        assert!(cpu.set_8254_clock(0x40, 2, 2));
+        cpu.write_io(0x43, 0xb7);
+        cpu.write_io(0x42, 0x05);
+        cpu.write_io(0x42, 0x00);
+        tick(&mut cpu, 2 + 4);
+        assert!(out(&cpu, 2));
+        tick(&mut cpu, 2);
+        assert!(!out(&cpu, 2));
+        tick(&mut cpu, 2);
+        assert!(!out(&cpu, 2));
+        tick(&mut cpu, 2);
+        assert!(out(&cpu, 2));
+
+        // mode 4, a strobe N+1 clocks after the count is written
+        cpu.write_io(0x43, 0x18);
+        cpu.write_io(0x40, 2);
+        tick(&mut cpu, 2);
+        assert!(out(&cpu, 0));
+        tick(&mut cpu, 1);
+        assert!(!out(&cpu, 0));
+        tick(&mut cpu, 1);
+        assert!(out(&cpu, 0));
+        // mode 5, the same started by GATE
+        cpu.write_io(0x43, 0x1a);
+        cpu.write_io(0x40, 2);
+        tick(&mut cpu, 5);
+        assert!(out(&cpu, 0));
+        cpu.set_8254_gate(0x40, 0, false);
+        cpu.set_8254_gate(0x40, 0, true);
+        tick(&mut cpu, 3);
+        assert!(!out(&cpu, 0));
+        tick(&mut cpu, 1);
+        assert!(out(&cpu, 0));
+
+        // read-back of the status and count of counter 0
+        cpu.write_io(0x43, 0xc2);
+        assert_eq!(cpu.read_io(0x40), 0x9a); // OUT, LSB only, mode 5
+        assert_eq!(cpu.read_io(0x40), 0xff);
+
+        // OUT 2 of a mode 2 counter raises RST7.5 through an inverter
+        let code = "lxi sp,1000h\nmvi a,08h\nsim\nei\nmvi a,0b4h\nout 43h\n\
+                    mvi a,64h\nout 42h\nmvi a,00h\nout 42h\nhlt";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        // the 8254 has no RESET, stop the square wave first
+        cpu.write_io(0x43, 0xb4);
+        cpu.reset();
+        cpu.load_segments(
+            &[Segment::new(0, bin), Segment::new(0x3c, vec![0x76])],
+            None,
+        )
+        .unwrap();
+        assert!(cpu
+            .connect_interrupt(0x40, "out3", Interrupt::Rst75, true)
+            .is_err());
+        cpu.connect_interrupt(0x40, "out2", Interrupt::Rst75, true)
+            .unwrap();
+        let mut taken = None;
+        for _ in 0..200 {
+            let info = cpu.step().unwrap();
+            if info.interrupt.is_some() {
+                taken = info.interrupt;
+                break;
+            }
+        }
+        assert_eq!(taken, Some(Interrupt::Rst75));
+        assert_eq!(cpu.PC, 0x003c);
+        assert!(cpu.total_cycles >= 200);
+    }
+
+    #[test]
+    fn test_i8255() {
+        let code = "lxi sp,1000h\nmvi a,08h\nsim\nmvi a,0b0h\nout 83h\n\
+                    mvi a,09h\nout 83h\nei\nhlt";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new(0x1000).unwrap());
+        cpu.add_8255(0x80).unwrap();
+        assert!(cpu.add_8255(0x82).is_err());
+        assert!(cpu.add_8255(0xfd).is_err());
+
+        // mode 0, ports A and B output, port C input
+        cpu.write_io(0x83, 0x89);
+        cpu.write_io(0x80, 0x5a);
+        assert_eq!(cpu.get_8255_output(0x80, 0), Some(0x5a));
+        assert_eq!(cpu.get_8255_output(0x80, 2), None);
+        assert!(cpu.set_8255_input(0x80, 2, 0xa5));
+        assert!(!cpu.set_8255_input(0x81, 2, 0xa5));
+        assert_eq!(cpu.read_io(0x82), 0xa5);
+        // port C upper output, bit set/reset
+        cpu.write_io(0x83, 0x81);
+        assert_eq!(cpu.get_8255_output(0x80, 0), Some(0x00));
+        cpu.write_io(0x83, 0x0f);
+        cpu.write_io(0x83, 0x0b);
+        assert_eq!(cpu.get_8255_output(0x80, 2), Some(0xa0));
+        cpu.write_io(0x83, 0x0e);
+        assert_eq!(cpu.read_io(0x82), 0x25);
+
+        // mode 1 output on port B, INTE B set through PC2
+        cpu.write_io(0x83, 0x84);
+        cpu.write_io(0x83, 0x05);
+        let intr_b = cpu.system_bus().unwrap().io_line(0x80, "intr b").unwrap();
+        cpu.write_io(0x81, 0x33);
+        assert_eq!(cpu.get_8255_output(0x80, 1), Some(0x33));
+        assert_eq!(cpu.read_io(0x82) & 0x07, 0x04); // OBF B low
+        assert!(cpu.acknowledge_8255(0x80, 1));
+        assert!(intr_b.level());
+        assert_eq!(cpu.read_io(0x82) & 0x07, 0x07);
+        cpu.write_io(0x81, 0x34);
+        assert!(!intr_b.level());
+
+        // mode 2 on port A
+        cpu.write_io(0x83, 0xc0);
+        cpu.write_io(0x80, 0x11);
+        assert_eq!(cpu.read_io(0x82) & 0xf8, 0x00);
+        cpu.acknowledge_8255(0x80, 0);
+        cpu.set_8255_input(0x80, 0, 0x22);
+        cpu.strobe_8255(0x80, 0);
+        assert_eq!(cpu.read_io(0x82) & 0xf8, 0xa0); // OBF A high, IBF A
+        assert_eq!(cpu.read_io(0x80), 0x22);
+        assert_eq!(cpu.read_io(0x82) & 0xf8, 0x80);
+
+        // mode 1 input on port A, INTR A raises RST5.5
+        cpu.reset();
+        cpu.load_segments(
+            &[
+                Segment::new(0, bin),
+                Segment::new(0x2c, vec![0xdb, 0x80, 0x76]),
+            ],
+            None,
+        )
+        .unwrap();
+        cpu.connect_interrupt(0x80, "intr a", Interrupt::Rst55, false)
+            .unwrap();
+        assert_eq!(cpu.run_until(100, |_| false), StopReason::Halted);
+        assert_eq!(cpu.read_io(0x82) & 0x38, 0x10); // INTE A
+        cpu.set_8255_input(0x80, 0, 0x42);
+        cpu.strobe_8255(0x80, 0);
+        assert_eq!(cpu.read_io(0x82) & 0x38, 0x38);
+        assert_eq!(cpu.step().unwrap().interrupt, Some(Interrupt::Rst55));
+        cpu.step().unwrap();
+        assert_eq!(cpu.A, 0x42);
+        assert_eq!(cpu.read_io(0x82) & 0x38, 0x10);
+        assert!(!cpu
+            .system_bus()
+            .unwrap()
+            .io_line(0x80, "intr a")
+            .unwrap()
+            .level());
+    }
+
+    #[test]
+    fn test_io_devices() {
+        use crate::ioport::{IoDevice, IoPort};
+
+        // counts T-states at ports 10h-11h, OUT 10h sets the count and IN 11h clears it
+        struct Counter {
+            count: u16,
+        }
+        impl IoDevice for Counter {
+            fn io_read(&mut self, offset: u8) -> u8 {
+                let data = self.io_peek(offset);
+                if offset == 1 {
+                    self.count = 0;
+                }
+                data
+            }
+            fn io_write(&mut self, _offset: u8, data: u8) {
+                self.count = data as u16;
+            }
+            fn io_peek(&self, offset: u8) -> u8 {
+                (self.count >> (8 * offset)) as u8
+            }
+            fn tick(&mut self, cycles: u32) {
+                self.count = self.count.wrapping_add(cycles as u16);
+            }
+            fn save(&self) -> Vec<u8> {
+                self.count.to_le_bytes().to_vec()
+            }
+            fn load(&mut self, data: &[u8]) -> Result<(), String> {
+                self.count = u16::from_le_bytes([data[0], data[1]]);
+                Ok(())
+            }
+        }
+
+        let (bin, _) =
+            crate::asm8085::assemble("mvi a,05h\nout 10h\nnop\nin 10h\nin 11h\nhlt").unwrap();
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new_from(&bin, 256));
+        let counter = Box::new(Counter { count: 0 });
+        cpu.system_bus_mut()
+            .unwrap()
+            .map_io(0x10, 0x11, "counter", counter)
+            .unwrap();
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.A, 5 + 10 + 4); // OUT, then NOP
+        cpu.step().unwrap();
+        assert_eq!(cpu.A, 0);
+        assert_eq!(cpu.bus().io_peek(0x10), 10); // cleared during IN 11h
+
+        assert!(cpu
+            .system_bus_mut()
+            .unwrap()
+            .map_io(0x11, 0x12, "other", Box::new(IoPort::new(0x11)))
+            .is_err());
+        assert!(!cpu.add_io_port(0x11));
+        assert!(!cpu.remove_io_port(0x10));
+        assert!(cpu.add_io_port(0x12));
+        assert_eq!(cpu.system_bus().unwrap().io_regions().len(), 2);
+        assert_eq!(cpu.read_io(0x20), 0);
+
+        let state = cpu.save_state();
+        let mut other = PP8085::new();
+        other.load_memory(Memory::new(256).unwrap());
+        assert!(other.load_state(&state).is_err());
+        let counter = Box::new(Counter { count: 0 });
+        other
+            .system_bus_mut()
+            .unwrap()
+            .map_io(0x10, 0x11, "counter", counter)
+            .unwrap();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.bus().io_peek(0x10), 10);
+        assert_eq!(other.system_bus().unwrap().io_ports().len(), 1);
     }
 
     #[test]
@@ -2393,6 +4102,11 @@ mod tests {
         assert_eq!(cpu.B, 0xff);
         cpu.inr_b();
         assert_eq!(cpu.B, 0x00);
+        assert!(!cpu.get_overflow());
+
+        cpu.B = 0x7f;
+        cpu.inr_b();
+        assert_eq!(cpu.B, 0x80);
         assert!(cpu.get_overflow());
     }
 
@@ -2405,13 +4119,1354 @@ mod tests {
             cpu.dcr_b();
         }
         assert_eq!(cpu.B, 0xff);
+        assert!(!cpu.get_overflow());
+
+        cpu.B = 0x80;
+        cpu.dcr_b();
+        assert_eq!(cpu.B, 0x7f);
+        assert!(cpu.get_overflow());
+        assert!(!cpu.get_auxiliary_carry());
+
+        cpu.B = 0x21;
+        cpu.dcr_b();
+        assert!(cpu.get_auxiliary_carry());
+    }
+
+    #[test]
+    fn test_rst75_interrupt() {
+        let mut cpu = PP8085::new();
+        cpu.SP = 0x1000;
+        cpu.PC = 0x0234;
+        cpu.IE = true;
+        cpu.set_rst75(true);
+        // masked after reset
+        assert_eq!(cpu.service_interrupts(), None);
+        cpu.A = 0x08; // MSE, unmask all
+        cpu.sim();
+        assert_eq!(cpu.service_interrupts(), Some(Interrupt::Rst75));
+        assert_eq!(cpu.PC, 0x003C);
+        assert!(!cpu.IE);
+        assert_eq!(cpu.read_memory(cpu.SP), 0x34);
+        assert_eq!(cpu.read_memory(cpu.SP + 1), 0x02);
+
+        // the latch was cleared by the acknowledge, a new edge is needed
+        cpu.IE = true;
+        assert_eq!(cpu.service_interrupts(), None);
+        cpu.set_rst75(false);
+        cpu.set_rst75(true);
+        cpu.rim();
+        assert_eq!(cpu.A, 0x48);
+        cpu.A = 0x10; // R7.5
+        cpu.sim();
+        assert_eq!(cpu.service_interrupts(), None);
+    }
+
+    #[test]
+    fn test_interrupt_priority() {
+        let mut cpu = PP8085::new();
+        cpu.SP = 0x1000;
+        cpu.A = 0x08;
+        cpu.sim();
+        cpu.IE = true;
+        cpu.set_intr(true);
+        cpu.set_rst55(true);
+        cpu.set_rst65(true);
+        cpu.set_trap(true);
+        cpu.rim();
+        assert_eq!(cpu.A, 0x38);
+
+        cpu.service_interrupts();
+        assert_eq!(cpu.PC, 0x0024);
+        cpu.IE = true;
+        cpu.service_interrupts();
+        assert_eq!(cpu.PC, 0x0034);
+        cpu.IE = true;
+        cpu.set_rst65(false);
+        cpu.service_interrupts();
+        assert_eq!(cpu.PC, 0x002C);
+        cpu.IE = true;
+        cpu.set_rst55(false);
+        cpu.set_intr_opcode(0xD7); // RST 2
+        cpu.service_interrupts();
+        assert_eq!(cpu.PC, 0x0010);
+    }
+
+    #[test]
+    fn test_mask_and_ei_delay() {
+        let mut cpu = PP8085::new();
+        let mut rom = Memory::new(8192).unwrap();
+        rom.write(0x0000, 0x31); // lxi sp 1000
+        rom.write(0x0001, 0x00);
+        rom.write(0x0002, 0x10);
+        rom.write(0x0003, 0x3e); // mvi a 0d
+        rom.write(0x0004, 0x0d);
+        rom.write(0x0005, 0x30); // sim
+        rom.write(0x0006, 0xfb); // ei
+        rom.write(0x0007, 0x00); // nop
+        rom.write(0x0008, 0x76); // hlt
+        rom.write(0x0034, 0x76); // hlt
+        cpu.load_memory(rom);
+        cpu.set_rst55(true);
+        cpu.set_rst65(true);
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+        // the instruction after EI is always executed
+        assert_eq!(cpu.PC, 0x0008);
+        cpu.step().unwrap();
+        // RST5.5 is masked, RST6.5 is taken
+        assert_eq!(cpu.PC, 0x0034);
+        assert_eq!(cpu.read_memory(cpu.SP), 0x08);
+    }
+
+    #[test]
+    fn test_trap_wakes_hlt() {
+        let mut cpu = PP8085::new();
+        cpu.SP = 0x1000;
+        cpu.write_memory(0x0000, 0x76); // hlt
+        cpu.write_memory(0x0024, 0x20); // rim
+        cpu.IE = true;
+        cpu.run().unwrap();
+        assert!(cpu.get_hlt());
+        cpu.set_trap(true);
+        cpu.step().unwrap();
+        assert!(!cpu.get_hlt());
+        assert_eq!(cpu.PC, 0x0024);
+        cpu.step().unwrap();
+        // RIM after TRAP reports IE from before the TRAP
+        assert_eq!(cpu.A & 0x08, 0x08);
+    }
+
+    #[test]
+    fn test_sid_sod() {
+        let mut cpu = PP8085::new();
+        cpu.set_sid(true);
+        cpu.rim();
+        assert_eq!(cpu.A & 0x80, 0x80);
+
+        cpu.A = 0xc0;
+        cpu.sim();
+        assert!(cpu.get_sod());
+        // SOE not set, SOD keeps its value
+        cpu.A = 0x00;
+        cpu.sim();
+        assert!(cpu.get_sod());
+        cpu.A = 0x40;
+        cpu.sim();
+        assert!(!cpu.get_sod());
+        assert_eq!(cpu.sod_log(), &[(0, true), (0, false)]);
+    }
+
+    #[test]
+    fn test_sertest() {
+        let (bin, _) = crate::asm8085::assemble(include_str!("../examples/sertest.asm")).unwrap();
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new_from(&bin, 8192));
+        // H is 0 after LXI H,D5H so the delay loop runs 213 + 255 * 256
+        // times, a bit is 919774 T-states: 1 baud at a 919774 Hz clock
+        let bit = 919_774;
+        cpu.run_for_cycles(11 * bit as u64).unwrap();
+        assert_eq!(cpu.decode_sod(1, bit), vec![0xdf]);
+    }
+
+    #[test]
+    fn test_t_states() {
+        for op in 0..=0xff_u8 {
+            let (taken, not_taken) = OPCODES[op as usize].t_states;
+            let mut res = Vec::new();
+            for f in [0x00, 0xff] {
+                let mut cpu = PP8085::new();
+                cpu.set_undocumented(true);
+                cpu.SP = 0x1000;
+                cpu.PC = 0x0100;
+                cpu.F = f;
+                cpu.IR = op;
+                res.push(cpu.decode_and_run());
+            }
+            res.sort();
+            assert_eq!(
+                res,
+                vec![not_taken.min(taken), taken.max(not_taken)],
+                "opcode {:#04x}",
+                op
+            );
+        }
+    }
+
+    #[test]
+    fn test_undocumented() {
+        let mut cpu = PP8085::new();
+        cpu.set_undocumented(true);
+
+        // DSUB
+        cpu.H = 0x12;
+        cpu.L = 0x34;
+        cpu.B = 0x02;
+        cpu.C = 0x35;
+        cpu.dsub();
+        assert_eq!(cpu.get_addr_hl(), 0x0fff);
+        assert!(!cpu.get_carry());
+        assert!(!cpu.get_zero());
+
+        // ARHL keeps the sign bit
+        cpu.H = 0x80;
+        cpu.L = 0x03;
+        cpu.arhl();
+        assert_eq!(cpu.get_addr_hl(), 0xc001);
+        assert!(cpu.get_carry());
+
+        // RDEL rotates through carry
+        cpu.D = 0x40;
+        cpu.E = 0x01;
+        cpu.set_carry(true);
+        cpu.rdel();
+        assert_eq!(cpu.get_addr_de(), 0x8003);
+        assert!(!cpu.get_carry());
         assert!(cpu.get_overflow());
+
+        // LDHI, LDSI
+        cpu.H = 0x20;
+        cpu.L = 0xf0;
+        cpu.PC = 0x0100;
+        cpu.write_memory(0x0100, 0x20);
+        cpu.ldhi();
+        assert_eq!(cpu.get_addr_de(), 0x2110);
+        cpu.SP = 0x1000;
+        cpu.write_memory(0x0101, 0x04);
+        cpu.ldsi();
+        assert_eq!(cpu.get_addr_de(), 0x1004);
+
+        // SHLX, LHLX
+        cpu.H = 0xbe;
+        cpu.L = 0xef;
+        cpu.shlx();
+        assert_eq!(cpu.read_memory(0x1004), 0xef);
+        assert_eq!(cpu.read_memory(0x1005), 0xbe);
+        cpu.H = 0;
+        cpu.L = 0;
+        cpu.lhlx();
+        assert_eq!(cpu.get_addr_hl(), 0xbeef);
+
+        // RSTV is taken only on overflow
+        cpu.PC = 0x0200;
+        cpu.set_overflow(false);
+        assert!(!cpu.rstv());
+        assert_eq!(cpu.PC, 0x0200);
+        cpu.set_overflow(true);
+        assert!(cpu.rstv());
+        assert_eq!(cpu.PC, 0x0040);
+        assert_eq!(cpu.read_memory(cpu.SP), 0x00);
+        assert_eq!(cpu.read_memory(cpu.SP + 1), 0x02);
+
+        // INX sets K when the pair wraps, JK and JNK test it
+        cpu.B = 0xff;
+        cpu.C = 0xff;
+        cpu.inx_b();
+        assert_eq!(cpu.get_addr_bc(), 0x0000);
+        assert!(cpu.get_k());
+        cpu.PC = 0x0300;
+        cpu.write_memory(0x0300, 0x00);
+        cpu.write_memory(0x0301, 0x04);
+        assert!(cpu.jk());
+        assert_eq!(cpu.PC, 0x0400);
+        cpu.inx_b();
+        assert!(!cpu.get_k());
+        cpu.PC = 0x0300;
+        assert!(!cpu.jk());
+        assert_eq!(cpu.PC, 0x0302);
+    }
+
+    #[test]
+    fn test_assemble_undocumented() {
+        let code = "dsub\narhl\nrdel\nldhi 20h\nldsi 04h\nrstv\nshlx\njnk 0400h\nlhlx\njk 0400h\n";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        assert_eq!(
+            bin,
+            vec![
+                0x08, 0x10, 0x18, 0x28, 0x20, 0x38, 0x04, 0xcb, 0xd9, 0xdd, 0x00, 0x04, 0xed, 0xfd,
+                0x00, 0x04
+            ]
+        );
+    }
+
+    #[test]
+    fn test_undocumented_disabled() {
+        let mut cpu = PP8085::new();
+        cpu.PC = 0x01f3;
+        cpu.write_memory(0x01f3, 0x08);
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::IllegalOpcode {
+                pc: 0x01f3,
+                opcode: 0x08
+            })
+        );
+        assert_eq!(cpu.PC, 0x01f3);
+        assert_eq!(
+            cpu.step().unwrap_err().to_string(),
+            "illegal opcode at 0x01F3 (0x08)"
+        );
+        // the run functions return the error too
+        let err = CpuError::IllegalOpcode {
+            pc: 0x01f3,
+            opcode: 0x08,
+        };
+        assert_eq!(cpu.run(), Err(err));
+        assert_eq!(cpu.run_next(), Err(err));
+        assert_eq!(cpu.run_for_cycles(10), Err(err));
+    }
+
+    #[test]
+    fn test_step_errors() {
+        let mut cpu = PP8085::new();
+        cpu.write_memory(0x0000, 0xc5); // push b
+        assert_eq!(cpu.step(), Err(CpuError::StackWrap { pc: 0, sp: 0xffff }));
+
+        cpu.reset();
+        cpu.write_memory(0x0000, 0x3a); // lda 2000h
+        cpu.write_memory(0x0001, 0x00);
+        cpu.write_memory(0x0002, 0x20);
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::MemoryOutOfRange {
+                pc: 0,
+                addr: 0x2000
+            })
+        );
+    }
+
+    #[test]
+    fn test_full_memory() {
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new(0x10000).unwrap());
+        assert_eq!(cpu.memory_map()[0].end, 0xffff);
+
+        // the stack wraps from 0x0000 to the top of memory
+        cpu.write_memory(0x0000, 0xc5); // push b
+        cpu.write_memory(0x0001, 0xc1); // pop b
+        cpu.write_memory(0x0002, 0x7e); // mov a,m
+        cpu.B = 0x12;
+        cpu.C = 0x34;
+        cpu.step().unwrap();
+        assert_eq!(cpu.SP, 0xfffe);
+        assert_eq!(cpu.peek_memory(0xfffe, 2), vec![0x34, 0x12]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.SP, 0x0000);
+        cpu.write_memory(0xffff, 0x42);
+        cpu.H = 0xff;
+        cpu.L = 0xff;
+        cpu.step().unwrap();
+        assert_eq!(cpu.A, 0x42);
+
+        // PC wraps to 0x0000
+        cpu.PC = 0xffff;
+        cpu.write_memory(0xffff, 0x00); // nop
+        cpu.step().unwrap();
+        assert_eq!(cpu.PC, 0x0000);
+        assert!(Memory::new(0x10001).is_err());
+
+        // reads outside of a smaller memory follow its setting
+        let mut mem = Memory::new(0x100).unwrap();
+        assert_eq!(mem.read(0x200), 0xff);
+        mem.set_out_of_range(OutOfRange::Zero);
+        assert_eq!(mem.read(0x200), 0x00);
+        mem.write(0x200, 0x12);
+        assert_eq!(mem.read(0x200), 0x00);
+
+        // unmapped writes fault by default, or are dropped
+        let mut cpu = PP8085::new();
+        cpu.write_memory(0x0000, 0x32); // sta 2000h
+        cpu.write_memory(0x0002, 0x20);
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::MemoryOutOfRange {
+                pc: 0,
+                addr: 0x2000
+            })
+        );
+        cpu.reset();
+        cpu.set_out_of_range(OutOfRange::OpenBus);
+        cpu.step().unwrap();
+    }
+
+    #[test]
+    fn test_unmapped_region_open_bus() {
+        use crate::memory::RegionKind;
+
+        let mut cpu = PP8085::new();
+        cpu.map_ram(0x4000, 0x4fff).unwrap();
+        cpu.set_region(0x4800, 0x4fff, RegionKind::Unmapped)
+            .unwrap();
+        assert!(cpu.set_region(0x4f00, 0x50ff, RegionKind::Rom).is_err());
+        assert!(cpu.set_region(0x6000, 0x60ff, RegionKind::Rom).is_err());
+        let (bin, _) = crate::asm8085::assemble("lda 4900h\nsta 4a00h\nhlt").unwrap();
+        for (i, b) in bin.iter().enumerate() {
+            cpu.write_memory(i as u16, *b);
+        }
+
+        // the unmapped region of the memory faults like the bus by default
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::MemoryOutOfRange {
+                pc: 0,
+                addr: 0x4900
+            })
+        );
+
+        // and follows the bus once it is set to open bus
+        cpu.reset();
+        cpu.set_out_of_range(OutOfRange::OpenBus);
+        assert_eq!(cpu.run_until_break(10), StopReason::Halted);
+        assert_eq!(cpu.A, 0xff);
+
+        // memory mapped later takes the setting of the bus too
+        cpu.map_ram(0x5000, 0x50ff).unwrap();
+        cpu.set_region(0x5000, 0x50ff, RegionKind::Unmapped)
+            .unwrap();
+        cpu.write_memory(1, 0x00);
+        cpu.write_memory(2, 0x50); // lda 5000h
+        cpu.reset();
+        assert_eq!(cpu.run_until_break(10), StopReason::Halted);
+        assert_eq!(cpu.A, 0xff);
+    }
+
+    #[test]
+    fn test_memory_regions() {
+        use crate::memory::RegionKind;
+
+        // a runaway loop that fills memory from HL, over its own code
+        let (bin, _) = crate::asm8085::assemble("loop: mov m,a\ninx h\njmp loop").unwrap();
+        let mut mem = Memory::new_from(&bin, 0x1000);
+        mem.set_region(0x0000, 0x00ff, RegionKind::Rom);
+        mem.set_region(0x0800, 0x0fff, RegionKind::Unmapped);
+        mem.set_rom_write_fault(true);
+        assert_eq!(mem.kind(0x0010), RegionKind::Rom);
+        assert_eq!(mem.kind(0x0100), RegionKind::Ram);
+        assert_eq!(mem.kind(0x0900), RegionKind::Unmapped);
+        assert_eq!(mem.kind(0x2000), RegionKind::Unmapped);
+        assert_eq!(mem.read(0x0900), 0xff);
+
+        let mut cpu = PP8085::new();
+        cpu.load_memory(mem.clone());
+        cpu.A = 0xaa;
+        assert_eq!(
+            cpu.run_until_break(100),
+            StopReason::Error(CpuError::RomWrite { pc: 0, addr: 0 })
+        );
+        assert_eq!(cpu.peek_memory(0, 1), vec![0x77]);
+        assert_eq!(
+            CpuError::RomWrite { pc: 0, addr: 0x10 }.to_string(),
+            "write to ROM 0x0010 at 0x0000"
+        );
+
+        // writes to ROM are ignored, the loop runs into unmapped memory
+        mem.set_rom_write_fault(false);
+        cpu.load_memory(mem);
+        cpu.reset();
+        cpu.A = 0xaa;
+        assert_eq!(
+            cpu.run_until_break(10000),
+            StopReason::Error(CpuError::MemoryOutOfRange {
+                pc: 0,
+                addr: 0x0800
+            })
+        );
+        assert_eq!(cpu.peek_memory(0, 5), bin);
+        assert_eq!(cpu.peek_memory(0x07ff, 2), vec![0xaa, 0xff]);
+
+        // a ROM device can fault too
+        let mut rom = Rom::new(bin.clone());
+        rom.set_write_fault(true);
+        let mut cpu = PP8085::new();
+        cpu.system_bus_mut().unwrap().unmap_all();
+        cpu.system_bus_mut()
+            .unwrap()
+            .map(0, 4, "rom", Box::new(rom))
+            .unwrap();
+        cpu.map_ram(0x1000, 0x1fff).unwrap();
+        assert_eq!(
+            cpu.run_until_break(100),
+            StopReason::Error(CpuError::RomWrite { pc: 0, addr: 0 })
+        );
+    }
+
+    #[test]
+    fn test_banked_memory() {
+        use crate::banked::BankedMemory;
+
+        let code = "mvi a,01h\nout 40h\nmvi a,11h\nsta 2000h\n\
+                    mvi a,02h\nout 40h\nmvi a,22h\nsta 2000h\nhlt";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new_from(&bin, 0x1000));
+        cpu.map_banked_ram(0x2000, 0x2fff, 4, 0x40).unwrap();
+        assert_eq!(range_size(0x2000, 0x2fff), Ok(0x1000));
+        assert_eq!(range_size(0x0000, 0xffff), Ok(0x10000));
+        assert!(range_size(0x3000, 0x2fff).is_err());
+        let state = cpu.save_state();
+        cpu.run().unwrap();
+
+        // the viewer sees the selected bank
+        assert_eq!(cpu.read_io(0x40), 0x02);
+        assert_eq!(cpu.peek_memory(0x2000, 1), vec![0x22]);
+        cpu.write_io(0x40, 0x01);
+        assert_eq!(cpu.peek_memory(0x2000, 1), vec![0x11]);
+        cpu.write_io(0x40, 0x00);
+        assert_eq!(cpu.peek_memory(0x2000, 1), vec![0x00]);
+        cpu.write_io(0x40, 0x05); // wraps to bank 1
+        assert_eq!(cpu.peek_memory(0x2000, 1), vec![0x11]);
+
+        // save states keep the selected bank and every bank
+        cpu.write_io(0x40, 0x02);
+        let after = cpu.save_state();
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.read_io(0x40), 0x00);
+        cpu.write_io(0x40, 0x01);
+        assert_eq!(cpu.peek_memory(0x2000, 1), vec![0x00]);
+        cpu.load_state(&after).unwrap();
+        assert_eq!(cpu.read_io(0x40), 0x02);
+        assert_eq!(cpu.peek_memory(0x2000, 1), vec![0x22]);
+        cpu.write_io(0x40, 0x01);
+        assert_eq!(cpu.peek_memory(0x2000, 1), vec![0x11]);
+
+        // ROM banks selected by a memory mapped register
+        let banked = BankedMemory::rom(vec![vec![0xaa; 0x100], vec![0xbb; 0x100]]);
+        let select = banked.selector();
+        let bus = cpu.system_bus_mut().unwrap();
+        bus.map(0x3000, 0x30ff, "banked rom", Box::new(banked))
+            .unwrap();
+        bus.map(0x3100, 0x3100, "bank select", Box::new(select))
+            .unwrap();
+        cpu.write_memory(0x3000, 0x00);
+        assert_eq!(cpu.peek_memory(0x3000, 1), vec![0xaa]);
+        cpu.write_memory(0x3100, 0x01);
+        assert_eq!(cpu.peek_memory(0x3000, 1), vec![0xbb]);
+        assert_eq!(cpu.read_memory(0x3100), 0x01);
+    }
+
+    #[test]
+    fn test_load_segments() {
+        use crate::loader::Segment;
+
+        let (prog, _) = crate::asm8085::assemble("lda 0100h\nsta 2100h\nhlt").unwrap();
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new(0x10000).unwrap());
+        cpu.system_bus_mut().unwrap().unmap_all();
+        cpu.map_rom(0x0000, &[0; 0x200]).unwrap();
+        cpu.map_ram(0x2000, 0xffff).unwrap();
+        let segments = [
+            Segment::new(0x2000, prog.clone()),
+            Segment::new(0x0100, vec![0x42]),
+        ];
+        cpu.load_segments(&segments, Some(0x2000)).unwrap();
+        assert_eq!(cpu.get_pc(), 0x2000);
+        assert_eq!(cpu.peek_memory(0x0100, 1), vec![0x42]); // loaded into ROM
+        cpu.run().unwrap();
+        assert_eq!(cpu.peek_memory(0x2100, 1), vec![0x42]);
+
+        // nothing is loaded when a segment does not fit
+        let err = cpu.load_segments(&[Segment::new(0xfffe, vec![1, 2, 3])], None);
+        assert_eq!(
+            err,
+            Err("Segment at 0xFFFE of 3 bytes runs past 0xFFFF".to_string())
+        );
+        let bad = [
+            Segment::new(0x3000, vec![1]),
+            Segment::new(0x01ff, vec![1, 2]),
+        ];
+        assert_eq!(
+            cpu.load_segments(&bad, Some(0)),
+            Err("Segment at 0x01FF does not fit in memory, 0x0200 is not mapped".to_string())
+        );
+        assert_eq!(cpu.peek_memory(0x3000, 1), vec![0x00]);
+        assert_eq!(cpu.get_pc(), 0x2007);
+        let overlap = [
+            Segment::new(0x3000, vec![1, 2]),
+            Segment::new(0x3001, vec![3]),
+        ];
+        assert!(cpu.load_segments(&overlap, None).is_err());
+
+        let mut mem = Memory::new(0x100).unwrap();
+        assert!(mem.load(0x00f0, &[0; 0x10]).is_ok());
+        assert!(mem.load(0x00f0, &[0; 0x11]).is_err());
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        use crate::ihex;
+        use crate::loader::{Image, Segment};
+
+        let hex = ihex::assemble("mvi a, 42h\nhlt").unwrap();
+        assert_eq!(hex, ":030000003E427607\n:00000001FF\n");
+
+        let image = Image {
+            segments: vec![
+                Segment::new(0x2000, (0..20).collect()),
+                Segment::new(0x3000, vec![0x76]),
+            ],
+            entry: Some(0x2000),
+        };
+        let text = ihex::write(&image);
+        assert_eq!(text.lines().count(), 5);
+        assert_eq!(ihex::parse(&text), Ok(image));
+
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new(0x10000).unwrap());
+        cpu.load_hex(":0420000021341276FF\n:0400000300002000D9\n:00000001FF\n")
+            .unwrap();
+        assert_eq!(cpu.get_pc(), 0x2000);
+        cpu.run().unwrap();
+        assert_eq!((cpu.H, cpu.L), (0x12, 0x34));
+        assert_eq!(
+            cpu.export_hex(0x2000, 0x2003),
+            ":0420000021341276FF\n:00000001FF\n"
+        );
+
+        let bad = [
+            (
+                "0300000021341296\n",
+                "Line 1: record does not start with ':'",
+            ),
+            (
+                ":03000000213412\n",
+                "Line 1: record length does not match its count",
+            ),
+            (
+                ":0300000021341297\n",
+                "Line 1: bad checksum 97, expected 96",
+            ),
+            (":03000000213412XY\n", "Line 1: bad hex digits"),
+            (":0300000621341290\n", "Line 1: unknown record type 06"),
+            (
+                ":020000040001F9\n:0100000000FF\n",
+                "Line 2: data past address 0xFFFF",
+            ),
+            (":0300000021341296\n", "Missing end of file record"),
+        ];
+        for (text, err) in bad.iter() {
+            assert_eq!(ihex::parse(text), Err(err.to_string()));
+        }
+
+        let mut mem = Memory::new(0x100).unwrap();
+        assert!(mem.load_hex(":0400F000213412762F\n:00000001FF\n").is_ok());
+        assert_eq!(mem.read(0x00f1), 0x34);
+        assert!(mem.load_hex(":0400FE002134127621\n:00000001FF\n").is_err());
+    }
+
+    #[test]
+    fn test_srec() {
+        use crate::loader::{Image, Segment};
+        use crate::srec;
+
+        let text = srec::assemble("mvi a, 42h\nhlt").unwrap();
+        assert_eq!(text, "S0030000FC\nS10600003E427603\nS9030000FC\n");
+
+        let image = Image {
+            segments: vec![
+                Segment::new(0x2000, (0..20).collect()),
+                Segment::new(0x3000, vec![0x76]),
+            ],
+            entry: Some(0x2000),
+        };
+        let text = srec::write(&image, "test");
+        assert!(text.starts_with("S00700007465737438\n"));
+        assert_eq!(text.lines().count(), 5);
+        assert_eq!(srec::parse(&text), Ok(image));
+
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new(0x10000).unwrap());
+        cpu.load_srec("S0030000FC\nS107200021341276FB\nS9032000DC\n")
+            .unwrap();
+        assert_eq!(cpu.get_pc(), 0x2000);
+        cpu.run().unwrap();
+        assert_eq!((cpu.H, cpu.L), (0x12, 0x34));
+        assert_eq!(
+            cpu.export_srec(0x2000, 0x2003),
+            "S0030000FC\nS107200021341276FB\nS9030000FC\n"
+        );
+
+        let bad = [
+            (
+                "107200021341276FB\n",
+                "Line 1: record does not start with 'S'",
+            ),
+            (
+                "S1072000213412\n",
+                "Line 1: record length does not match its count",
+            ),
+            (
+                "S107200021341276FC\n",
+                "Line 1: bad checksum FC, expected FB",
+            ),
+            ("S107200021341276XY\n", "Line 1: bad hex digits"),
+            (
+                "S208000000213412761A\n",
+                "Line 1: S2 records address more than 64K",
+            ),
+            (
+                "S105FFFF21341276FB\n",
+                "Line 1: record length does not match its count",
+            ),
+            ("S106FFFF21341294\n", "Line 1: data past address 0xFFFF"),
+            ("S107200021341276FB\n", "Missing S9 termination record"),
+        ];
+        for (text, err) in bad.iter() {
+            assert_eq!(srec::parse(text), Err(err.to_string()));
+        }
+
+        let mut mem = Memory::new(0x100).unwrap();
+        assert!(mem.load_srec("S10700F0213412762B\nS9030000FC\n").is_ok());
+        assert_eq!(mem.read(0x00f2), 0x12);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut cpu = PP8085::new();
+        cpu.write_memory(0x0000, 0x04); // inr b
+        cpu.write_memory(0x0001, 0x04); // inr b
+        cpu.write_memory(0x0002, 0x76); // hlt
+        assert_eq!(cpu.run_until(10, |c| c.B == 1), StopReason::Condition);
+        assert_eq!(cpu.PC, 0x0001);
+        assert_eq!(cpu.run_until(1, |_| false), StopReason::StepLimit);
+        assert_eq!(cpu.run_until(10, |_| false), StopReason::Halted);
+        let info = cpu.step().unwrap();
+        assert!(info.halted);
+        assert_eq!(info.t_states, 1);
+
+        cpu.reset();
+        cpu.write_memory(0x0000, 0xdd);
+        assert_eq!(
+            cpu.run_until(10, |_| false),
+            StopReason::Error(CpuError::IllegalOpcode {
+                pc: 0,
+                opcode: 0xdd
+            })
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let code = "mvi a,05h\nsta 1000h\nout 05h\nlda 1000h\nhlt\n";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new_from(&bin, 8192));
+        cpu.add_io_port(0x05);
+        cpu.set_tracer(Some(Tracer::ring_buffer(3)));
+        cpu.run().unwrap();
+        cpu.step().unwrap(); // halted, not traced
+
+        let records = cpu.tracer().unwrap().records();
+        assert_eq!(records.len(), 3);
+        let out = records[0];
+        assert_eq!(out.pc, 0x0005);
+        assert_eq!(out.bytes, vec![0xd3, 0x05]);
+        assert_eq!(out.mnemonic, "out 05h");
+        assert_eq!(
+            out.io_writes,
+            vec![Access {
+                address: 0x05,
+                data: 0x05
+            }]
+        );
+        let lda = records[1];
+        assert_eq!(lda.mnemonic, "lda 1000h");
+        assert_eq!(lda.before.a, 0x05);
+        assert_eq!(lda.after.pc, 0x000a);
+        assert_eq!(
+            lda.mem_reads,
+            vec![Access {
+                address: 0x1000,
+                data: 0x05
+            }]
+        );
+        assert_eq!(lda.cycles, 13);
+        assert_eq!(
+            lda.to_string(),
+            "0007  3a 00 10  lda 1000h       \
+             A:05 F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:0000  ->  \
+             A:05 F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:0000  R 1000=05  13T"
+        );
+        assert_eq!(records[2].mnemonic, "hlt");
+    }
+
+    #[test]
+    fn test_trace_json_lines() {
+        use std::io::Write;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone)]
+        struct Buf(Arc<Mutex<Vec<u8>>>);
+        impl Write for Buf {
+            fn write(&mut self, b: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(b)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buf = Buf(Arc::new(Mutex::new(Vec::new())));
+        let mut cpu = PP8085::new();
+        cpu.write_memory(0x0000, 0x3c); // inr a
+        cpu.write_memory(0x0001, 0x76); // hlt
+        cpu.set_tracer(Some(Tracer::json_lines(buf.clone())));
+        cpu.run().unwrap();
+
+        let out = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let rec: TraceRecord = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(rec.mnemonic, "inr a");
+        assert_eq!(rec.after.a, 0x01);
+        assert_eq!(rec.cycles, 4);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let code = "lxi h,1000h\nmvi m,07h\nloop: mov a,m\nout 05h\ndcr m\njnz loop\nhlt\n";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new_from(&bin, 8192));
+        cpu.add_io_port(0x05);
+
+        // pc breakpoint stops before the instruction
+        let bp = cpu.add_breakpoint(0x0005);
+        assert_eq!(cpu.run_until_break(100), StopReason::Breakpoint(bp));
+        assert_eq!(cpu.PC, 0x0005);
+        // and does not stop again at the same place
+        assert_eq!(cpu.run_until_break(1), StopReason::StepLimit);
+        assert!(cpu.remove_breakpoint(bp));
+        assert!(!cpu.remove_breakpoint(bp));
+
+        // memory write watchpoint, the read by mov a,m does not trigger it
+        let wp = cpu.add_watchpoint(0x0fff, 0x1000, false, true);
+        assert_eq!(cpu.run_until_break(100), StopReason::Breakpoint(wp));
+        assert_eq!(cpu.PC, 0x0009); // after dcr m
+        assert_eq!(cpu.read_memory(0x1000), 0x06);
+        cpu.clear_breakpoints();
+
+        // io watchpoint
+        let io = cpu.add_io_watchpoint(0x05, false, true);
+        assert_eq!(cpu.run_until_break(100), StopReason::Breakpoint(io));
+        assert_eq!(cpu.get_io_output(0x05), Some(0x06));
+        cpu.clear_breakpoints();
+
+        // register condition triggers when it becomes true
+        assert!(cpu.add_register_condition("q", "<", 3).is_err());
+        assert!(cpu.add_register_condition("a", "<>", 3).is_err());
+        let cond = cpu.add_register_condition("a", "<", 3).unwrap();
+        assert_eq!(cpu.run_until_break(100), StopReason::Breakpoint(cond));
+        assert_eq!(cpu.A, 0x02);
+        assert_eq!(cpu.run_until_break(100), StopReason::Halted);
+    }
+
+    #[test]
+    fn test_step_back() {
+        let code = "lxi sp,1800h\nlxi h,1000h\nmvi m,03h\nloop: mov a,m\nout 05h\npush psw\ndcr m\njnz loop\nmvi a,0c0h\nsim\nhlt\n";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new_from(&bin, 8192));
+        cpu.add_io_port(0x05);
+        cpu.set_history_depth(100);
+
+        let start = cpu.registers();
+        let mut states = vec![start];
+        while !cpu.HLT {
+            cpu.step().unwrap();
+            states.push(cpu.registers());
+        }
+        assert_eq!(cpu.sod_log().len(), 1);
+        assert_eq!(cpu.get_history_len(), states.len() - 1);
+
+        // back to the last mov a,m, the memory and port hold the values of that time
+        assert!(cpu.run_back_until(0x0008));
+        assert_eq!(cpu.read_memory(0x1000), 0x01);
+        assert_eq!(cpu.get_io_output(0x05), Some(0x02));
+        assert!(cpu.sod_log().is_empty());
+        assert!(!cpu.HLT);
+
+        states.truncate(cpu.get_history_len() + 1);
+        assert_eq!(cpu.registers(), *states.last().unwrap());
+        while cpu.step_back() {
+            states.pop();
+            assert_eq!(cpu.registers(), *states.last().unwrap());
+        }
+        assert_eq!(cpu.registers(), start);
+        assert_eq!(cpu.get_total_cycles(), 0);
+        assert_eq!(cpu.read_memory(0x1000), 0x00);
+        assert_eq!(cpu.read_memory(0x17ff), 0x00);
+        assert_eq!(cpu.get_io_output(0x05), Some(0x00));
+
+        // the journal is bounded
+        cpu.set_history_depth(2);
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.get_history_len(), 2);
+        assert!(!cpu.run_back_until(0x0000));
+    }
+
+    #[test]
+    fn test_send() {
+        // the machine, devices included, can be moved to another thread
+        fn is_send<T: Send>() {}
+        is_send::<PP8085>();
+        let mut cpu = PP8085::new();
+        cpu.add_8254(0x40).unwrap();
+        cpu.write_memory(0x0000, 0x76); // hlt
+        let cpu = std::thread::spawn(move || {
+            cpu.run().unwrap();
+            cpu
+        })
+        .join()
+        .unwrap();
+        assert!(cpu.get_hlt());
+    }
+
+    #[test]
+    fn test_step_back_io_devices() {
+        let code = "mvi a,30h\nout 43h\nmvi a,0f3h\nout 40h\nmvi a,0ffh\nout 40h\n\
+                    nop\nin 20h\nhlt";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new_from(&bin, 0x1000));
+        cpu.add_8254(0x40).unwrap();
+        cpu.add_8255(0x20).unwrap();
+        cpu.write_io(0x23, 0xb0); // port A strobed input
+        cpu.set_8255_input(0x20, 0, 0x42);
+        cpu.strobe_8255(0x20, 0);
+        cpu.set_history_depth(100);
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+
+        // the counter is changed by the clock only
+        let count = |cpu: &PP8085| {
+            cpu.system_bus()
+                .unwrap()
+                .io_device::<I8254>(0x40)
+                .unwrap()
+                .count(0)
+        };
+        let before = count(&cpu);
+        cpu.step().unwrap();
+        assert_eq!(count(&cpu), before - 4);
+        assert!(cpu.step_back());
+        assert_eq!(count(&cpu), before);
+        assert_eq!(cpu.PC, 0x000c);
+        cpu.step().unwrap();
+
+        // IN from the strobed port clears IBF, undone with the step
+        assert_eq!(cpu.bus().io_peek(0x22) & 0x20, 0x20);
+        cpu.step().unwrap();
+        assert_eq!(cpu.A, 0x42);
+        assert_eq!(cpu.bus().io_peek(0x22) & 0x20, 0x00);
+        assert!(cpu.step_back());
+        assert_eq!(cpu.bus().io_peek(0x22) & 0x20, 0x20);
+        assert_eq!(count(&cpu), before - 4);
+    }
+
+    #[test]
+    fn test_step_back_rom_write() {
+        use crate::memory::RegionKind;
+
+        let (bin, _) = crate::asm8085::assemble("sta 0010h\nnop").unwrap();
+        let mut mem = Memory::new_from(&bin, 0x1000);
+        mem.set_region(0x0000, 0x00ff, RegionKind::Rom);
+        mem.set_rom_write_fault(true);
+        let mut cpu = PP8085::new();
+        cpu.load_memory(mem);
+        cpu.set_history_depth(10);
+        assert_eq!(cpu.step(), Err(CpuError::RomWrite { pc: 0, addr: 0x10 }));
+        // undoing the write does not fault again
+        assert!(cpu.step_back());
+        cpu.set_pc(3);
+        assert!(cpu.step().is_ok());
+        assert_eq!(cpu.PC, 4);
+        assert_eq!(cpu.read_memory(0x0010), 0x00);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let code = "lxi sp,1800h\nlxi h,1000h\nmvi m,05h\nloop: mov a,m\nout 05h\npush psw\ndcr m\njnz loop\nhlt\n";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new_from(&bin, 8192));
+        cpu.add_io_port(0x05);
+        cpu.add_io_port(0x06);
+        for _ in 0..12 {
+            cpu.step().unwrap();
+        }
+        let bin_state = cpu.save_state();
+        let json_state = cpu.save_state_json();
+        cpu.run().unwrap();
+
+        for load in [
+            |c: &mut PP8085, b: &[u8], _: &str| c.load_state(b),
+            |c: &mut PP8085, _: &[u8], j: &str| c.load_state_json(j),
+        ] {
+            let mut other = PP8085::new();
+            load(&mut other, &bin_state, &json_state).unwrap();
+            assert_eq!(other.save_state(), bin_state);
+            other.run().unwrap();
+            assert_eq!(other.registers(), cpu.registers());
+            assert_eq!(other.get_total_cycles(), cpu.get_total_cycles());
+            assert_eq!(other.read_memory(0x1000), 0x00);
+            assert_eq!(other.read_memory(0x17f5), cpu.read_memory(0x17f5));
+            assert_eq!(other.get_io_output(0x05), Some(0x01));
+            assert_eq!(other.system_bus().unwrap().io_ports().len(), 2);
+        }
+
+        let mut other = PP8085::new();
+        let mut bad = bin_state.clone();
+        bad[4] = 0xff;
+        assert!(other.load_state(&bad).is_err());
+        assert!(other.load_state(&bin_state[..20]).is_err());
+        let bad = json_state.replace("\"version\": 4", "\"version\": 99");
+        assert!(other.load_state_json(&bad).is_err());
+    }
+
+    #[test]
+    fn test_set_bus() {
+        // the program runs from a flat Memory used as the whole bus
+        let mut cpu = PP8085::new();
+        let mut mem = Memory::new(0x100).unwrap();
+        mem.load(0, &[0x3e, 0x42, 0x32, 0x80, 0x00, 0x76]).unwrap(); // MVI A,42h; STA 80h; HLT
+        cpu.set_bus(Box::new(mem));
+        assert!(cpu.system_bus().is_none());
+        assert!(cpu.add_8255(0x20).is_err());
+        assert!(!cpu.add_io_port(0x10));
+        cpu.run_for_cycles(100).unwrap();
+        assert!(cpu.HLT);
+        assert_eq!(cpu.bus().peek(0x80), 0x42);
+        assert!(cpu.memory_map().is_empty());
+
+        let state = cpu.save_state();
+        cpu.A = 0;
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.A, 0x42);
+    }
+
+    #[test]
+    fn test_load_state_failure() {
+        let mut cpu = PP8085::new();
+        cpu.add_io_port(0x05);
+        cpu.add_8255(0x20).unwrap();
+        cpu.write_memory(0x0100, 0x12);
+        cpu.set_pc(0x0100);
+        let state = cpu.to_save_state();
+
+        let mut other = PP8085::new();
+        other.add_io_port(0x05);
+        other.add_8255(0x20).unwrap();
+        other.write_memory(0x0100, 0x34);
+        other.set_pc(0x0200);
+        let before = other.save_state();
+
+        // the 8255 rejects its data after the memory would have been loaded
+        let mut bad = state.clone();
+        bad.io_devices[0].data.clear();
+        assert!(other.restore_save_state(bad).is_err());
+        assert_eq!(other.save_state(), before);
+
+        // a saved latch overlaps the 8255
+        let mut bad = state.clone();
+        bad.io_ports.push(crate::ioport::IoPort::new(0x21));
+        assert!(other.restore_save_state(bad).is_err());
+        assert_eq!(other.save_state(), before);
+        assert_eq!(other.system_bus().unwrap().io_ports().len(), 1);
+
+        other.restore_save_state(state).unwrap();
+        assert_eq!(other.read_memory(0x0100), 0x12);
+        assert_eq!(other.PC, 0x0100);
+    }
+
+    #[test]
+    fn test_opcode_table() {
+        // every opcode assembles back from its formatted form
+        for op in 0..=0xff_u8 {
+            let info = opcodes::info(op);
+            assert_eq!(info.opcode, op);
+            let bytes = [op, 0x12, 0x34];
+            let bytes = &bytes[..info.length as usize];
+            let (bin, _) = crate::asm8085::assemble(&info.format(bytes)).unwrap();
+            assert_eq!(bin, bytes, "{}", info.format(bytes));
+        }
+
+        // cmp takes a register and cpi one data byte
+        let (bin, _) = crate::asm8085::assemble("cmp b\ncpi 05h\nhlt").unwrap();
+        assert_eq!(bin, vec![0xb8, 0xfe, 0x05, 0x76]);
+        assert!(crate::asm8085::assemble("mov m,m").is_err());
+
+        assert_eq!(opcodes::find("lxi_sp").unwrap().opcode, 0x31);
+        assert_eq!(opcodes::info(0xc2).t_states, (10, 7));
+        assert_eq!(opcodes::info(0x3e).format(&[0x3e, 0x0a]), "mvi a,0ah");
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let code = "start: mvi a,0ah\nloop: dcr a\njnz loop\nrst 1\nsta 2000h\nhlt";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        let ins = disasm8085::decode_slice(&bin, 0, None);
+        let text: Vec<String> = ins.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            text,
+            vec![
+                "mvi a,0ah",
+                "dcr a",
+                "jnz 0002h",
+                "rst 1",
+                "sta 2000h",
+                "hlt"
+            ]
+        );
+        assert_eq!(ins[2].address, 3);
+        assert_eq!(ins[2].bytes, vec![0xc2, 0x02, 0x00]);
+        assert_eq!(ins[2].target, Some(0x0002));
+        assert_eq!(ins[3].target, Some(0x0008));
+        assert_eq!(ins[5].next(), bin.len() as u16);
+
+        // targets with a symbol are written as the symbol
+        let mut symbols = disasm8085::Symbols::new();
+        symbols.insert(0x0002, "loop".to_string());
+        let ins = disasm8085::decode_slice(&bin, 0, Some(&symbols));
+        assert_eq!(ins[1].label.as_deref(), Some("loop"));
+        assert_eq!(ins[2].to_string(), "jnz loop");
+        assert_eq!(ins[4].to_string(), "sta 2000h");
+        assert_eq!(
+            disasm8085::listing(&ins[1..3]),
+            "loop:\n0002  3d         dcr a\n0003  c2 02 00   jnz loop"
+        );
+
+        // an instruction cut short is data
+        let ins = disasm8085::decode_slice(&[0x00, 0xc3, 0x00], 0x100, None);
+        assert_eq!(ins.len(), 2);
+        assert!(ins[1].is_data());
+        assert_eq!(ins[1].to_string(), "db c3h,00h");
+
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new_from(&bin, 0x1000));
+        let ins = disasm8085::decode_range(cpu.bus(), 2, 3, None);
+        assert_eq!(ins.len(), 2);
+        assert_eq!(
+            disasm8085::decode_at(cpu.bus(), 3, None).to_string(),
+            "jnz 0002h"
+        );
+    }
+
+    #[test]
+    fn test_memory_map() {
+        use crate::bus::MemoryDevice;
+
+        // a memory mapped latch
+        struct Latch {
+            value: u8,
+        }
+        impl MemoryDevice for Latch {
+            fn write(&mut self, _offset: u16, data: u8) {
+                self.value = data;
+            }
+            fn peek(&self, _offset: u16) -> u8 {
+                self.value
+            }
+        }
+
+        let mut cpu = PP8085::new();
+        cpu.system_bus_mut().unwrap().unmap_all();
+        let (bin, _) = crate::asm8085::assemble("lda 8000h\nsta 1000h\nsta 0001h\nhlt").unwrap();
+        cpu.map_rom(0x0000, &bin).unwrap();
+        cpu.map_ram(0x1000, 0x1fff).unwrap();
+        cpu.system_bus_mut()
+            .unwrap()
+            .map(0x8000, 0x8000, "latch", Box::new(Latch { value: 0x5a }))
+            .unwrap();
+
+        // overlaps are refused
+        assert!(cpu
+            .system_bus_mut()
+            .unwrap()
+            .map(
+                0x1800,
+                0x27ff,
+                "ram",
+                Box::new(Memory::new(0x1000).unwrap())
+            )
+            .is_err());
+        assert!(cpu
+            .system_bus_mut()
+            .unwrap()
+            .map(0x0000, 0x0000, "ram", Box::new(Memory::new(1).unwrap()))
+            .is_err());
+        assert_eq!(cpu.memory_map().len(), 3);
+        assert_eq!(cpu.memory_map()[1].start, 0x1000);
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.peek_memory(0x1000, 1), vec![0x5a]);
+        // rom is not written
+        assert_eq!(cpu.peek_memory(0x0000, 2), vec![0x3a, 0x00]);
+        let latch = cpu.system_bus_mut().unwrap().unmap(0x8000).unwrap();
+        assert_eq!(latch.peek(0), 0x5a);
+
+        // unmapped addresses go to the default handler
+        assert_eq!(cpu.peek_memory(0x9000, 1), vec![0xff]);
+        cpu.set_out_of_range(OutOfRange::Zero);
+        assert_eq!(cpu.peek_memory(0x9000, 1), vec![0x00]);
+        assert!(cpu.unmap_memory(0x1000));
+        assert!(!cpu.unmap_memory(0x1000));
+    }
+
+    #[test]
+    fn test_trace_code() {
+        let code = "jmp start\n\
+                    db 01h,02h,03h\n\
+                    start: lxi h,table\n\
+                    call sum\n\
+                    sta 0020h\n\
+                    hlt\n\
+                    sum: mov a,m\n\
+                    inx h\n\
+                    add m\n\
+                    rz\n\
+                    jmp 0008h\n\
+                    table: db 0c3h,0ffh,10h,00h,00h,00h,00h,00h,00h,55h";
+        let (bin, _) = crate::asm8085::assemble(code).unwrap();
+        assert_eq!(&bin[3..6], &[1, 2, 3]);
+
+        let ins = disasm8085::trace_code(&bin, &[0], false);
+        let src = disasm8085::source(&ins);
+        assert!(src.starts_with("sub_0000:\n\tjmp loc_0006\n\tdb 01h,02h,03h\nloc_0006:\n"));
+        assert!(src.contains("\tcall sub_0010\n\tsta dat_0020\n"));
+        // jumps into the middle of an instruction keep the address
+        assert!(src.contains("\tjmp 0008h\n"));
+        // the table is data, the byte that looks like a jmp is not followed
+        assert!(
+            src.contains("\tdb c3h,ffh,10h,00h,00h,00h,00h,00h\n\tdb 00h\ndat_0020:\n\tdb 55h\n")
+        );
+        let (again, _) = crate::asm8085::assemble(&src).unwrap();
+        assert_eq!(again, bin);
+
+        // a path ends at an undocumented opcode unless they are enabled
+        let bin = vec![
+            0x3a, 0x06, 0x00, 0xc3, 0x09, 0x00, 0x42, 0xc9, 0x00, 0x08, 0xc9,
+        ];
+        let ins = disasm8085::trace_code(&bin, &[0], false);
+        let src = disasm8085::source(&ins);
+        assert_eq!(
+            src,
+            "sub_0000:\n\tlda dat_0006\n\tjmp dat_0009\ndat_0006:\n\tdb 42h,c9h,00h\ndat_0009:\n\tdb 08h,c9h\n"
+        );
+        let (again, _) = crate::asm8085::assemble(&src).unwrap();
+        assert_eq!(again, bin);
+        let ins = disasm8085::trace_code(&bin, &[0], true);
+        assert!(disasm8085::source(&ins).ends_with("loc_0009:\n\tdsub\n\tret\n"));
+    }
+
+    #[test]
+    fn test_run_for_cycles() {
+        let mut cpu = PP8085::new();
+        let mut rom = Memory::new(8192).unwrap();
+        rom.write(0x0000, 0x0e); // mvi c 0a
+        rom.write(0x0001, 0x0a);
+        rom.write(0x0002, 0x0d); // dcr c
+        rom.write(0x0003, 0xc2); // jnz 0002
+        rom.write(0x0004, 0x02);
+        rom.write(0x0005, 0x00);
+        rom.write(0x0006, 0x76); // hlt
+        cpu.load_memory(rom);
+        cpu.run().unwrap();
+        // 7 + 10 * 4 + 9 * 10 + 7 + 5
+        assert_eq!(cpu.get_total_cycles(), 149);
+        assert_eq!(cpu.get_elapsed_ns(), 149 * 1_000_000_000 / 3_072_000);
+
+        cpu.reset();
+        assert_eq!(cpu.run_for_cycles(20).unwrap(), 21);
+        assert_eq!(cpu.PC, 0x0002);
+
+        // halted cpu keeps counting cycles
+        cpu.reset();
+        assert!(cpu.set_clock_hz(0).is_err());
+        assert_eq!(cpu.get_clock_hz(), DEFAULT_CLOCK_HZ);
+        cpu.set_clock_hz(1_000_000).unwrap();
+        cpu.run_for_duration(1_000_000).unwrap();
+        assert!(cpu.get_hlt());
+        assert_eq!(cpu.get_total_cycles(), 1000);
+    }
+
+    #[test]
+    fn test_bus_mode() {
+        let mut cpu = PP8085::new();
+        cpu.SP = 0x1000;
+        cpu.PC = 0x0100;
+        cpu.write_memory(0x0100, 0xc5); // push b
+        cpu.write_memory(0x0101, 0xd3); // out 05
+        cpu.write_memory(0x0102, 0x05);
+        cpu.write_memory(0x0103, 0x09); // dad b
+        cpu.B = 0x12;
+        cpu.C = 0x34;
+        cpu.A = 0x99;
+        cpu.set_bus_mode(true);
+
+        cpu.step().unwrap();
+        let kinds: Vec<(MachineCycleKind, u16, u8, u8)> = cpu
+            .machine_cycles()
+            .iter()
+            .map(|c| (c.kind, c.address, c.data, c.t_states))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (MachineCycleKind::OpcodeFetch, 0x0100, 0xc5, 6),
+                (MachineCycleKind::MemoryWrite, 0x0fff, 0x12, 3),
+                (MachineCycleKind::MemoryWrite, 0x0ffe, 0x34, 3),
+            ]
+        );
+        let trace = cpu.take_bus_trace();
+        assert_eq!(trace.len(), 12);
+        assert!(trace[0].ale && !trace[0].io_m && trace[0].s1 && trace[0].s0);
+        assert_eq!(trace[0].ad, Some(0x00));
+        assert!(!trace[1].rd && trace[1].wr && !trace[1].ale);
+        assert!(trace[4].rd && trace[4].ad.is_none());
+        assert!(!trace[7].wr && trace[7].ad == Some(0x12));
+
+        cpu.step().unwrap();
+        let out = cpu.machine_cycles()[2];
+        assert_eq!(out.kind, MachineCycleKind::IoWrite);
+        assert_eq!(out.address, 0x0505);
+        assert_eq!(out.data, 0x99);
+        assert!(out.status() == (true, false, true));
+
+        cpu.step().unwrap();
+        let kinds: Vec<MachineCycleKind> = cpu.machine_cycles().iter().map(|c| c.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                MachineCycleKind::OpcodeFetch,
+                MachineCycleKind::BusIdle,
+                MachineCycleKind::BusIdle
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bus_mode_branch_not_taken() {
+        let mut cpu = PP8085::new();
+        cpu.write_memory(0x0000, 0xca); // jz 1234
+        cpu.write_memory(0x0001, 0x34);
+        cpu.write_memory(0x0002, 0x12);
+        cpu.set_bus_mode(true);
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(0));
+        let s = seen.clone();
+        cpu.set_bus_callback(Some(Box::new(move |_| *s.lock().unwrap() += 1)));
+        cpu.step().unwrap();
+        assert_eq!(cpu.machine_cycles().len(), 2);
+        assert_eq!(*seen.lock().unwrap(), 7);
+        assert!(cpu.take_bus_trace().is_empty());
     }
 
     #[test]
     fn test_memory_run() {
         let mut cpu = PP8085::new();
-        let mut rom = Memory::new(8192);
+        let mut rom = Memory::new(8192).unwrap();
         rom.write(0x0000, 0x3e); // mvi a
         rom.write(0x0001, 0x44);
         rom.write(0x0002, 0x16); // mvi d
@@ -2422,8 +5477,8 @@ mod tests {
         rom.write(0x0007, 0x76); // hlt
         cpu.load_memory(rom);
         cpu.add_io_port(0xdf);
-        cpu.run();
+        cpu.run().unwrap();
         cpu.display();
-        println!("{:#02x}", cpu.read_io(0xdf));
+        println!("{:#02x}", cpu.get_io_output(0xdf).unwrap());
     }
 }
@@ -0,0 +1,313 @@
+use super::bus::Bus;
+use super::opcodes::{self, OpcodeInfo};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+/// Names of addresses, used in place of the address operands they name.
+pub type Symbols = HashMap<u16, String>;
+
+/// One decoded instruction.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    /// "db" for bytes that do not make a whole instruction.
+    pub mnemonic: &'static str,
+    /// operands in assembler syntax, resolved targets are written as their symbol.
+    pub operands: String,
+    /// address a jump, call, restart or direct load/store refers to.
+    pub target: Option<u16>,
+    /// symbol of `address`, if any.
+    pub label: Option<String>,
+}
+
+impl Instruction {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn is_data(&self) -> bool {
+        self.mnemonic == "db"
+    }
+
+    pub fn info(&self) -> Option<&'static OpcodeInfo> {
+        match self.is_data() {
+            true => None,
+            false => Some(opcodes::info(self.bytes[0])),
+        }
+    }
+
+    /// address of the next instruction.
+    pub fn next(&self) -> u16 {
+        self.address.wrapping_add(self.bytes.len() as u16)
+    }
+
+    /// one listing line, "0003  c3 00 00   jmp start", after a "label:" line.
+    pub fn listing(&self) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let line = format!("{:04x}  {:<9}  {}", self.address, bytes.join(" "), self);
+        match &self.label {
+            Some(l) => format!("{}:\n{}", l, line),
+            None => line,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.operands.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operands)
+        }
+    }
+}
+
+fn data(bytes: &[u8], address: u16, symbols: Option<&Symbols>) -> Instruction {
+    let operands: Vec<String> = bytes.iter().map(|b| format!("{:02x}h", b)).collect();
+    Instruction {
+        address,
+        bytes: bytes.to_vec(),
+        mnemonic: "db",
+        operands: operands.join(","),
+        target: None,
+        label: symbols.and_then(|s| s.get(&address)).cloned(),
+    }
+}
+
+/// decode the instruction at the start of `bytes`, which is at `address`.
+/// an instruction cut short by the end of `bytes` is returned as data.
+pub fn decode(bytes: &[u8], address: u16, symbols: Option<&Symbols>) -> Option<Instruction> {
+    let info = opcodes::info(*bytes.first()?);
+    let len = info.length as usize;
+    if bytes.len() < len {
+        return Some(data(bytes, address, symbols));
+    }
+    let bytes = &bytes[..len];
+
+    let target = if info.operands.ends_with("a16") {
+        Some((bytes[2] as u16) << 8 | bytes[1] as u16)
+    } else if info.mnemonic == "rst" {
+        Some((info.opcode & 0x38) as u16)
+    } else if info.mnemonic == "rstv" {
+        Some(0x0040)
+    } else {
+        None
+    };
+    let name = target.and_then(|t| symbols.and_then(|s| s.get(&t)));
+    let operands = match name {
+        Some(name) if info.operands.ends_with("a16") => {
+            format!("{}{}", info.operands.trim_end_matches("a16"), name)
+        }
+        _ => info.format_operands(bytes),
+    };
+
+    Some(Instruction {
+        address,
+        bytes: bytes.to_vec(),
+        mnemonic: info.mnemonic,
+        operands,
+        target,
+        label: symbols.and_then(|s| s.get(&address)).cloned(),
+    })
+}
+
+/// decode the instruction at `address` in memory, read without side effects.
+pub fn decode_at<B: Bus + ?Sized>(
+    memory: &B,
+    address: u16,
+    symbols: Option<&Symbols>,
+) -> Instruction {
+    let bytes: Vec<u8> = (0..3)
+        .map(|i| memory.peek(address.wrapping_add(i)))
+        .collect();
+    decode(&bytes, address, symbols).unwrap()
+}
+
+/// decode `bytes` one instruction after the other, the first is at `origin`.
+pub fn decode_slice(bytes: &[u8], origin: u16, symbols: Option<&Symbols>) -> Vec<Instruction> {
+    let mut res = Vec::new();
+    let mut i = 0;
+    while let Some(ins) = decode(&bytes[i..], origin.wrapping_add(i as u16), symbols) {
+        i += ins.len();
+        res.push(ins);
+    }
+    res
+}
+
+/// decode the instructions that start in `start..=end` of memory.
+/// the last one may run past `end`.
+pub fn decode_range<B: Bus + ?Sized>(
+    memory: &B,
+    start: u16,
+    end: u16,
+    symbols: Option<&Symbols>,
+) -> Vec<Instruction> {
+    let mut res = Vec::new();
+    let mut addr = start as u32;
+    while addr <= end as u32 {
+        let ins = decode_at(memory, addr as u16, symbols);
+        addr += ins.len() as u32;
+        res.push(ins);
+    }
+    res
+}
+
+/// listing of the instructions, one per line.
+pub fn listing(instructions: &[Instruction]) -> String {
+    let lines: Vec<String> = instructions.iter().map(|i| i.listing()).collect();
+    lines.join("\n")
+}
+
+/// Where execution can go after an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    /// on to the next instruction.
+    Next,
+    /// to the target only, JMP.
+    Jump(u16),
+    /// to the target or on to the next instruction, conditional jumps.
+    Branch(u16),
+    /// to the target, then back to the next instruction. CALL, Ccc and restarts.
+    Call(u16),
+    /// nowhere that can be known, RET and PCHL.
+    Return,
+}
+
+impl Instruction {
+    pub fn flow(&self) -> Flow {
+        let op = match self.info() {
+            Some(info) => info.opcode,
+            None => return Flow::Next,
+        };
+        match (op, self.target) {
+            (0xc3, Some(t)) => Flow::Jump(t),
+            (0xdd, Some(t)) | (0xfd, Some(t)) => Flow::Branch(t), // JNK JK
+            (op, Some(t)) if op & 0xc7 == 0xc2 => Flow::Branch(t),
+            (0xcd, Some(t)) | (0xcb, Some(t)) => Flow::Call(t), // CALL RSTV
+            (op, Some(t)) if op & 0xc7 == 0xc4 || op & 0xc7 == 0xc7 => Flow::Call(t),
+            (0xc9, _) | (0xe9, _) => Flow::Return,
+            _ => Flow::Next,
+        }
+    }
+}
+
+/// reset, RST 0-7, TRAP and RST 5.5-7.5 vectors.
+pub const VECTORS: [u16; 12] = [
+    0x0000, 0x0008, 0x0010, 0x0018, 0x0020, 0x0024, 0x0028, 0x002c, 0x0030, 0x0034, 0x0038, 0x003c,
+];
+
+/// separate the code of a ROM image at address 0 from its data, by following
+/// every path from the `entries`. code is decoded into instructions and the
+/// rest into "db" lines, with labels for the addresses that are referred to.
+/// undocumented opcodes end a path unless `undocumented` is set.
+pub fn trace_code(image: &[u8], entries: &[u16], undocumented: bool) -> Vec<Instruction> {
+    let len = image.len();
+    let mut start = vec![false; len]; // an instruction starts at the byte
+    let mut code = vec![false; len]; // the byte is part of an instruction
+    let mut found = Vec::new();
+    let mut todo: Vec<u16> = entries.to_vec();
+
+    while let Some(mut addr) = todo.pop() {
+        while (addr as usize) < len && !start[addr as usize] {
+            let ins = match decode(&image[addr as usize..], addr, None) {
+                Some(ins) => ins,
+                None => break,
+            };
+            let at = addr as usize..addr as usize + ins.len();
+            let legal = ins.info().is_some_and(|i| undocumented || !i.undocumented);
+            if !legal || code[at.clone()].iter().any(|c| *c) {
+                break;
+            }
+            start[addr as usize] = true;
+            code[at].iter_mut().for_each(|c| *c = true);
+            let (flow, next) = (ins.flow(), ins.next());
+            found.push(ins);
+            match flow {
+                Flow::Next => addr = next,
+                Flow::Branch(t) | Flow::Call(t) => {
+                    todo.push(t);
+                    addr = next;
+                }
+                Flow::Jump(t) => {
+                    todo.push(t);
+                    break;
+                }
+                Flow::Return => break,
+            }
+        }
+    }
+
+    // label the targets that start an instruction or lie in data
+    let mut symbols = Symbols::new();
+    for ins in &found {
+        let t = match ins.target {
+            Some(t) if (t as usize) < len && (start[t as usize] || !code[t as usize]) => t,
+            _ => continue,
+        };
+        let name = match ins.flow() {
+            _ if !code[t as usize] => format!("dat_{:04x}", t),
+            Flow::Call(_) => format!("sub_{:04x}", t),
+            _ if symbols.contains_key(&t) => continue,
+            _ => format!("loc_{:04x}", t),
+        };
+        symbols.insert(t, name);
+    }
+    for &e in entries {
+        if (e as usize) < len && start[e as usize] {
+            symbols.entry(e).or_insert_with(|| format!("sub_{:04x}", e));
+        }
+    }
+
+    let mut res = Vec::new();
+    let mut addr = 0;
+    while addr < len {
+        if start[addr] {
+            let ins = decode(&image[addr..], addr as u16, Some(&symbols)).unwrap();
+            addr += ins.len();
+            res.push(ins);
+            continue;
+        }
+        let mut end = addr + 1;
+        while end < len && end - addr < 8 && !start[end] && !symbols.contains_key(&(end as u16)) {
+            end += 1;
+        }
+        res.push(data(&image[addr..end], addr as u16, Some(&symbols)));
+        addr = end;
+    }
+    res
+}
+
+/// assembler source of the instructions, which assembles to their bytes
+/// as long as they are contiguous from address 0.
+pub fn source(instructions: &[Instruction]) -> String {
+    let mut res = String::new();
+    for ins in instructions {
+        if let Some(l) = &ins.label {
+            res.push_str(&format!("{}:\n", l));
+        }
+        res.push_str(&format!("\t{}\n", ins));
+    }
+    res
+}
+
+/// decode a binary loaded at `origin`, for viewing it before it is run.
+#[wasm_bindgen]
+pub fn disassemble_wasm(bin: &[u8], origin: u16) -> JsValue {
+    serde_wasm_bindgen::to_value(&decode_slice(bin, origin, None)).unwrap()
+}
+
+/// assembler source of a ROM image, traced from the reset and interrupt
+/// vectors and from `entries`.
+#[wasm_bindgen]
+pub fn rom_source_wasm(bin: &[u8], entries: &[u16]) -> String {
+    let mut all = VECTORS.to_vec();
+    all.extend_from_slice(entries);
+    source(&trace_code(bin, &all, false))
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+/// Errors that stop the cpu. `pc` is the address of the faulting instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CpuError {
+    IllegalOpcode {
+        pc: u16,
+        opcode: u8,
+    },
+    /// SP wrapped around 0x0000 on a push or pop.
+    StackWrap {
+        pc: u16,
+        sp: u16,
+    },
+    /// access beyond the configured memory size.
+    MemoryOutOfRange {
+        pc: u16,
+        addr: u16,
+    },
+    /// write to memory marked as ROM.
+    RomWrite {
+        pc: u16,
+        addr: u16,
+    },
+}
+
+impl CpuError {
+    pub fn pc(&self) -> u16 {
+        match *self {
+            CpuError::IllegalOpcode { pc, .. } => pc,
+            CpuError::StackWrap { pc, .. } => pc,
+            CpuError::MemoryOutOfRange { pc, .. } => pc,
+            CpuError::RomWrite { pc, .. } => pc,
+        }
+    }
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { pc, opcode } => {
+                write!(f, "illegal opcode at 0x{:04X} (0x{:02X})", pc, opcode)
+            }
+            CpuError::StackWrap { pc, sp } => {
+                write!(f, "stack pointer wrapped to 0x{:04X} at 0x{:04X}", sp, pc)
+            }
+            CpuError::MemoryOutOfRange { pc, addr } => {
+                write!(
+                    f,
+                    "memory access out of range 0x{:04X} at 0x{:04X}",
+                    addr, pc
+                )
+            }
+            CpuError::RomWrite { pc, addr } => {
+                write!(f, "write to ROM 0x{:04X} at 0x{:04X}", addr, pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+impl From<CpuError> for JsValue {
+    fn from(e: CpuError) -> JsValue {
+        JsValue::from(e.to_string())
+    }
+}
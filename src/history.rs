@@ -0,0 +1,118 @@
+use super::interrupt::Interrupts;
+use super::trace::Registers;
+use std::collections::VecDeque;
+
+/// cpu state that an instruction can change, other than memory and I/O.
+#[derive(Clone)]
+pub struct CpuSnapshot {
+    pub regs: Registers,
+    pub ir: u8,
+    pub ie: bool,
+    pub hlt: bool,
+    pub ei_delay: bool,
+    pub interrupts: Interrupts,
+    pub cycles: u32,
+    pub total_cycles: u64,
+    pub sod: bool,
+    pub sod_log_len: usize,
+}
+
+/// Undo record of one step.
+#[derive(Clone)]
+pub struct HistoryEntry {
+    pub cpu: CpuSnapshot,
+    pub mem: Vec<(u16, u8)>, // old value of every byte written, in write order
+    pub io: Vec<(u8, Vec<u8>)>, // saved state of every I/O device the step changed, by its first port
+}
+
+/// Bounded undo journal, the oldest entries are dropped past `depth`.
+/// a depth of zero turns the journal off.
+#[derive(Clone, Default)]
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    depth: usize,
+    open: bool, // a step is being recorded into the last entry
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            entries: VecDeque::new(),
+            depth: 0,
+            open: false,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.entries.len() > depth {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.open = false;
+    }
+
+    /// start recording a step that begins in state `cpu`.
+    pub fn begin(&mut self, cpu: CpuSnapshot) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.entries.len() == self.depth {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(HistoryEntry {
+            cpu,
+            mem: Vec::new(),
+            io: Vec::new(),
+        });
+        self.open = true;
+    }
+
+    pub fn end(&mut self) {
+        self.open = false;
+    }
+
+    pub fn recording(&self) -> bool {
+        self.open
+    }
+
+    pub fn record_mem(&mut self, addr: u16, old: u8) {
+        if let (true, Some(e)) = (self.open, self.entries.back_mut()) {
+            e.mem.push((addr, old));
+        }
+    }
+
+    pub fn record_io(&mut self, port: u8, old: Vec<u8>) {
+        if let (true, Some(e)) = (self.open, self.entries.back_mut()) {
+            e.io.push((port, old));
+        }
+    }
+
+    /// whether the step being recorded saved the I/O device at `start`.
+    pub fn has_io(&self, start: u8) -> bool {
+        match (self.open, self.entries.back()) {
+            (true, Some(e)) => e.io.iter().any(|(p, _)| *p == start),
+            _ => false,
+        }
+    }
+
+    /// take the entry of the last step.
+    pub fn pop(&mut self) -> Option<HistoryEntry> {
+        self.entries.pop_back()
+    }
+}
@@ -0,0 +1,686 @@
+use super::interrupt::Line;
+use super::ioport::IoDevice;
+use serde::{Deserialize, Serialize};
+
+/// ports the 8155 answers to from its I/O base: the command/status register,
+/// ports A, B and C and the low and high bytes of the timer.
+pub const PORTS: u8 = 6;
+
+/// bytes of RAM.
+pub const RAM_SIZE: u16 = 256;
+
+// command register bits
+const CMD_PA_OUT: u8 = 1 << 0;
+const CMD_PB_OUT: u8 = 1 << 1;
+const CMD_PC: u8 = 3 << 2;
+const CMD_IEA: u8 = 1 << 4;
+const CMD_IEB: u8 = 1 << 5;
+
+// port C modes, bits 3-2 of the command
+const ALT1: u8 = 0 << 2; // all input
+const ALT3: u8 = 1 << 2; // PC0-2 control port A, PC3-5 output
+const ALT4: u8 = 2 << 2; // PC0-2 control port A, PC3-5 control port B
+const ALT2: u8 = 3 << 2; // all output
+
+/// Output modes of the timer, bits 7-6 of its high byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimerMode {
+    /// TIMER OUT high for the first half of the count and low for the second.
+    SingleSquareWave,
+    /// the square wave repeated, the count is reloaded at TC.
+    SquareWave,
+    /// TIMER OUT low for one clock at TC.
+    SinglePulse,
+    /// a pulse at every TC, the count is reloaded.
+    Pulses,
+}
+
+impl TimerMode {
+    fn from_bits(bits: u8) -> TimerMode {
+        match bits & 3 {
+            0 => TimerMode::SingleSquareWave,
+            1 => TimerMode::SquareWave,
+            2 => TimerMode::SinglePulse,
+            _ => TimerMode::Pulses,
+        }
+    }
+
+    fn is_continuous(self) -> bool {
+        self == TimerMode::SquareWave || self == TimerMode::Pulses
+    }
+}
+
+/// Handshake of a strobed port.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+struct Handshake {
+    request: bool, // INTR before the INTE gate
+    full: bool,    // BF
+    latch: u8,     // input latched by STB
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct State {
+    command: u8,
+    output: [u8; 3], // latches of ports A, B and C
+    pins: [u8; 3],   // levels the host drives on the port pins
+    a: Handshake,
+    b: Handshake,
+    length: u16, // count length register, the mode in bits 15-14
+    count: u16,  // clocks left to TC
+    start: u16,  // count length of the running count
+    mode: TimerMode,
+    running: bool,
+    reload: bool,     // START while running, load the new count at TC
+    stop_at_tc: bool, // STOP AFTER TC
+    timer_out: bool,  // TIMER OUT pin, active low
+    pulse: bool,      // TIMER OUT is low for this clock only
+    timer_flag: bool, // TIMER bit of the status, set at TC
+    clock_div: u32,   // T-states per TIMER IN clock
+    clock: u32,       // T-states towards the next TIMER IN clock
+}
+
+/// Intel 8155 RAM-I/O-timer. this is its I/O part, mapped at 6 ports from
+/// its base, the 256 bytes of RAM are a `Memory` mapped separately.
+/// the host drives the port pins with `set_input` and `strobe`, and sees the
+/// outputs with `output` and the TIMER OUT, INTR A and INTR B lines.
+pub struct I8155 {
+    state: State,
+    timer_out: Line,
+    intr_a: Line,
+    intr_b: Line,
+}
+
+impl Default for I8155 {
+    fn default() -> I8155 {
+        I8155::new()
+    }
+}
+
+impl I8155 {
+    /// an 8155 after reset, its TIMER IN clocked by every T-state.
+    pub fn new() -> I8155 {
+        I8155 {
+            state: State {
+                command: 0,
+                output: [0; 3],
+                pins: [0; 3],
+                a: Handshake::default(),
+                b: Handshake::default(),
+                length: 0,
+                count: 0,
+                start: 0,
+                mode: TimerMode::SingleSquareWave,
+                running: false,
+                reload: false,
+                stop_at_tc: false,
+                timer_out: true,
+                pulse: false,
+                timer_flag: false,
+                clock_div: 1,
+                clock: 0,
+            },
+            timer_out: Line::new(true),
+            intr_a: Line::new(false),
+            intr_b: Line::new(false),
+        }
+    }
+
+    /// T-states per TIMER IN clock, TIMER IN is often CLK OUT divided down.
+    pub fn set_timer_clock(&mut self, div: u32) {
+        assert!(div > 0);
+        self.state.clock_div = div;
+    }
+
+    /// drive the pins of port 0 (A), 1 (B) or 2 (C, 6 bits). the inputs of a
+    /// strobed port are only taken in by `strobe`.
+    pub fn set_input(&mut self, port: usize, data: u8) {
+        self.state.pins[port] = data;
+    }
+
+    /// what the 8155 drives on the pins of port 0 (A), 1 (B) or 2 (C),
+    /// None if the port is an input. the handshake outputs of port C are
+    /// included, its input bits read 0.
+    pub fn output(&self, port: usize) -> Option<u8> {
+        let s = &self.state;
+        match port {
+            0 if s.command & CMD_PA_OUT != 0 => Some(s.output[0]),
+            1 if s.command & CMD_PB_OUT != 0 => Some(s.output[1]),
+            2 => match s.command & CMD_PC {
+                ALT1 => None,
+                ALT2 => Some(s.output[2] & 0x3f),
+                ALT3 => Some(self.control_a() & 0x03 | s.output[2] & 0x38),
+                _ => Some(self.control_a() & 0x03 | self.control_b() & 0x18),
+            },
+            _ => None,
+        }
+    }
+
+    /// a pulse on STB of port 0 (A) or 1 (B), when the port is strobed. an
+    /// input port latches its pins and an output port takes its data, then
+    /// INTR is raised if it is enabled.
+    pub fn strobe(&mut self, port: usize) {
+        if !self.strobed(port) {
+            return;
+        }
+        let out = self.state.command & (1 << port) != 0;
+        let pins = self.state.pins[port];
+        let hs = self.handshake_mut(port);
+        if out {
+            hs.full = false;
+        } else {
+            hs.latch = pins;
+            hs.full = true;
+        }
+        hs.request = true;
+        self.update_lines();
+    }
+
+    pub fn timer_out(&self) -> bool {
+        self.state.timer_out
+    }
+
+    pub fn timer_running(&self) -> bool {
+        self.state.running
+    }
+
+    /// the status register, read without clearing the TIMER bit.
+    pub fn status(&self) -> u8 {
+        let s = &self.state;
+        let mut res = self.control_a() & 0x03 | self.control_b() & 0x18;
+        if s.command & CMD_IEA != 0 {
+            res |= 1 << 2;
+        }
+        if s.command & CMD_IEB != 0 {
+            res |= 1 << 5;
+        }
+        if s.timer_flag {
+            res |= 1 << 6;
+        }
+        res
+    }
+
+    fn strobed(&self, port: usize) -> bool {
+        matches!(
+            (port, self.state.command & CMD_PC),
+            (0, ALT3) | (0, ALT4) | (1, ALT4)
+        )
+    }
+
+    fn handshake_mut(&mut self, port: usize) -> &mut Handshake {
+        match port {
+            0 => &mut self.state.a,
+            _ => &mut self.state.b,
+        }
+    }
+
+    fn intr(&self, port: usize) -> bool {
+        let (hs, enable) = match port {
+            0 => (&self.state.a, CMD_IEA),
+            _ => (&self.state.b, CMD_IEB),
+        };
+        self.strobed(port) && hs.request && self.state.command & enable != 0
+    }
+
+    /// INTR A, BF A and STB A in bits 0-2.
+    fn control_a(&self) -> u8 {
+        let s = &self.state;
+        self.intr(0) as u8 | (s.a.full as u8) << 1 | s.pins[2] & 0x04
+    }
+
+    /// INTR B, BF B and STB B in bits 3-5.
+    fn control_b(&self) -> u8 {
+        let s = &self.state;
+        (self.intr(1) as u8) << 3 | (s.b.full as u8) << 4 | s.pins[2] & 0x20
+    }
+
+    /// what IN from port 0 (A), 1 (B) or 2 (C) reads.
+    fn read_port(&self, port: usize) -> u8 {
+        let s = &self.state;
+        match port {
+            2 => match s.command & CMD_PC {
+                ALT1 => s.pins[2] & 0x3f,
+                ALT2 => s.output[2] & 0x3f,
+                ALT3 => self.control_a() | s.output[2] & 0x38,
+                _ => self.control_a() | self.control_b(),
+            },
+            _ if s.command & (1 << port) != 0 => s.output[port],
+            0 if self.strobed(0) => s.a.latch,
+            1 if self.strobed(1) => s.b.latch,
+            _ => s.pins[port],
+        }
+    }
+
+    fn write_port(&mut self, port: usize, data: u8) {
+        self.state.output[port] = data;
+        if port < 2 && self.strobed(port) && self.state.command & (1 << port) != 0 {
+            let hs = self.handshake_mut(port);
+            hs.full = true;
+            hs.request = false;
+            self.update_lines();
+        }
+    }
+
+    fn command(&mut self, data: u8) {
+        self.state.command = data & 0x3f;
+        match data >> 6 {
+            1 => self.state.running = false,
+            2 => self.state.stop_at_tc = self.state.running,
+            3 if self.state.running => self.state.reload = true,
+            3 => self.start_timer(),
+            _ => {}
+        }
+        self.update_lines();
+    }
+
+    /// load the count length and mode registers into the counter.
+    fn start_timer(&mut self) {
+        let s = &mut self.state;
+        s.start = (s.length & 0x3fff).max(2);
+        s.mode = TimerMode::from_bits((s.length >> 14) as u8);
+        s.count = s.start;
+        s.running = true;
+        s.reload = false;
+        s.stop_at_tc = false;
+        s.timer_out = true;
+        s.pulse = false;
+        self.timer_out.set(true);
+    }
+
+    /// one TIMER IN clock.
+    fn clock(&mut self) {
+        let s = &mut self.state;
+        if s.pulse {
+            s.pulse = false;
+            s.timer_out = true;
+        }
+        if s.running {
+            s.count -= 1;
+            if s.count == 0 {
+                self.terminal_count();
+            } else if let TimerMode::SingleSquareWave | TimerMode::SquareWave = s.mode {
+                s.timer_out = s.count > s.start / 2;
+            }
+        }
+        self.timer_out.set(self.state.timer_out);
+    }
+
+    fn terminal_count(&mut self) {
+        let s = &mut self.state;
+        s.timer_flag = true;
+        let pulse = !matches!(s.mode, TimerMode::SingleSquareWave | TimerMode::SquareWave);
+        if s.reload {
+            self.start_timer();
+        } else if s.stop_at_tc || !s.mode.is_continuous() {
+            s.running = false;
+            s.stop_at_tc = false;
+        } else {
+            s.count = s.start;
+        }
+        // TIMER OUT of the count that ended
+        self.state.timer_out = !pulse;
+        self.state.pulse = pulse;
+    }
+
+    fn update_lines(&self) {
+        self.intr_a.set(self.intr(0));
+        self.intr_b.set(self.intr(1));
+        self.timer_out.set(self.state.timer_out);
+    }
+}
+
+impl IoDevice for I8155 {
+    fn io_read(&mut self, offset: u8) -> u8 {
+        let data = self.io_peek(offset);
+        match offset {
+            0 => self.state.timer_flag = false,
+            // reading a strobed input empties it
+            1 | 2 if self.strobed(offset as usize - 1) && self.state.command & offset == 0 => {
+                let hs = self.handshake_mut(offset as usize - 1);
+                hs.full = false;
+                hs.request = false;
+                self.update_lines();
+            }
+            _ => {}
+        }
+        data
+    }
+
+    fn io_write(&mut self, offset: u8, data: u8) {
+        match offset {
+            0 => self.command(data),
+            1..=3 => self.write_port(offset as usize - 1, data),
+            4 => self.state.length = self.state.length & 0xff00 | data as u16,
+            5 => self.state.length = (data as u16) << 8 | self.state.length & 0x00ff,
+            _ => {}
+        }
+    }
+
+    fn io_peek(&self, offset: u8) -> u8 {
+        let s = &self.state;
+        match offset {
+            0 => self.status(),
+            1..=3 => self.read_port(offset as usize - 1),
+            // the counter, with the mode in the high byte
+            4 => s.count as u8,
+            5 => (s.count >> 8) as u8 & 0x3f | (s.length >> 8) as u8 & 0xc0,
+            _ => 0,
+        }
+    }
+
+    /// clocks the timer with the T-states divided by the TIMER IN divider.
+    fn tick(&mut self, cycles: u32) {
+        self.state.clock += cycles;
+        while self.state.clock >= self.state.clock_div {
+            self.state.clock -= self.state.clock_div;
+            self.clock();
+        }
+    }
+
+    /// the ports become inputs and the timer stops, its count is kept.
+    fn reset(&mut self) {
+        let s = &mut self.state;
+        s.command = 0;
+        s.a = Handshake::default();
+        s.b = Handshake::default();
+        s.running = false;
+        s.reload = false;
+        s.stop_at_tc = false;
+        s.timer_out = true;
+        s.pulse = false;
+        s.timer_flag = false;
+        self.update_lines();
+    }
+
+    /// "timer out", "intr a" and "intr b".
+    fn line(&self, pin: &str) -> Option<Line> {
+        match pin.to_lowercase().as_ref() {
+            "timer out" => Some(self.timer_out.clone()),
+            "intr a" => Some(self.intr_a.clone()),
+            "intr b" => Some(self.intr_b.clone()),
+            _ => None,
+        }
+    }
+
+    fn save(&self) -> Vec<u8> {
+        bincode::serialize(&self.state).unwrap()
+    }
+
+    fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        self.state =
+            bincode::deserialize(data).map_err(|e| format!("Corrupt 8155 state: {}", e))?;
+        self.update_lines();
+        Ok(())
+    }
+}
+
+// -----------------------TESTS----------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu8085::PP8085;
+    use crate::memory::Memory;
+
+    /// load the count length and mode and START the timer.
+    fn start(d: &mut I8155, length: u16, mode: u8) {
+        d.io_write(4, length as u8);
+        d.io_write(5, mode << 6 | (length >> 8) as u8);
+        d.io_write(0, d.state.command | 0xc0);
+    }
+
+    #[test]
+    fn test_port_modes() {
+        // ALT 1 after reset: every port is an input, port C has 6 bits
+        let mut d = I8155::new();
+        d.set_input(0, 0x12);
+        d.set_input(1, 0x34);
+        d.set_input(2, 0xff);
+        assert_eq!(d.io_read(1), 0x12);
+        assert_eq!(d.io_read(2), 0x34);
+        assert_eq!(d.io_read(3), 0x3f);
+        assert_eq!((d.output(0), d.output(1), d.output(2)), (None, None, None));
+
+        // ALT 2: ports A, B and C are outputs, reads return the latches
+        d.io_write(0, 0x0f);
+        d.io_write(1, 0xa5);
+        d.io_write(2, 0x5a);
+        d.io_write(3, 0xff);
+        assert_eq!(d.output(0), Some(0xa5));
+        assert_eq!(d.output(1), Some(0x5a));
+        assert_eq!(d.output(2), Some(0x3f));
+        assert_eq!(d.io_read(1), 0xa5);
+        assert_eq!(d.io_read(3), 0x3f);
+
+        // port A output, port B input
+        d.io_write(0, 0x01);
+        assert_eq!(d.output(0), Some(0xa5));
+        assert_eq!(d.output(1), None);
+        assert_eq!(d.io_read(2), 0x34);
+
+        // ALT 3: PC0-2 are the handshake of port A, PC3-5 outputs
+        d.io_write(0, 0x04);
+        assert_eq!(d.output(2), Some(0x38));
+        d.set_input(2, 0x04); // STB A
+        assert_eq!(d.io_read(3), 0x3c);
+
+        // ALT 4: PC3-5 are the handshake of port B
+        d.io_write(0, 0x08);
+        assert_eq!(d.output(2), Some(0x00));
+        d.set_input(2, 0x24); // STB A and STB B
+        assert_eq!(d.io_read(3), 0x24);
+
+        // reset makes every port an input again
+        d.reset();
+        assert_eq!(d.output(0), None);
+        assert_eq!(d.io_read(3), 0x24);
+    }
+
+    #[test]
+    fn test_timer_modes() {
+        let mut d = I8155::new();
+        let timer_out = d.line("timer out").unwrap();
+
+        // single square wave: high for the first half, low for the second
+        start(&mut d, 4, 0);
+        d.tick(1);
+        assert!(d.timer_out());
+        d.tick(1);
+        assert!(!d.timer_out());
+        assert!(!timer_out.level());
+        assert_eq!(d.io_peek(4), 2);
+        assert_eq!(d.io_peek(5), 0x00);
+        assert_eq!(d.status() & 0x40, 0);
+        d.tick(2);
+        assert!(d.timer_out());
+        assert!(!d.timer_running());
+        assert_eq!(d.io_read(0) & 0x40, 0x40); // TC, cleared by the read
+        assert_eq!(d.io_read(0) & 0x40, 0);
+
+        // square wave: the count is reloaded at TC, an odd count is high longer
+        start(&mut d, 5, 1);
+        let levels: Vec<bool> = (0..10)
+            .map(|_| {
+                d.tick(1);
+                d.timer_out()
+            })
+            .collect();
+        assert_eq!(
+            levels,
+            [true, true, false, false, true, true, true, false, false, true]
+        );
+        assert!(d.timer_running());
+        assert_eq!(d.io_peek(5), 0x40); // the mode in bits 7-6
+
+        // single pulse: low for one clock at TC, then the timer stops
+        d.io_write(0, 0x40); // STOP
+        start(&mut d, 4, 2);
+        d.tick(3);
+        assert!(d.timer_out());
+        d.tick(1);
+        assert!(!d.timer_out());
+        assert!(!d.timer_running());
+        d.tick(1);
+        assert!(d.timer_out());
+
+        // pulses: low for one clock at every TC
+        start(&mut d, 3, 3);
+        let levels: Vec<bool> = (0..6)
+            .map(|_| {
+                d.tick(1);
+                d.timer_out()
+            })
+            .collect();
+        assert_eq!(levels, [true, true, false, true, true, false]);
+        assert!(d.timer_running());
+
+        // counts below 2 count 2
+        d.io_write(0, 0x40); // STOP
+        start(&mut d, 1, 3);
+        d.tick(1);
+        assert!(d.timer_out());
+        d.tick(1);
+        assert!(!d.timer_out());
+
+        // TIMER IN every 3 T-states
+        d.set_timer_clock(3);
+        d.io_write(0, 0x40); // STOP
+        start(&mut d, 2, 2);
+        d.tick(5);
+        assert!(d.timer_out());
+        d.tick(1);
+        assert!(!d.timer_out());
+    }
+
+    #[test]
+    fn test_timer_commands() {
+        let mut d = I8155::new();
+
+        // STOP stops the count at once, the count is kept
+        start(&mut d, 10, 1);
+        d.tick(3);
+        d.io_write(0, 0x40);
+        assert!(!d.timer_running());
+        d.tick(5);
+        assert_eq!(d.io_peek(4), 7);
+
+        // STOP AFTER TC lets the count reach TC first
+        start(&mut d, 10, 1);
+        d.tick(3);
+        d.io_write(0, 0x80);
+        d.tick(6);
+        assert!(d.timer_running());
+        d.tick(1);
+        assert!(!d.timer_running());
+        assert_eq!(d.status() & 0x40, 0x40);
+
+        // STOP AFTER TC does nothing to a stopped timer
+        d.io_write(0, 0x80);
+        start(&mut d, 4, 1);
+        d.tick(8);
+        assert!(d.timer_running());
+
+        // START while running loads the new count and mode at TC
+        d.io_write(4, 6);
+        d.io_write(5, 0xc0);
+        d.io_write(0, 0xc0);
+        d.tick(3);
+        assert_eq!(d.io_peek(4), 1);
+        d.tick(1);
+        assert_eq!(d.io_peek(4), 6);
+        assert_eq!(d.io_peek(5), 0xc0);
+
+        // reset stops the timer and raises TIMER OUT, the count is kept
+        d.tick(2);
+        d.reset();
+        assert!(!d.timer_running());
+        assert!(d.timer_out());
+        assert_eq!(d.io_peek(4), 4);
+    }
+
+    #[test]
+    fn test_strobed_handshake() {
+        let mut d = I8155::new();
+        let intr_a = d.line("intr a").unwrap();
+        let intr_b = d.line("intr b").unwrap();
+
+        // port A is not strobed in ALT 1, STB is ignored
+        d.set_input(0, 0x77);
+        d.strobe(0);
+        assert_eq!(d.status(), 0x00);
+        assert_eq!(d.io_read(1), 0x77);
+
+        // ALT 4 with INTE A, STB latches the input and sets BF A and INTR A
+        d.io_write(0, 0x18);
+        assert_eq!(d.io_read(1), 0x00);
+        d.strobe(0);
+        d.set_input(0, 0x00);
+        assert!(intr_a.level());
+        assert_eq!(d.status() & 0x07, 0x07); // INTR A, BF A, INTE A
+        assert_eq!(d.io_read(1), 0x77); // the latch, emptied by the read
+        assert!(!intr_a.level());
+        assert_eq!(d.status() & 0x07, 0x04);
+
+        // without INTE A the request waits for it
+        d.io_write(0, 0x08);
+        d.strobe(0);
+        assert!(!intr_a.level());
+        assert_eq!(d.status() & 0x07, 0x02);
+        d.io_write(0, 0x18);
+        assert!(intr_a.level());
+        d.io_read(1);
+
+        // port B output with INTE B, a write fills the buffer, STB empties it
+        d.io_write(0, 0x2a);
+        d.io_write(2, 0x5a);
+        assert_eq!(d.output(1), Some(0x5a));
+        assert_eq!(d.status() & 0x38, 0x30); // BF B, INTE B
+        assert!(!intr_b.level());
+        d.strobe(1);
+        assert_eq!(d.status() & 0x38, 0x28); // INTR B, INTE B
+        assert!(intr_b.level());
+        assert_eq!(d.io_read(3) & 0x18, 0x08); // INTR B on PC3
+        d.io_write(2, 0xa5);
+        assert!(!intr_b.level());
+
+        // reset clears the handshakes
+        d.strobe(1);
+        d.reset();
+        assert!(!intr_b.level());
+        assert_eq!(d.status(), 0x00);
+    }
+
+    #[test]
+    fn test_address_decode() {
+        let mut cpu = PP8085::new();
+        cpu.load_memory(Memory::new(0x1000).unwrap());
+        cpu.add_8155(0x2000, 0x20).unwrap();
+        assert!(cpu.add_8155(0x2100, 0x25).is_err()); // ports overlap
+        assert!(cpu.add_8155(0x2080, 0x30).is_err()); // RAM overlaps
+        assert!(cpu.add_8155(0xff01, 0x30).is_err()); // RAM past 0xFFFF
+        assert!(cpu.add_8155(0x3000, 0xfb).is_err()); // ports past 0xFF
+        let bus = cpu.system_bus_mut().unwrap();
+        assert_eq!(bus.regions().len(), 2);
+        assert_eq!(bus.io_regions().len(), 1);
+
+        // 256 bytes of RAM from the RAM base
+        bus.write(0x2000, 0x11);
+        bus.write(0x20ff, 0x55);
+        assert_eq!((bus.read(0x2000), bus.read(0x20ff)), (0x11, 0x55));
+        assert!(bus.is_mapped(0x20ff));
+        assert!(!bus.is_mapped(0x2100));
+
+        // 6 ports from the I/O base, the 7th is not decoded
+        bus.io_write(0x20, 0x03);
+        bus.io_write(0x21, 0xa5);
+        bus.io_write(0x22, 0x5a);
+        assert_eq!((bus.io_read(0x21), bus.io_read(0x22)), (0xa5, 0x5a));
+        bus.io_write(0x24, 0x34);
+        bus.io_write(0x25, 0x52);
+        bus.io_write(0x20, 0xc3);
+        assert_eq!((bus.io_peek(0x24), bus.io_peek(0x25)), (0x34, 0x52));
+        assert_eq!(bus.io_read(0x26), 0);
+        let d = bus.io_device::<I8155>(0x20).unwrap();
+        assert_eq!(d.output(0), Some(0xa5));
+        assert!(d.timer_running());
+    }
+}
@@ -0,0 +1,651 @@
+use super::interrupt::Line;
+use super::ioport::IoDevice;
+use serde::{Deserialize, Serialize};
+
+/// ports the 8254 answers to from its I/O base: counters 0, 1 and 2 and the
+/// control word register.
+pub const PORTS: u8 = 4;
+
+/// counters of an 8254.
+pub const COUNTERS: usize = 3;
+
+// read/load of a counter, bits 5-4 of the control word
+const RW_LATCH: u8 = 0;
+const RW_LSB: u8 = 1;
+const RW_MSB: u8 = 2;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Counter {
+    mode: u8,
+    rw: u8,    // 1 LSB only, 2 MSB only, 3 LSB then MSB
+    bcd: bool, // counts in 4 decades
+    cr: u16,   // count register, as written
+    ce: u32,   // counting element, 0 is the full count
+    write_msb: bool,
+    read_msb: bool,
+    latch: Option<u16>, // output latch of a counter latch command
+    status: Option<u8>, // status latched by a read-back command
+    out: bool,
+    gate: bool,
+    trigger: bool,    // GATE rose since the last CLK
+    null_count: bool, // the count register is not loaded yet
+    load: bool,       // load the count register at the next CLK
+    counting: bool,
+    armed: bool,    // modes 4 and 5 strobe OUT once per count
+    odd: bool,      // mode 3 count is odd
+    first: bool,    // first CLK of a mode 3 half cycle
+    clock_div: u32, // T-states per CLK
+    clock: u32,     // T-states towards the next CLK
+}
+
+impl Counter {
+    fn new() -> Counter {
+        Counter {
+            mode: 0,
+            rw: 3,
+            bcd: false,
+            cr: 0,
+            ce: 0,
+            write_msb: false,
+            read_msb: false,
+            latch: None,
+            status: None,
+            out: false,
+            gate: true,
+            trigger: false,
+            null_count: true,
+            load: false,
+            counting: false,
+            armed: false,
+            odd: false,
+            first: false,
+            clock_div: 1,
+            clock: 0,
+        }
+    }
+
+    fn modulus(&self) -> u32 {
+        if self.bcd {
+            10000
+        } else {
+            0x10000
+        }
+    }
+
+    /// the count register as a number of clocks, 0 for the full count.
+    fn initial(&self) -> u32 {
+        if self.bcd {
+            let digit = |i: u32| (self.cr as u32 >> (4 * i) & 0xf) % 10;
+            (0..4).map(|i| digit(i) * 10u32.pow(i)).sum()
+        } else {
+            self.cr as u32
+        }
+    }
+
+    /// the counting element as it is read.
+    fn count(&self) -> u16 {
+        if self.bcd {
+            let ce = self.ce % 10000;
+            (0..4)
+                .map(|i| ((ce / 10u32.pow(i) % 10) << (4 * i)) as u16)
+                .sum()
+        } else {
+            self.ce as u16
+        }
+    }
+
+    fn status_byte(&self) -> u8 {
+        (self.out as u8) << 7
+            | (self.null_count as u8) << 6
+            | self.rw << 4
+            | self.mode << 1
+            | self.bcd as u8
+    }
+
+    fn decrement(&mut self, n: u32) {
+        let m = self.modulus();
+        self.ce = (self.ce + m - n) % m;
+    }
+
+    fn reload(&mut self) {
+        self.ce = self.initial();
+        self.null_count = false;
+        self.load = false;
+        self.counting = true;
+    }
+
+    fn control(&mut self, data: u8) {
+        self.rw = (data >> 4) & 3;
+        self.mode = match (data >> 1) & 7 {
+            m @ 0..=5 => m,
+            m => m & 3, // 6 and 7 are modes 2 and 3
+        };
+        self.bcd = data & 1 != 0;
+        self.out = self.mode != 0;
+        self.write_msb = false;
+        self.read_msb = false;
+        self.latch = None;
+        self.status = None;
+        self.null_count = true;
+        self.load = false;
+        self.counting = false;
+        self.armed = false;
+        self.trigger = false;
+    }
+
+    fn write(&mut self, data: u8) {
+        match self.rw {
+            RW_LSB => self.cr = data as u16,
+            RW_MSB => self.cr = (data as u16) << 8,
+            _ if !self.write_msb => {
+                self.cr = self.cr & 0xff00 | data as u16;
+                self.write_msb = true;
+                if self.mode == 0 {
+                    // the first byte stops the count
+                    self.counting = false;
+                    self.out = false;
+                }
+                return;
+            }
+            _ => {
+                self.cr = (data as u16) << 8 | self.cr & 0x00ff;
+                self.write_msb = false;
+            }
+        }
+        self.null_count = true;
+        match self.mode {
+            0 => {
+                self.load = true;
+                self.out = false;
+            }
+            2 | 3 if self.counting => {} // taken at the end of the period
+            1 | 5 => {}                  // taken at the next trigger
+            _ => self.load = true,
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if let Some(s) = self.status.take() {
+            return s;
+        }
+        let count = self.latch.unwrap_or_else(|| self.count());
+        let (data, done) = match self.rw {
+            RW_LSB => (count as u8, true),
+            RW_MSB => ((count >> 8) as u8, true),
+            _ if !self.read_msb => {
+                self.read_msb = true;
+                (count as u8, false)
+            }
+            _ => {
+                self.read_msb = false;
+                ((count >> 8) as u8, true)
+            }
+        };
+        if done {
+            self.latch = None;
+        }
+        data
+    }
+
+    fn peek(&self) -> u8 {
+        if let Some(s) = self.status {
+            return s;
+        }
+        let count = self.latch.unwrap_or_else(|| self.count());
+        match self.rw {
+            RW_MSB => (count >> 8) as u8,
+            RW_LSB => count as u8,
+            _ if self.read_msb => (count >> 8) as u8,
+            _ => count as u8,
+        }
+    }
+
+    fn set_gate(&mut self, level: bool) {
+        if level && !self.gate {
+            self.trigger = true;
+        }
+        self.gate = level;
+        if !level && (self.mode == 2 || self.mode == 3) {
+            self.out = true;
+        }
+    }
+
+    /// one CLK pulse.
+    fn clock(&mut self) {
+        let trigger = self.trigger;
+        self.trigger = false;
+        match self.mode {
+            0 => {
+                if self.load {
+                    self.reload();
+                } else if self.counting && self.gate {
+                    self.decrement(1);
+                    if self.ce == 0 {
+                        self.out = true;
+                    }
+                }
+            }
+            1 => {
+                if trigger {
+                    self.reload();
+                    self.out = false;
+                } else if self.counting {
+                    self.decrement(1);
+                    if self.ce == 0 {
+                        self.out = true;
+                    }
+                }
+            }
+            2 => {
+                if self.load || trigger && self.counting {
+                    self.reload();
+                    self.out = true;
+                } else if self.counting && self.gate {
+                    if !self.out {
+                        self.reload();
+                        self.out = true;
+                    } else {
+                        self.decrement(1);
+                        self.out = self.ce != 1;
+                    }
+                }
+            }
+            3 => {
+                if self.load || trigger && self.counting {
+                    self.reload();
+                    self.out = true;
+                    self.odd = self.ce % 2 == 1;
+                    self.first = true;
+                } else if self.counting && self.gate {
+                    let n = match (self.first && self.odd, self.out) {
+                        (true, true) => 1,
+                        (true, false) => 3,
+                        _ => 2,
+                    };
+                    self.first = false;
+                    self.decrement(n);
+                    if self.ce == 0 {
+                        self.out = !self.out;
+                        self.reload();
+                        self.odd = self.ce % 2 == 1;
+                        self.first = true;
+                    }
+                }
+            }
+            _ => {
+                let start = match self.mode {
+                    4 => self.load,
+                    _ => trigger,
+                };
+                self.out = true;
+                if start {
+                    self.reload();
+                    self.armed = true;
+                } else if self.counting && (self.gate || self.mode == 5) {
+                    self.decrement(1);
+                    if self.ce == 0 && self.armed {
+                        self.out = false;
+                        self.armed = false;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Intel 8254 programmable interval timer, mapped at 4 ports from its base.
+/// the 8253 is the same without the read-back command. every counter is
+/// clocked by the T-states of the cpu, divided by `set_clock`, the host
+/// drives the GATE inputs with `set_gate` and sees OUT with `out` and the
+/// "out0", "out1" and "out2" lines.
+pub struct I8254 {
+    counters: [Counter; COUNTERS],
+    lines: [Line; COUNTERS],
+}
+
+impl Default for I8254 {
+    fn default() -> I8254 {
+        I8254::new()
+    }
+}
+
+impl I8254 {
+    /// an 8254 with its GATE inputs high and every CLK a T-state.
+    pub fn new() -> I8254 {
+        I8254 {
+            counters: [Counter::new(), Counter::new(), Counter::new()],
+            lines: [Line::new(false), Line::new(false), Line::new(false)],
+        }
+    }
+
+    /// T-states per CLK of `counter`, CLK is often CLK OUT divided down.
+    pub fn set_clock(&mut self, counter: usize, div: u32) {
+        assert!(div > 0);
+        self.counters[counter].clock_div = div;
+    }
+
+    /// drive GATE of `counter`.
+    pub fn set_gate(&mut self, counter: usize, level: bool) {
+        self.counters[counter].set_gate(level);
+        self.update_lines();
+    }
+
+    pub fn gate(&self, counter: usize) -> bool {
+        self.counters[counter].gate
+    }
+
+    /// the OUT pin of `counter`.
+    pub fn out(&self, counter: usize) -> bool {
+        self.counters[counter].out
+    }
+
+    /// the counting element of `counter`, in BCD if it counts in BCD.
+    pub fn count(&self, counter: usize) -> u16 {
+        self.counters[counter].count()
+    }
+
+    fn control(&mut self, data: u8) {
+        let sc = (data >> 6) as usize;
+        if sc < COUNTERS {
+            let c = &mut self.counters[sc];
+            match (data >> 4) & 3 {
+                RW_LATCH if c.latch.is_none() => c.latch = Some(c.count()),
+                RW_LATCH => {}
+                _ => c.control(data),
+            }
+            return;
+        }
+        // read-back, COUNT and STATUS are active low
+        for (i, c) in self.counters.iter_mut().enumerate() {
+            if data & (2 << i) == 0 {
+                continue;
+            }
+            if data & 0x20 == 0 && c.latch.is_none() {
+                c.latch = Some(c.count());
+            }
+            if data & 0x10 == 0 && c.status.is_none() {
+                c.status = Some(c.status_byte());
+            }
+        }
+    }
+
+    fn update_lines(&self) {
+        for (c, l) in self.counters.iter().zip(self.lines.iter()) {
+            l.set(c.out);
+        }
+    }
+}
+
+impl IoDevice for I8254 {
+    fn io_read(&mut self, offset: u8) -> u8 {
+        match offset {
+            0..=2 => self.counters[offset as usize].read(),
+            _ => 0,
+        }
+    }
+
+    fn io_write(&mut self, offset: u8, data: u8) {
+        match offset {
+            0..=2 => self.counters[offset as usize].write(data),
+            _ => self.control(data),
+        }
+        self.update_lines();
+    }
+
+    /// the control word register cannot be read, it reads 0.
+    fn io_peek(&self, offset: u8) -> u8 {
+        match offset {
+            0..=2 => self.counters[offset as usize].peek(),
+            _ => 0,
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        for (c, l) in self.counters.iter_mut().zip(self.lines.iter()) {
+            c.clock += cycles;
+            while c.clock >= c.clock_div {
+                c.clock -= c.clock_div;
+                c.clock();
+                l.set(c.out);
+            }
+        }
+    }
+
+    /// "out0", "out1" and "out2".
+    fn line(&self, pin: &str) -> Option<Line> {
+        match pin.to_lowercase().as_ref() {
+            "out0" => Some(self.lines[0].clone()),
+            "out1" => Some(self.lines[1].clone()),
+            "out2" => Some(self.lines[2].clone()),
+            _ => None,
+        }
+    }
+
+    fn save(&self) -> Vec<u8> {
+        bincode::serialize(&self.counters).unwrap()
+    }
+
+    fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        self.counters =
+            bincode::deserialize(data).map_err(|e| format!("Corrupt 8254 state: {}", e))?;
+        self.update_lines();
+        Ok(())
+    }
+}
+
+// -----------------------TESTS----------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// binary count, LSB then MSB.
+    fn program(d: &mut I8254, counter: u8, mode: u8, count: u16) {
+        d.io_write(3, counter << 6 | 0x30 | mode << 1);
+        d.io_write(counter, count as u8);
+        d.io_write(counter, (count >> 8) as u8);
+    }
+
+    /// OUT of `counter` after each of `n` clocks, H or L.
+    fn levels(d: &mut I8254, counter: usize, n: usize) -> String {
+        (0..n)
+            .map(|_| {
+                d.tick(1);
+                if d.out(counter) {
+                    'H'
+                } else {
+                    'L'
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mode0() {
+        // OUT goes low with the count and high N clocks after it is loaded
+        let mut d = I8254::new();
+        let out0 = d.line("out0").unwrap();
+        program(&mut d, 0, 0, 3);
+        assert!(!out0.level());
+        assert_eq!(levels(&mut d, 0, 6), "LLLHHH");
+        assert!(out0.level());
+
+        // a low GATE holds the count
+        program(&mut d, 0, 0, 3);
+        d.tick(1);
+        d.set_gate(0, false);
+        assert_eq!(levels(&mut d, 0, 4), "LLLL");
+        d.set_gate(0, true);
+        assert_eq!(levels(&mut d, 0, 3), "LLH");
+
+        // the first byte of a new count stops the counter
+        program(&mut d, 0, 0, 3);
+        d.tick(2);
+        d.io_write(0, 5);
+        assert_eq!(levels(&mut d, 0, 3), "LLL");
+        d.io_write(0, 0);
+        assert_eq!(levels(&mut d, 0, 6), "LLLLLH");
+    }
+
+    #[test]
+    fn test_mode1() {
+        // OUT goes low at the clock after the GATE trigger, for N clocks
+        let mut d = I8254::new();
+        program(&mut d, 1, 1, 3);
+        assert_eq!(levels(&mut d, 1, 3), "HHH");
+        d.set_gate(1, false);
+        d.set_gate(1, true);
+        assert_eq!(levels(&mut d, 1, 5), "LLLHH");
+
+        // a trigger during the pulse restarts it
+        d.set_gate(1, false);
+        d.set_gate(1, true);
+        d.tick(2);
+        d.set_gate(1, false);
+        d.set_gate(1, true);
+        assert_eq!(levels(&mut d, 1, 5), "LLLHH");
+
+        // a new count is only taken at the next trigger
+        d.io_write(1, 5);
+        d.io_write(1, 0);
+        assert_eq!(levels(&mut d, 1, 2), "HH");
+        d.set_gate(1, false);
+        d.set_gate(1, true);
+        assert_eq!(levels(&mut d, 1, 6), "LLLLLH");
+    }
+
+    #[test]
+    fn test_mode2() {
+        // rate generator, OUT low for one clock every N clocks
+        let mut d = I8254::new();
+        program(&mut d, 2, 2, 3);
+        assert!(d.out(2));
+        assert_eq!(levels(&mut d, 2, 9), "HHLHHLHHL");
+
+        // GATE low forces OUT high and stops the count, its rise reloads
+        d.tick(1);
+        d.set_gate(2, false);
+        assert!(d.out(2));
+        assert_eq!(levels(&mut d, 2, 3), "HHH");
+        d.set_gate(2, true);
+        assert_eq!(levels(&mut d, 2, 6), "HHLHHL");
+
+        // a new count is taken at the end of the period
+        d.io_write(2, 4);
+        d.io_write(2, 0);
+        assert_eq!(levels(&mut d, 2, 11), "HHHLHHHLHHH");
+    }
+
+    #[test]
+    fn test_mode3() {
+        // square wave, an even count is high and low for N/2 clocks
+        let mut d = I8254::new();
+        program(&mut d, 0, 3, 4);
+        assert_eq!(levels(&mut d, 0, 9), "HHLLHHLLH");
+
+        // an odd count is high for (N+1)/2 clocks and low for (N-1)/2
+        program(&mut d, 0, 3, 5);
+        assert_eq!(levels(&mut d, 0, 11), "HHHLLHHHLLH");
+        program(&mut d, 0, 3, 3);
+        assert_eq!(levels(&mut d, 0, 7), "HHLHHLH");
+
+        // GATE low forces OUT high, its rise restarts the half cycle
+        program(&mut d, 0, 3, 4);
+        d.tick(3);
+        assert!(!d.out(0));
+        d.set_gate(0, false);
+        assert!(d.out(0));
+        assert_eq!(levels(&mut d, 0, 2), "HH");
+        d.set_gate(0, true);
+        assert_eq!(levels(&mut d, 0, 5), "HHLLH");
+    }
+
+    #[test]
+    fn test_mode4() {
+        // software strobe, OUT low for one clock N clocks after the load
+        let mut d = I8254::new();
+        program(&mut d, 1, 4, 3);
+        assert_eq!(levels(&mut d, 1, 7), "HHHLHHH");
+
+        // a low GATE holds the count
+        program(&mut d, 1, 4, 3);
+        d.tick(1);
+        d.set_gate(1, false);
+        assert_eq!(levels(&mut d, 1, 3), "HHH");
+        d.set_gate(1, true);
+        assert_eq!(levels(&mut d, 1, 4), "HHLH");
+    }
+
+    #[test]
+    fn test_mode5() {
+        // hardware strobe, OUT low for one clock N clocks after the trigger
+        let mut d = I8254::new();
+        program(&mut d, 2, 5, 3);
+        assert_eq!(levels(&mut d, 2, 3), "HHH");
+        d.set_gate(2, false);
+        d.set_gate(2, true);
+        assert_eq!(levels(&mut d, 2, 6), "HHHLHH");
+
+        // a trigger before the strobe restarts the count
+        d.set_gate(2, false);
+        d.set_gate(2, true);
+        d.tick(2);
+        d.set_gate(2, false);
+        d.set_gate(2, true);
+        assert_eq!(levels(&mut d, 2, 6), "HHHLHH");
+    }
+
+    #[test]
+    fn test_bcd() {
+        // the count wraps from 0 to 9999 in BCD and to 0xFFFF in binary
+        let mut d = I8254::new();
+        d.io_write(3, 0x31); // counter 0, mode 0, BCD
+        d.io_write(0, 0x01);
+        d.io_write(0, 0x00);
+        d.tick(2);
+        assert!(d.out(0));
+        assert_eq!(d.count(0), 0x0000);
+        d.tick(1);
+        assert_eq!(d.count(0), 0x9999);
+        assert_eq!((d.io_read(0), d.io_read(0)), (0x99, 0x99));
+
+        program(&mut d, 1, 0, 1);
+        d.tick(3);
+        assert_eq!(d.count(1), 0xffff);
+
+        // a BCD count of 0 is 10000 clocks, digits above 9 are not BCD
+        d.io_write(3, 0x31);
+        d.io_write(0, 0x00);
+        d.io_write(0, 0x00);
+        d.tick(1);
+        assert_eq!(d.count(0), 0x0000);
+        d.tick(1);
+        assert_eq!(d.count(0), 0x9999);
+        d.tick(9998);
+        assert!(!d.out(0));
+        d.tick(1);
+        assert!(d.out(0));
+        d.io_write(3, 0x31);
+        d.io_write(0, 0x10);
+        d.io_write(0, 0x00);
+        d.tick(1);
+        assert_eq!(d.count(0), 0x0010);
+        d.tick(1);
+        assert_eq!(d.count(0), 0x0009);
+    }
+
+    #[test]
+    fn test_latch_and_read_back() {
+        let mut d = I8254::new();
+        program(&mut d, 0, 2, 0x1234);
+        d.tick(3);
+        d.io_write(3, 0x00); // counter latch
+        d.tick(2);
+        assert_eq!((d.io_read(0), d.io_read(0)), (0x32, 0x12));
+        assert_eq!((d.io_read(0), d.io_read(0)), (0x30, 0x12));
+
+        // read-back of the status and count of counter 0
+        d.io_write(3, 0xc2);
+        assert_eq!(d.io_read(0), 0x80 | 0x30 | 2 << 1); // OUT high, mode 2
+        assert_eq!((d.io_read(0), d.io_read(0)), (0x30, 0x12));
+    }
+}
@@ -0,0 +1,512 @@
+use super::interrupt::Line;
+use super::ioport::IoDevice;
+use serde::{Deserialize, Serialize};
+
+/// ports the 8255 answers to from its I/O base: ports A, B and C and the
+/// control word register.
+pub const PORTS: u8 = 4;
+
+// mode definition word bits
+const MODE_SET: u8 = 1 << 7;
+const PC_LOWER_IN: u8 = 1 << 0;
+const PB_IN: u8 = 1 << 1;
+const PB_MODE1: u8 = 1 << 2;
+const PC_UPPER_IN: u8 = 1 << 3;
+const PA_IN: u8 = 1 << 4;
+
+// port C bits of the handshakes
+const INTR_B: u8 = 1 << 0;
+const IBF_B: u8 = 1 << 1; // OBF B in output mode
+const INTE_B: u8 = 1 << 2; // STB B or ACK B
+const INTR_A: u8 = 1 << 3;
+const INTE_A_IN: u8 = 1 << 4; // STB A
+const IBF_A: u8 = 1 << 5;
+const INTE_A_OUT: u8 = 1 << 6; // ACK A
+const OBF_A: u8 = 1 << 7;
+
+/// Handshake of port A or B in mode 1 or 2.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+struct Handshake {
+    latch: u8,         // input latched by STB
+    ibf: bool,         // input buffer full
+    obf: bool,         // output buffer full, the OBF pin is low
+    in_request: bool,  // INTR for the input before INTE
+    out_request: bool, // INTR for the output before INTE
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct State {
+    control: u8,     // the last mode definition word
+    output: [u8; 3], // latches of ports A, B and C
+    pins: [u8; 3],   // levels the host drives on the port pins
+    a: Handshake,
+    b: Handshake,
+}
+
+/// Intel 8255 programmable peripheral interface, mapped at 4 ports from its
+/// base. the host drives the port pins with `set_input`, `strobe` and
+/// `acknowledge`, and sees the outputs with `output` and the INTR A and
+/// INTR B lines.
+pub struct I8255 {
+    state: State,
+    intr_a: Line,
+    intr_b: Line,
+}
+
+impl Default for I8255 {
+    fn default() -> I8255 {
+        I8255::new()
+    }
+}
+
+impl I8255 {
+    /// an 8255 after reset, every port an input in mode 0.
+    pub fn new() -> I8255 {
+        I8255 {
+            state: State {
+                control: 0x9b,
+                output: [0; 3],
+                pins: [0; 3],
+                a: Handshake::default(),
+                b: Handshake::default(),
+            },
+            intr_a: Line::new(false),
+            intr_b: Line::new(false),
+        }
+    }
+
+    /// mode of group A (port A and PC3-7): 0, 1 or 2.
+    pub fn mode_a(&self) -> u8 {
+        match (self.state.control >> 5) & 3 {
+            0 => 0,
+            1 => 1,
+            _ => 2,
+        }
+    }
+
+    /// mode of group B (port B and PC0-2): 0 or 1.
+    pub fn mode_b(&self) -> u8 {
+        (self.state.control & PB_MODE1 != 0) as u8
+    }
+
+    /// drive the pins of port 0 (A), 1 (B) or 2 (C). the inputs of a strobed
+    /// port are only taken in by `strobe`, STB and ACK are pulsed with
+    /// `strobe` and `acknowledge` rather than driven on port C.
+    pub fn set_input(&mut self, port: usize, data: u8) {
+        self.state.pins[port] = data;
+    }
+
+    /// what the 8255 drives on the pins of port 0 (A), 1 (B) or 2 (C), None
+    /// if the port is an input. the handshake outputs of port C are included,
+    /// its input bits read 0. in mode 2 port A is only driven while ACK is
+    /// low, its latch is returned.
+    pub fn output(&self, port: usize) -> Option<u8> {
+        let s = &self.state;
+        match port {
+            0 if self.mode_a() == 2 || s.control & PA_IN == 0 => Some(s.output[0]),
+            1 if s.control & PB_IN == 0 => Some(s.output[1]),
+            2 => {
+                let mask = self.c_output_mask();
+                let handshake = self.c_handshake() & self.c_handshake_outputs();
+                match mask | self.c_handshake_outputs() {
+                    0 => None,
+                    _ => Some(s.output[2] & mask | handshake),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// a pulse on STB of port 0 (A) or 1 (B), when the port is a strobed
+    /// input. the pins are latched, IBF is set and INTR raised if enabled.
+    pub fn strobe(&mut self, port: usize) {
+        if !self.strobed_input(port) {
+            return;
+        }
+        let pins = self.state.pins[port];
+        let hs = self.handshake_mut(port);
+        hs.latch = pins;
+        hs.ibf = true;
+        hs.in_request = true;
+        self.update_lines();
+    }
+
+    /// a pulse on ACK of port 0 (A) or 1 (B), when the port is a strobed
+    /// output. OBF is cleared and INTR raised if enabled.
+    pub fn acknowledge(&mut self, port: usize) {
+        if !self.strobed_output(port) {
+            return;
+        }
+        let hs = self.handshake_mut(port);
+        hs.obf = false;
+        hs.out_request = true;
+        self.update_lines();
+    }
+
+    fn strobed_input(&self, port: usize) -> bool {
+        match port {
+            0 => self.mode_a() == 2 || self.mode_a() == 1 && self.state.control & PA_IN != 0,
+            1 => self.mode_b() == 1 && self.state.control & PB_IN != 0,
+            _ => false,
+        }
+    }
+
+    fn strobed_output(&self, port: usize) -> bool {
+        match port {
+            0 => self.mode_a() == 2 || self.mode_a() == 1 && self.state.control & PA_IN == 0,
+            1 => self.mode_b() == 1 && self.state.control & PB_IN == 0,
+            _ => false,
+        }
+    }
+
+    fn handshake_mut(&mut self, port: usize) -> &mut Handshake {
+        match port {
+            0 => &mut self.state.a,
+            _ => &mut self.state.b,
+        }
+    }
+
+    /// bits of port C used by the handshakes.
+    fn c_handshake_bits(&self) -> u8 {
+        let a = match self.mode_a() {
+            0 => 0,
+            2 => INTR_A | INTE_A_IN | IBF_A | INTE_A_OUT | OBF_A,
+            _ if self.state.control & PA_IN != 0 => INTR_A | INTE_A_IN | IBF_A,
+            _ => INTR_A | INTE_A_OUT | OBF_A,
+        };
+        let b = match self.mode_b() {
+            0 => 0,
+            _ => INTR_B | IBF_B | INTE_B,
+        };
+        a | b
+    }
+
+    /// handshake bits of port C that are outputs, the others are STB and ACK.
+    fn c_handshake_outputs(&self) -> u8 {
+        self.c_handshake_bits() & (INTR_A | IBF_A | OBF_A | INTR_B | IBF_B)
+    }
+
+    /// the plain I/O bits of port C that are outputs.
+    fn c_output_mask(&self) -> u8 {
+        let mut mask = 0;
+        if self.state.control & PC_UPPER_IN == 0 {
+            mask |= 0xf0;
+        }
+        if self.state.control & PC_LOWER_IN == 0 {
+            mask |= 0x0f;
+        }
+        mask & !self.c_handshake_bits()
+    }
+
+    /// the handshake bits as port C reads them, INTE in place of STB and ACK.
+    fn c_handshake(&self) -> u8 {
+        let s = &self.state;
+        let mut res = s.output[2] & (INTE_A_IN | INTE_A_OUT | INTE_B);
+        if self.intr_a() {
+            res |= INTR_A;
+        }
+        if s.a.ibf {
+            res |= IBF_A;
+        }
+        if !s.a.obf {
+            res |= OBF_A;
+        }
+        if self.intr_b() {
+            res |= INTR_B;
+        }
+        let full = match s.control & PB_IN {
+            0 => !s.b.obf,
+            _ => s.b.ibf,
+        };
+        if full {
+            res |= IBF_B;
+        }
+        res & self.c_handshake_bits()
+    }
+
+    fn intr_a(&self) -> bool {
+        let s = &self.state;
+        let inte_in = s.output[2] & INTE_A_IN != 0;
+        let inte_out = s.output[2] & INTE_A_OUT != 0;
+        match self.mode_a() {
+            0 => false,
+            2 => s.a.in_request && inte_in || s.a.out_request && inte_out,
+            _ if s.control & PA_IN != 0 => s.a.in_request && inte_in,
+            _ => s.a.out_request && inte_out,
+        }
+    }
+
+    fn intr_b(&self) -> bool {
+        let s = &self.state;
+        let inte = s.output[2] & INTE_B != 0;
+        self.mode_b() == 1 && inte && (s.b.in_request || s.b.out_request)
+    }
+
+    /// what IN from port 0 (A), 1 (B) or 2 (C) reads.
+    fn read_port(&self, port: usize) -> u8 {
+        let s = &self.state;
+        match port {
+            2 => {
+                let out = self.c_output_mask();
+                let input = !out & !self.c_handshake_bits();
+                s.output[2] & out | s.pins[2] & input | self.c_handshake()
+            }
+            0 if self.strobed_input(0) => s.a.latch,
+            1 if self.strobed_input(1) => s.b.latch,
+            0 if self.output(0).is_some() => s.output[0],
+            1 if self.output(1).is_some() => s.output[1],
+            _ => s.pins[port],
+        }
+    }
+
+    fn write_port(&mut self, port: usize, data: u8) {
+        if port == 2 {
+            // the handshake bits are only changed by bit set/reset
+            let keep = self.c_handshake_bits();
+            self.state.output[2] = self.state.output[2] & keep | data & !keep;
+            return;
+        }
+        self.state.output[port] = data;
+        if self.strobed_output(port) {
+            let hs = self.handshake_mut(port);
+            hs.obf = true;
+            hs.out_request = false;
+            self.update_lines();
+        }
+    }
+
+    fn control(&mut self, data: u8) {
+        if data & MODE_SET != 0 {
+            // a new mode clears every output and handshake
+            let s = &mut self.state;
+            s.control = data;
+            s.output = [0; 3];
+            s.a = Handshake::default();
+            s.b = Handshake::default();
+        } else {
+            let bit = 1 << ((data >> 1) & 7);
+            match data & 1 {
+                0 => self.state.output[2] &= !bit,
+                _ => self.state.output[2] |= bit,
+            }
+        }
+        self.update_lines();
+    }
+
+    fn update_lines(&self) {
+        self.intr_a.set(self.intr_a());
+        self.intr_b.set(self.intr_b());
+    }
+}
+
+impl IoDevice for I8255 {
+    fn io_read(&mut self, offset: u8) -> u8 {
+        let data = self.io_peek(offset);
+        // reading a strobed input empties it
+        if offset < 2 && self.strobed_input(offset as usize) {
+            let hs = self.handshake_mut(offset as usize);
+            hs.ibf = false;
+            hs.in_request = false;
+            self.update_lines();
+        }
+        data
+    }
+
+    fn io_write(&mut self, offset: u8, data: u8) {
+        match offset {
+            0..=2 => self.write_port(offset as usize, data),
+            3 => self.control(data),
+            _ => {}
+        }
+    }
+
+    /// the control word register cannot be read, it reads 0.
+    fn io_peek(&self, offset: u8) -> u8 {
+        match offset {
+            0..=2 => self.read_port(offset as usize),
+            _ => 0,
+        }
+    }
+
+    /// every port becomes an input in mode 0.
+    fn reset(&mut self) {
+        self.control(0x9b);
+    }
+
+    /// "intr a" (PC3) and "intr b" (PC0).
+    fn line(&self, pin: &str) -> Option<Line> {
+        match pin.to_lowercase().as_ref() {
+            "intr a" => Some(self.intr_a.clone()),
+            "intr b" => Some(self.intr_b.clone()),
+            _ => None,
+        }
+    }
+
+    fn save(&self) -> Vec<u8> {
+        bincode::serialize(&self.state).unwrap()
+    }
+
+    fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        self.state =
+            bincode::deserialize(data).map_err(|e| format!("Corrupt 8255 state: {}", e))?;
+        self.update_lines();
+        Ok(())
+    }
+}
+
+// -----------------------TESTS----------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// bit set/reset word for bit `bit` of port C.
+    fn bsr(bit: u8, set: bool) -> u8 {
+        bit << 1 | set as u8
+    }
+
+    #[test]
+    fn test_port_c_bit_set_reset() {
+        // mode 0, every port an output
+        let mut d = I8255::new();
+        d.io_write(3, 0x80);
+        d.io_write(3, bsr(7, true));
+        d.io_write(3, bsr(0, true));
+        d.io_write(3, bsr(3, true));
+        assert_eq!(d.io_read(2), 0x89);
+        assert_eq!(d.output(2), Some(0x89));
+        d.io_write(3, bsr(7, false));
+        assert_eq!(d.io_read(2), 0x09);
+
+        // bit set/reset leaves the mode and the other ports alone
+        d.io_write(0, 0xa5);
+        d.io_write(3, bsr(5, true));
+        assert_eq!((d.mode_a(), d.mode_b()), (0, 0));
+        assert_eq!(d.output(0), Some(0xa5));
+        assert_eq!(d.io_read(2), 0x29);
+
+        // a bit of an input half changes the latch, not what is read
+        d.io_write(3, 0x88); // upper port C input, the outputs are cleared
+        d.set_input(2, 0xf0);
+        d.io_write(3, bsr(1, true));
+        d.io_write(3, bsr(6, false));
+        assert_eq!(d.io_read(2), 0xf2);
+        assert_eq!(d.output(2), Some(0x02));
+    }
+
+    #[test]
+    fn test_mode1_input() {
+        // ports A and B strobed inputs
+        let mut d = I8255::new();
+        let intr_a = d.line("intr a").unwrap();
+        let intr_b = d.line("intr b").unwrap();
+        d.io_write(3, 0xb6);
+        assert_eq!((d.mode_a(), d.mode_b()), (1, 1));
+        assert_eq!(d.output(0), None);
+
+        // STB A latches the pins and sets IBF A, INTR A waits for INTE A
+        d.set_input(0, 0x42);
+        d.strobe(0);
+        d.set_input(0, 0x00);
+        assert_eq!(d.io_peek(2) & (IBF_A | INTR_A), IBF_A);
+        assert!(!intr_a.level());
+        d.io_write(3, bsr(4, true)); // INTE A
+        assert!(intr_a.level());
+        assert_eq!(d.io_peek(2) & 0x38, IBF_A | INTE_A_IN | INTR_A);
+
+        // reading port A empties the latch
+        assert_eq!(d.io_read(0), 0x42);
+        assert!(!intr_a.level());
+        assert_eq!(d.io_peek(2) & 0x38, INTE_A_IN);
+
+        // port B with INTE B, on PC0-2
+        d.io_write(3, bsr(2, true));
+        d.set_input(1, 0x99);
+        d.strobe(1);
+        assert!(intr_b.level());
+        assert_eq!(d.io_peek(2) & 0x07, INTR_B | IBF_B | INTE_B);
+        assert_eq!(d.io_read(1), 0x99);
+        assert!(!intr_b.level());
+        assert_eq!(d.io_peek(2) & 0x07, INTE_B);
+
+        // ACK does nothing to an input
+        d.acknowledge(0);
+        assert!(!intr_a.level());
+    }
+
+    #[test]
+    fn test_mode1_output() {
+        // ports A and B strobed outputs, OBF is active low
+        let mut d = I8255::new();
+        let intr_a = d.line("intr a").unwrap();
+        let intr_b = d.line("intr b").unwrap();
+        d.io_write(3, 0xa4);
+        d.io_write(3, bsr(6, true)); // INTE A
+        d.io_write(3, bsr(2, true)); // INTE B
+        assert_eq!(d.output(2), Some(OBF_A | IBF_B));
+
+        // a write fills the buffer, ACK empties it and raises INTR
+        d.io_write(0, 0x5a);
+        assert_eq!(d.output(0), Some(0x5a));
+        assert_eq!(d.io_peek(2) & (OBF_A | INTR_A), 0);
+        d.acknowledge(0);
+        assert_eq!(d.io_peek(2) & (OBF_A | INTR_A), OBF_A | INTR_A);
+        assert!(intr_a.level());
+        d.io_write(0, 0xa5);
+        assert!(!intr_a.level());
+
+        // the same on PC0-1 for port B
+        d.io_write(1, 0x33);
+        assert_eq!(d.io_peek(2) & (IBF_B | INTR_B), 0);
+        d.acknowledge(1);
+        assert_eq!(d.io_peek(2) & (IBF_B | INTR_B), IBF_B | INTR_B);
+        assert!(intr_b.level());
+
+        // clearing INTE B drops INTR B, STB does nothing to an output
+        d.io_write(3, bsr(2, false));
+        assert!(!intr_b.level());
+        d.strobe(1);
+        assert_eq!(d.io_read(1), 0x33);
+
+        // a new mode clears the handshakes
+        d.io_write(3, 0xa4);
+        assert_eq!(d.output(2), Some(OBF_A | IBF_B));
+    }
+
+    #[test]
+    fn test_mode2() {
+        // port A bidirectional, port B and PC0-2 mode 0 outputs
+        let mut d = I8255::new();
+        let intr_a = d.line("intr a").unwrap();
+        d.io_write(3, 0xc0);
+        assert_eq!(d.mode_a(), 2);
+        d.io_write(3, bsr(6, true)); // INTE 1, output
+        d.io_write(3, bsr(4, true)); // INTE 2, input
+
+        // the output side: OBF goes low until ACK
+        d.io_write(0, 0x11);
+        assert_eq!(d.output(0), Some(0x11));
+        assert_eq!(d.io_peek(2) & OBF_A, 0);
+
+        // the input side: STB latches the pins while OBF stays low
+        d.set_input(0, 0x22);
+        d.strobe(0);
+        assert!(intr_a.level());
+        assert_eq!(d.io_peek(2) & (IBF_A | OBF_A), IBF_A);
+        assert_eq!(d.io_read(0), 0x22);
+        assert!(!intr_a.level());
+        assert_eq!(d.output(0), Some(0x11));
+
+        // ACK takes the output, INTR for the output needs INTE 1
+        d.acknowledge(0);
+        assert_eq!(d.io_peek(2) & (OBF_A | INTR_A), OBF_A | INTR_A);
+        assert!(intr_a.level());
+        d.io_write(3, bsr(6, false));
+        assert!(!intr_a.level());
+
+        // writes to port C only change PC0-2, the handshake bits are kept
+        d.io_write(2, 0xff);
+        assert_eq!(d.io_peek(2) & 0x07, 0x07);
+        assert_eq!(d.io_peek(2) & (INTE_A_OUT | INTE_A_IN), INTE_A_IN);
+        assert_eq!(d.output(2), Some(0x87));
+    }
+}
@@ -0,0 +1,134 @@
+use super::bus::Bus;
+use super::loader::{hex_bytes, Image, Segment};
+use wasm_bindgen::prelude::*;
+
+/// data bytes per record written.
+const RECORD_LEN: usize = 16;
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |s, b| s.wrapping_add(*b))
+        .wrapping_neg()
+}
+
+/// read Intel HEX text into segments, data records that follow each other
+/// are joined. the start address records give the entry point. data past
+/// 0xFFFF, through an extended address record or not, is refused.
+pub fn parse(text: &str) -> Result<Image, String> {
+    let mut image = Image::new();
+    let mut base: u32 = 0; // from the extended address records
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let n = n + 1;
+        let rec = match line.strip_prefix(':') {
+            Some(r) => r,
+            None => return Err(format!("Line {}: record does not start with ':'", n)),
+        };
+        let bytes = match hex_bytes(rec) {
+            Some(b) => b,
+            None => return Err(format!("Line {}: bad hex digits", n)),
+        };
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(format!(
+                "Line {}: record length does not match its count",
+                n
+            ));
+        }
+        let (body, sum) = bytes.split_at(bytes.len() - 1);
+        if checksum(body) != sum[0] {
+            return Err(format!(
+                "Line {}: bad checksum {:02X}, expected {:02X}",
+                n,
+                sum[0],
+                checksum(body)
+            ));
+        }
+        let addr = (body[1] as u16) << 8 | body[2] as u16;
+        let data = &body[4..];
+        match (body[3], data.len()) {
+            (0x00, _) => {
+                let start = base + addr as u32;
+                if start + data.len() as u32 > 0x10000 {
+                    return Err(format!("Line {}: data past address 0xFFFF", n));
+                }
+                image.add(start as u16, data);
+            }
+            (0x01, 0) => return Ok(image),
+            (0x02, 2) => base = ((data[0] as u32) << 8 | data[1] as u32) << 4,
+            (0x04, 2) => base = ((data[0] as u32) << 8 | data[1] as u32) << 16,
+            (0x03, 4) => {
+                let cs = (data[0] as u32) << 8 | data[1] as u32;
+                let ip = (data[2] as u32) << 8 | data[3] as u32;
+                image.entry = Some(entry(n, (cs << 4) + ip)?);
+            }
+            (0x05, 4) => {
+                let eip = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                image.entry = Some(entry(n, eip)?);
+            }
+            (0x01..=0x05, _) => {
+                return Err(format!(
+                    "Line {}: wrong length for a type {:02X} record",
+                    n, body[3]
+                ))
+            }
+            (t, _) => return Err(format!("Line {}: unknown record type {:02X}", n, t)),
+        }
+    }
+    Err("Missing end of file record".to_string())
+}
+
+fn entry(line: usize, addr: u32) -> Result<u16, String> {
+    match addr {
+        0..=0xffff => Ok(addr as u16),
+        _ => Err(format!(
+            "Line {}: start address 0x{:X} past 0xFFFF",
+            line, addr
+        )),
+    }
+}
+
+fn record(kind: u8, addr: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+    bytes.extend_from_slice(data);
+    bytes.push(checksum(&bytes));
+    let digits: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(":{}\n", digits.concat())
+}
+
+/// Intel HEX text of the image, 16 data bytes per record. the entry point is
+/// written as a start segment address record.
+pub fn write(image: &Image) -> String {
+    let mut res = String::new();
+    for Segment { origin, data } in &image.segments {
+        for (i, chunk) in data.chunks(RECORD_LEN).enumerate() {
+            let addr = origin.wrapping_add((i * RECORD_LEN) as u16);
+            res.push_str(&record(0x00, addr, chunk));
+        }
+    }
+    if let Some(e) = image.entry {
+        res.push_str(&record(0x03, 0, &[0, 0, (e >> 8) as u8, e as u8]));
+    }
+    res.push_str(&record(0x01, 0, &[]));
+    res
+}
+
+/// Intel HEX text of `start..=end` of memory, read without side effects.
+pub fn write_range<B: Bus + ?Sized>(memory: &B, start: u16, end: u16) -> String {
+    let data: Vec<u8> = (start..=end).map(|a| memory.peek(a)).collect();
+    write(&Image::from_bytes(&data, start))
+}
+
+/// assemble `code` into Intel HEX from address 0.
+pub fn assemble(code: &str) -> Result<String, String> {
+    let (bin, _) = super::asm8085::assemble(code)?;
+    Ok(write(&Image::from_bytes(&bin, 0)))
+}
+
+#[wasm_bindgen]
+pub fn assemble_hex_wasm(code: &str) -> Result<String, JsValue> {
+    Ok(assemble(code)?)
+}
@@ -0,0 +1,269 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Interrupt sources of the 8085 in order of priority.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interrupt {
+    Trap,
+    Rst75,
+    Rst65,
+    Rst55,
+    Intr,
+}
+
+impl Interrupt {
+    /// address the cpu jumps to when the interrupt is acknowledged.
+    /// INTR is vectored by the RST instruction placed on the data bus.
+    pub fn vector(&self, intr_opcode: u8) -> u16 {
+        match self {
+            Interrupt::Trap => 0x0024,
+            Interrupt::Rst75 => 0x003C,
+            Interrupt::Rst65 => 0x0034,
+            Interrupt::Rst55 => 0x002C,
+            Interrupt::Intr => (intr_opcode & 0x38) as u16,
+        }
+    }
+}
+
+impl FromStr for Interrupt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Interrupt, String> {
+        match s.to_lowercase().as_ref() {
+            "trap" => Ok(Interrupt::Trap),
+            "rst7.5" | "rst75" => Ok(Interrupt::Rst75),
+            "rst6.5" | "rst65" => Ok(Interrupt::Rst65),
+            "rst5.5" | "rst55" => Ok(Interrupt::Rst55),
+            "intr" => Ok(Interrupt::Intr),
+            _ => Err(format!("Unknown interrupt input {}", s)),
+        }
+    }
+}
+
+/// An output pin of a device, shared by the device that drives it and the cpu
+/// that samples it after every instruction. edges are kept until sampled, so
+/// a pulse shorter than an instruction is not lost.
+#[derive(Clone, Default)]
+pub struct Line {
+    state: Arc<Mutex<(bool, bool, bool)>>, // level, rose and fell since the last sample
+}
+
+impl Line {
+    pub fn new(level: bool) -> Line {
+        Line {
+            state: Arc::new(Mutex::new((level, false, false))),
+        }
+    }
+
+    pub fn set(&self, level: bool) {
+        let mut state = self.state.lock().unwrap();
+        let (old, rose, fell) = *state;
+        *state = (level, rose || level && !old, fell || !level && old);
+    }
+
+    pub fn level(&self) -> bool {
+        self.state.lock().unwrap().0
+    }
+
+    /// the level and whether it rose and fell since the last sample.
+    pub fn sample(&self) -> (bool, bool, bool) {
+        let mut state = self.state.lock().unwrap();
+        let (level, rose, fell) = *state;
+        *state = (level, false, false);
+        (level, rose, fell)
+    }
+}
+
+/// A device output wired to an interrupt input, through an inverter if `inverted`.
+#[derive(Clone)]
+pub struct Wire {
+    pub input: Interrupt,
+    pub line: Line,
+    pub inverted: bool,
+}
+
+impl Wire {
+    /// drive the input with what the line did since the last sample.
+    pub fn sample(&self, interrupts: &mut Interrupts) {
+        let (level, rose, fell) = self.line.sample();
+        let (level, rose, fell) = match self.inverted {
+            true => (!level, fell, rose),
+            false => (level, rose, fell),
+        };
+        if fell {
+            interrupts.set(self.input, false);
+        }
+        if rose {
+            interrupts.set(self.input, true);
+        }
+        interrupts.set(self.input, level);
+    }
+}
+
+// SIM accumulator bits
+const SIM_M55: u8 = 1 << 0;
+const SIM_M65: u8 = 1 << 1;
+const SIM_M75: u8 = 1 << 2;
+const SIM_MSE: u8 = 1 << 3;
+const SIM_R75: u8 = 1 << 4;
+
+/// State of the interrupt input pins and the internal latches and masks.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Interrupts {
+    trap: bool,            // TRAP pin level
+    trap_latch: bool,      // set on a rising edge of TRAP
+    rst75: bool,           // RST7.5 pin level
+    rst75_latch: bool,     // set on a rising edge of RST7.5
+    rst65: bool,           // RST6.5 pin level
+    rst55: bool,           // RST5.5 pin level
+    intr: bool,            // INTR pin level
+    intr_opcode: u8,       // instruction supplied during INTA
+    mask: u8,              // M7.5 M6.5 M5.5 as in SIM
+    trap_ie: Option<bool>, // IE before the last TRAP, reported by the next RIM
+}
+
+impl Default for Interrupts {
+    fn default() -> Self {
+        Interrupts::new()
+    }
+}
+
+impl Interrupts {
+    pub fn new() -> Interrupts {
+        Interrupts {
+            trap: false,
+            trap_latch: false,
+            rst75: false,
+            rst75_latch: false,
+            rst65: false,
+            rst55: false,
+            intr: false,
+            intr_opcode: 0xFF, // RST 7, a floating data bus reads as 0xFF
+            mask: SIM_M75 | SIM_M65 | SIM_M55,
+            trap_ie: None,
+        }
+    }
+
+    /// all masks are set and the latches are cleared on reset,
+    /// the pin levels are driven from outside and are left untouched.
+    pub fn reset(&mut self) {
+        self.trap_latch = false;
+        self.rst75_latch = false;
+        self.mask = SIM_M75 | SIM_M65 | SIM_M55;
+        self.trap_ie = None;
+    }
+
+    pub fn set_trap(&mut self, level: bool) {
+        if level && !self.trap {
+            self.trap_latch = true;
+        }
+        self.trap = level;
+    }
+
+    pub fn set_rst75(&mut self, level: bool) {
+        if level && !self.rst75 {
+            self.rst75_latch = true;
+        }
+        self.rst75 = level;
+    }
+
+    pub fn set_rst65(&mut self, level: bool) {
+        self.rst65 = level;
+    }
+
+    pub fn set_rst55(&mut self, level: bool) {
+        self.rst55 = level;
+    }
+
+    pub fn set_intr(&mut self, level: bool) {
+        self.intr = level;
+    }
+
+    /// drive the pin of `int`.
+    pub fn set(&mut self, int: Interrupt, level: bool) {
+        match int {
+            Interrupt::Trap => self.set_trap(level),
+            Interrupt::Rst75 => self.set_rst75(level),
+            Interrupt::Rst65 => self.set_rst65(level),
+            Interrupt::Rst55 => self.set_rst55(level),
+            Interrupt::Intr => self.set_intr(level),
+        }
+    }
+
+    /// set the instruction the external interrupt controller places on the
+    /// data bus when INTR is acknowledged, it must be one of the RST opcodes.
+    pub fn set_intr_opcode(&mut self, opcode: u8) {
+        self.intr_opcode = 0xC7 | (opcode & 0x38);
+    }
+
+    pub fn intr_opcode(&self) -> u8 {
+        self.intr_opcode
+    }
+
+    /// return the highest priority interrupt that would be accepted now.
+    pub fn pending(&self, ie: bool) -> Option<Interrupt> {
+        if self.trap && self.trap_latch {
+            return Some(Interrupt::Trap);
+        }
+        if !ie {
+            return None;
+        }
+        if self.rst75_latch && self.mask & SIM_M75 == 0 {
+            Some(Interrupt::Rst75)
+        } else if self.rst65 && self.mask & SIM_M65 == 0 {
+            Some(Interrupt::Rst65)
+        } else if self.rst55 && self.mask & SIM_M55 == 0 {
+            Some(Interrupt::Rst55)
+        } else if self.intr {
+            Some(Interrupt::Intr)
+        } else {
+            None
+        }
+    }
+
+    /// clear the internal latch of an interrupt once it has been acknowledged.
+    pub fn acknowledge(&mut self, int: Interrupt, ie: bool) {
+        match int {
+            Interrupt::Trap => {
+                self.trap_latch = false;
+                self.trap_ie = Some(ie);
+            }
+            Interrupt::Rst75 => self.rst75_latch = false,
+            _ => (),
+        }
+    }
+
+    /// bits 6-0 of the accumulator after RIM.
+    /// 7   6   5   4   3  2   1   0
+    /// SID I75 I65 I55 IE M75 M65 M55
+    pub fn rim(&mut self, ie: bool) -> u8 {
+        let ie = self.trap_ie.take().unwrap_or(ie);
+        let mut res = self.mask;
+        if ie {
+            res |= 1 << 3;
+        }
+        if self.rst55 {
+            res |= 1 << 4;
+        }
+        if self.rst65 {
+            res |= 1 << 5;
+        }
+        if self.rst75_latch {
+            res |= 1 << 6;
+        }
+        res
+    }
+
+    /// apply the interrupt related bits of the accumulator on SIM.
+    /// 7   6   5   4   3   2   1   0
+    /// SOD SOE  X R75 MSE M75 M65 M55
+    pub fn sim(&mut self, a: u8) {
+        if a & SIM_MSE != 0 {
+            self.mask = a & (SIM_M75 | SIM_M65 | SIM_M55);
+        }
+        if a & SIM_R75 != 0 {
+            self.rst75_latch = false;
+        }
+    }
+}
@@ -1,28 +1,100 @@
+use super::interrupt::Line;
 use serde::{Deserialize, Serialize};
+use std::any::Any;
 
-#[derive(Serialize, Deserialize)]
+/// A device that answers to a range of I/O ports. ports are given as the
+/// offset from the first port of the range. like memory devices they are `Send`.
+pub trait IoDevice: Any + Send {
+    fn io_read(&mut self, offset: u8) -> u8 {
+        self.io_peek(offset)
+    }
+    fn io_write(&mut self, offset: u8, data: u8);
+    /// read without side effects, for viewers and the debugger.
+    fn io_peek(&self, offset: u8) -> u8;
+    /// called after every instruction with the T-states it took.
+    fn tick(&mut self, _cycles: u32) {}
+    /// the RESET OUT of the cpu.
+    fn reset(&mut self) {}
+    /// an output pin by its name, to wire it to an interrupt input.
+    fn line(&self, _pin: &str) -> Option<Line> {
+        None
+    }
+    /// contents to keep in a save state, nothing for devices without state.
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn load(&mut self, _data: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// A plain port with two latches, like a bank of switches and a bank of LEDs
+/// at the same address: IN reads the input the host sets and OUT writes the
+/// output the host watches.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct IoPort {
     addr: u8,
-    data: u8,
+    input: u8,  // driven by the host
+    output: u8, // driven by the cpu
 }
 
 impl IoPort {
     pub fn new(addr: u8) -> IoPort {
         IoPort {
             addr: addr,
-            data: 0,
+            input: 0,
+            output: 0,
         }
     }
 
+    /// what IN reads, the input latch.
     pub fn read(&self) -> u8 {
-        self.data
+        self.input
     }
 
+    /// what OUT writes, the output latch.
     pub fn write(&mut self, data: u8) {
-        self.data = data;
+        self.output = data;
+    }
+
+    pub fn input(&self) -> u8 {
+        self.input
+    }
+
+    pub fn set_input(&mut self, data: u8) {
+        self.input = data;
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output
     }
 
     pub fn get_addr(&self) -> u8 {
         self.addr
     }
 }
+
+impl IoDevice for IoPort {
+    fn io_write(&mut self, _offset: u8, data: u8) {
+        self.write(data);
+    }
+
+    fn io_peek(&self, _offset: u8) -> u8 {
+        self.read()
+    }
+
+    fn save(&self) -> Vec<u8> {
+        vec![self.input, self.output]
+    }
+
+    fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        match data {
+            [i, o] => {
+                self.input = *i;
+                self.output = *o;
+                Ok(())
+            }
+            _ => Err("Saved I/O port does not match the machine".to_string()),
+        }
+    }
+}
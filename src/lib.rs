@@ -1,8 +1,26 @@
 #![allow(dead_code)]
 pub mod asm8085;
+pub mod banked;
+pub mod breakpoint;
+pub mod bus;
 pub mod cpu8085;
+pub mod disasm8085;
+pub mod error;
+pub mod history;
+pub mod i8155;
+pub mod i8254;
+pub mod i8255;
+pub mod ihex;
+pub mod interrupt;
 pub mod ioport;
+pub mod loader;
+pub mod machine_cycle;
 pub mod memory;
+pub mod opcodes;
+pub mod serial;
+pub mod srec;
+pub mod state;
+pub mod trace;
 
 cfg_if::cfg_if! {
     // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+/// Bytes to be loaded from `origin`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Segment {
+    pub origin: u16,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    pub fn new(origin: u16, data: Vec<u8>) -> Segment {
+        Segment { origin, data }
+    }
+
+    /// one past the last address, up to 0x10000.
+    pub fn end(&self) -> u32 {
+        self.origin as u32 + self.data.len() as u32
+    }
+}
+
+/// the segments must fit in the 64K address space and not overlap.
+pub fn check_segments(segments: &[Segment]) -> Result<(), String> {
+    for s in segments {
+        if s.end() > 0x10000 {
+            return Err(format!(
+                "Segment at 0x{:04X} of {} bytes runs past 0xFFFF",
+                s.origin,
+                s.data.len()
+            ));
+        }
+    }
+    let mut sorted: Vec<&Segment> = segments.iter().filter(|s| !s.data.is_empty()).collect();
+    sorted.sort_by_key(|s| s.origin);
+    for w in sorted.windows(2) {
+        if w[0].end() > w[1].origin as u32 {
+            return Err(format!(
+                "Segments at 0x{:04X} and 0x{:04X} overlap",
+                w[0].origin, w[1].origin
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Segments and entry point read from a file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub entry: Option<u16>,
+}
+
+impl Image {
+    pub fn new() -> Image {
+        Image {
+            segments: Vec::new(),
+            entry: None,
+        }
+    }
+
+    /// an image of `data` at `origin`.
+    pub fn from_bytes(data: &[u8], origin: u16) -> Image {
+        Image {
+            segments: vec![Segment::new(origin, data.to_vec())],
+            entry: None,
+        }
+    }
+
+    /// add bytes, they join the last segment if they follow it.
+    pub fn add(&mut self, origin: u16, data: &[u8]) {
+        match self.segments.last_mut() {
+            Some(s) if s.end() == origin as u32 => s.data.extend_from_slice(data),
+            _ => self.segments.push(Segment::new(origin, data.to_vec())),
+        }
+    }
+}
+
+/// bytes written as pairs of hex digits, None if a digit is bad or missing.
+pub fn hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
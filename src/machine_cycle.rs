@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+/// Machine cycles the 8085 bus can be in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MachineCycleKind {
+    OpcodeFetch,
+    MemoryRead,
+    MemoryWrite,
+    IoRead,
+    IoWrite,
+    InterruptAck, // INTR acknowledge, the instruction is read with INTA
+    RestartAck,   // TRAP and RST x.5 acknowledge, the vector is internal
+    BusIdle,
+    Halt,
+}
+
+/// One machine cycle of an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MachineCycle {
+    pub kind: MachineCycleKind,
+    pub address: u16,
+    pub data: u8,
+    pub t_states: u8,
+}
+
+/// Signal levels on the bus during one T-state.
+/// `rd`, `wr` and `inta` are active low pins, `false` means asserted.
+/// `ad` is the multiplexed AD7-AD0 bus, `None` when it is floating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TState {
+    pub cycle: MachineCycleKind,
+    pub t: u8,
+    pub ale: bool,
+    pub io_m: bool,
+    pub s1: bool,
+    pub s0: bool,
+    pub rd: bool,
+    pub wr: bool,
+    pub inta: bool,
+    pub address: u16,
+    pub ad: Option<u8>,
+}
+
+/// Receives every T-state in bus mode.
+pub type BusCallback = Box<dyn FnMut(&TState) + Send>;
+
+impl MachineCycle {
+    pub fn new(kind: MachineCycleKind, address: u16, data: u8, t_states: u8) -> MachineCycle {
+        MachineCycle {
+            kind,
+            address,
+            data,
+            t_states,
+        }
+    }
+
+    /// status lines (IO/M, S1, S0) for the machine cycle.
+    pub fn status(&self) -> (bool, bool, bool) {
+        use MachineCycleKind::*;
+        match self.kind {
+            OpcodeFetch => (false, true, true),
+            MemoryRead => (false, true, false),
+            MemoryWrite => (false, false, true),
+            IoRead => (true, true, false),
+            IoWrite => (true, false, true),
+            InterruptAck => (true, true, true),
+            RestartAck => (true, true, true),
+            BusIdle => (false, true, false),
+            Halt => (false, false, false),
+        }
+    }
+
+    /// expand the machine cycle into the bus signals of each of its T-states.
+    pub fn t_states(&self) -> Vec<TState> {
+        use MachineCycleKind::*;
+        let (io_m, s1, s0) = self.status();
+        let addressed = !matches!(self.kind, BusIdle | Halt);
+        let reads = matches!(self.kind, OpcodeFetch | MemoryRead | IoRead);
+        let writes = matches!(self.kind, MemoryWrite | IoWrite);
+        let inta = self.kind == InterruptAck;
+
+        (1..=self.t_states)
+            .map(|t| {
+                let strobe = t == 2 || t == 3;
+                let ad = match t {
+                    1 if addressed => Some(self.address as u8),
+                    2 if writes => Some(self.data),
+                    3 if reads || writes || inta => Some(self.data),
+                    _ => None,
+                };
+                TState {
+                    cycle: self.kind,
+                    t,
+                    ale: t == 1 && addressed,
+                    io_m,
+                    s1,
+                    s0,
+                    rd: !(strobe && reads),
+                    wr: !(strobe && writes),
+                    inta: !(strobe && inta),
+                    address: self.address,
+                    ad,
+                }
+            })
+            .collect()
+    }
+}
+
+/// number of T-states of the opcode fetch machine cycle.
+/// instructions that use the 16 bit incrementer or push onto the stack
+/// spend two more T-states in the fetch.
+pub fn opcode_fetch_t_states(opcode: u8) -> u8 {
+    match opcode {
+        0x03 | 0x13 | 0x23 | 0x33 => 6,                    // INX
+        0x0B | 0x1B | 0x2B | 0x3B => 6,                    // DCX
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => 6,                    // PUSH
+        0xE9 | 0xF9 => 6,                                  // PCHL SPHL
+        0xCD => 6,                                         // CALL
+        op if op & 0xC7 == 0xC0 || op & 0xC7 == 0xC4 => 6, // Rccc Cccc
+        op if op & 0xC7 == 0xC7 => 6,                      // RST
+        0xCB => 6,                                         // RSTV
+        _ => 4,
+    }
+}
+
+/// build the machine cycles of an instruction from the bus accesses it made.
+/// the first access must be the opcode read. accesses beyond the T-states the
+/// instruction takes are operands the 8085 does not fetch (a branch that is
+/// not taken) and are dropped, T-states left over are spent with the bus idle.
+pub fn compose(accesses: &[MachineCycle], t_states: u8) -> Vec<MachineCycle> {
+    let mut res = Vec::new();
+    let fetch = match accesses.first() {
+        Some(a) => a,
+        None => return res,
+    };
+    let fetch_t = opcode_fetch_t_states(fetch.data);
+    res.push(MachineCycle::new(
+        MachineCycleKind::OpcodeFetch,
+        fetch.address,
+        fetch.data,
+        fetch_t,
+    ));
+
+    let mut left = t_states.saturating_sub(fetch_t);
+    for a in &accesses[1..] {
+        if left < 3 {
+            break;
+        }
+        res.push(MachineCycle::new(a.kind, a.address, a.data, 3));
+        left -= 3;
+    }
+
+    let idle = if fetch.data == 0x76 {
+        MachineCycleKind::Halt
+    } else {
+        MachineCycleKind::BusIdle
+    };
+    while left > 0 {
+        let t = left.min(3);
+        res.push(MachineCycle::new(idle, fetch.address, 0, t));
+        left -= t;
+    }
+    res
+}
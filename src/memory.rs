@@ -1,65 +1,168 @@
+use super::bus::{Bus, Fault, MemoryDevice};
+use super::ihex;
+use super::loader::{self, Image};
+use super::srec;
 use js_sys::Uint8Array;
+use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
+/// What accesses beyond the end of a memory, or to unmapped addresses, do.
 #[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutOfRange {
+    OpenBus, // reads return 0xff, the data bus is pulled up
+    Zero,    // reads return 0
+    Fault,   // the access stops the cpu, reads return 0xff
+}
+
+impl OutOfRange {
+    /// value read from outside the range.
+    pub fn value(&self) -> u8 {
+        match self {
+            OutOfRange::Zero => 0x00,
+            _ => 0xff,
+        }
+    }
+}
+
+/// What an address range of a memory is.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegionKind {
+    Ram,
+    Rom,      // writes are ignored, or fault
+    Unmapped, // accessed like addresses beyond the end
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Memory {
     data: Vec<u8>,
-    size: u16,
+    size: u32, // up to 64K
+    out_of_range: OutOfRange,
+    regions: Vec<(u16, u16, RegionKind)>, // later ones win, addresses in none are RAM
+    rom_write_fault: bool,                // writes to ROM stop the cpu
+    #[serde(skip)]
+    fault: Option<Fault>, // fault of the last access
 }
 
 #[wasm_bindgen]
 impl Memory {
-    pub fn new(size: u16) -> Memory {
-        Memory {
-            data: vec![0; size as usize],
-            size: size,
+    /// `size` is at most 65536 bytes, the whole address space.
+    pub fn new(size: u32) -> Result<Memory, String> {
+        if size > 0x10000 {
+            return Err("Memory larger than 64K".to_string());
         }
+        Ok(Memory {
+            data: vec![0; size as usize],
+            size,
+            out_of_range: OutOfRange::Fault,
+            regions: Vec::new(),
+            rom_write_fault: false,
+            fault: None,
+        })
     }
 
-    pub fn size(&self) -> u16 {
+    pub fn size(&self) -> u32 {
         self.size
     }
 
+    pub fn set_out_of_range(&mut self, out_of_range: OutOfRange) {
+        self.out_of_range = out_of_range;
+    }
+
+    pub fn get_out_of_range(&self) -> OutOfRange {
+        self.out_of_range
+    }
+
+    /// mark `start..=end` as RAM, ROM or unmapped.
+    pub fn set_region(&mut self, start: u16, end: u16, kind: RegionKind) {
+        self.regions.push((start, end, kind));
+    }
+
+    pub fn clear_regions(&mut self) {
+        self.regions.clear();
+    }
+
+    /// whether writes to ROM fault or are ignored.
+    pub fn set_rom_write_fault(&mut self, fault: bool) {
+        self.rom_write_fault = fault;
+    }
+
+    /// what `addr` is, addresses beyond the end are unmapped.
+    pub fn kind(&self, addr: u16) -> RegionKind {
+        if addr as u32 >= self.size {
+            return RegionKind::Unmapped;
+        }
+        self.regions
+            .iter()
+            .rev()
+            .find(|(start, end, _)| *start <= addr && addr <= *end)
+            .map_or(RegionKind::Ram, |r| r.2)
+    }
+
     /// write to a given address in the memory
     pub fn write(&mut self, addr: u16, content: u8) {
-        if addr < self.size {
-            self.data[addr as usize] = content;
-        } else {
-            // panic!("Memory overflow {:#02x}/{:#02x}", addr, self.size);
+        match self.kind(addr) {
+            RegionKind::Ram => self.data[addr as usize] = content,
+            RegionKind::Rom if self.rom_write_fault => self.fault = Some(Fault::RomWrite(addr)),
+            RegionKind::Rom => (),
+            RegionKind::Unmapped if self.out_of_range == OutOfRange::Fault => {
+                self.fault = Some(Fault::OutOfRange(addr))
+            }
+            RegionKind::Unmapped => (),
         }
     }
 
-    // return as optional result here
     /// read from a given address in the memory
     pub fn read(&self, addr: u16) -> u8 {
-        if addr < self.size {
-            return self.data[addr as usize];
-        } else {
-            // panic!("Memory overflow");
-            return 0;
+        match self.kind(addr) {
+            RegionKind::Unmapped => self.out_of_range.value(),
+            _ => self.data[addr as usize],
         }
     }
 
     pub fn display(&self, addr: u16) {
-        if addr < self.size {
+        if self.kind(addr) != RegionKind::Unmapped {
             println!("{:#02x} : {:#02x}", addr, self.data[addr as usize]);
         } else {
             println!("Memory out of range");
         }
     }
 
-    pub fn new_from_js(bin: &JsValue, size: usize) -> Memory {
-        let buffer = Uint8Array::new(bin);
-        let bin = buffer.to_vec();
-        assert!(bin.len() < size);
-        let mut res = Memory {
-            data: vec![0; size as usize],
-            size: size as u16,
-        };
-        for i in 0..bin.len() {
-            res.data[i] = bin[i];
+    /// a memory of `size` bytes with `bin` from address 0.
+    pub fn new_from_js(bin: &JsValue, size: usize) -> Result<Memory, String> {
+        if size > 0x10000 {
+            return Err("Memory larger than 64K".to_string());
         }
-        res
+        let mut res = Memory::new(size as u32)?;
+        res.load(0, &Uint8Array::new(bin).to_vec())?;
+        Ok(res)
+    }
+
+    /// copy `data` to the memory from `origin`, ROM regions included.
+    pub fn load(&mut self, origin: u16, data: &[u8]) -> Result<(), String> {
+        let end = origin as usize + data.len();
+        if end > self.size as usize {
+            return Err(format!(
+                "{} bytes at 0x{:04X} do not fit in a memory of {} bytes",
+                data.len(),
+                origin,
+                self.size
+            ));
+        }
+        self.data[origin as usize..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// load the records of Intel HEX text, nothing is loaded if one is bad.
+    pub fn load_hex(&mut self, text: &str) -> Result<(), String> {
+        self.load_image(&ihex::parse(text)?)
+    }
+
+    /// load the records of Motorola S-record text, nothing is loaded if one is bad.
+    pub fn load_srec(&mut self, text: &str) -> Result<(), String> {
+        self.load_image(&srec::parse(text)?)
     }
 }
 
@@ -68,15 +171,165 @@ impl Memory {
         self.data.as_ptr()
     }
 
-    pub fn new_from(bin: &Vec<u8>, size: usize) -> Memory {
-        assert!(bin.len() < size);
-        let mut res = Memory {
-            data: vec![0; size as usize],
-            size: size as u16,
-        };
-        for i in 0..bin.len() {
-            res.data[i] = bin[i];
+    /// load the segments of an image, nothing is loaded if one does not fit.
+    pub fn load_image(&mut self, image: &Image) -> Result<(), String> {
+        loader::check_segments(&image.segments)?;
+        if let Some(s) = image.segments.iter().find(|s| s.end() > self.size) {
+            return Err(format!(
+                "{} bytes at 0x{:04X} do not fit in a memory of {} bytes",
+                s.data.len(),
+                s.origin,
+                self.size
+            ));
+        }
+        for s in &image.segments {
+            self.load(s.origin, &s.data)?;
         }
+        Ok(())
+    }
+
+    /// panics if `bin` does not fit, see `load` for loading that can fail.
+    pub fn new_from(bin: &Vec<u8>, size: usize) -> Memory {
+        assert!(bin.len() <= size);
+        let mut res = Memory::new(size as u32).expect("Memory larger than 64K");
+        res.data[..bin.len()].copy_from_slice(bin);
         res
     }
+
+    /// read that notes a fault when out of range.
+    fn read_checked(&mut self, addr: u16) -> u8 {
+        if self.kind(addr) == RegionKind::Unmapped && self.out_of_range == OutOfRange::Fault {
+            self.fault = Some(Fault::OutOfRange(addr));
+        }
+        Memory::read(self, addr)
+    }
+}
+
+impl MemoryDevice for Memory {
+    fn read(&mut self, offset: u16) -> u8 {
+        self.read_checked(offset)
+    }
+
+    fn write(&mut self, offset: u16, data: u8) {
+        Memory::write(self, offset, data)
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        Memory::read(self, offset)
+    }
+
+    fn poke(&mut self, offset: u16, data: u8) {
+        if (offset as u32) < self.size {
+            self.data[offset as usize] = data;
+        }
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.data[..self.size as usize].to_vec()
+    }
+
+    fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() != self.size as usize {
+            return Err(format!(
+                "Saved memory has {} bytes instead of {}",
+                data.len(),
+                self.size
+            ));
+        }
+        self.data[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.fault.take()
+    }
+
+    fn set_out_of_range(&mut self, out_of_range: OutOfRange) {
+        Memory::set_out_of_range(self, out_of_range)
+    }
+}
+
+/// A flat memory is a bus with nothing on its I/O ports.
+impl Bus for Memory {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_checked(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        Memory::write(self, addr, data)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        Memory::read(self, addr)
+    }
+
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.fault.take()
+    }
+
+    fn io_read(&mut self, _port: u8) -> u8 {
+        0
+    }
+
+    fn io_write(&mut self, _port: u8, _data: u8) {}
+
+    fn io_peek(&self, _port: u8) -> u8 {
+        0
+    }
+
+    fn poke(&mut self, addr: u16, data: u8) -> bool {
+        MemoryDevice::poke(self, addr, data);
+        (addr as u32) < self.size
+    }
+
+    fn is_mapped(&self, addr: u16) -> bool {
+        (addr as u32) < self.size
+    }
+
+    fn set_out_of_range(&mut self, out_of_range: OutOfRange) {
+        Memory::set_out_of_range(self, out_of_range)
+    }
+}
+
+/// Read only memory, writes are ignored or fault.
+pub struct Rom {
+    data: Vec<u8>,
+    write_fault: bool,
+    fault: Option<Fault>,
+}
+
+impl Rom {
+    pub fn new(data: Vec<u8>) -> Rom {
+        Rom {
+            data,
+            write_fault: false,
+            fault: None,
+        }
+    }
+
+    pub fn set_write_fault(&mut self, fault: bool) {
+        self.write_fault = fault;
+    }
+}
+
+impl MemoryDevice for Rom {
+    fn write(&mut self, offset: u16, _data: u8) {
+        if self.write_fault {
+            self.fault = Some(Fault::RomWrite(offset));
+        }
+    }
+
+    fn peek(&self, offset: u16) -> u8 {
+        self.data.get(offset as usize).copied().unwrap_or(0)
+    }
+
+    fn poke(&mut self, offset: u16, data: u8) {
+        if let Some(b) = self.data.get_mut(offset as usize) {
+            *b = data;
+        }
+    }
+
+    fn take_fault(&mut self) -> Option<Fault> {
+        self.fault.take()
+    }
 }
@@ -0,0 +1,381 @@
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// Everything known about one opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct OpcodeInfo {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    /// operands in assembler syntax, d8 and d16 stand for immediate data
+    /// and a16 for an address, e.g. "a,d8" for MVI A.
+    pub operands: &'static str,
+    pub length: u8,
+    /// T-states for a taken and a not taken branch, equal for other instructions.
+    pub t_states: (u8, u8),
+    /// flags the instruction changes, out of "S Z K AC P V CY".
+    pub flags: &'static str,
+    pub undocumented: bool,
+    pub description: &'static str,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn op(
+    opcode: u8,
+    mnemonic: &'static str,
+    operands: &'static str,
+    length: u8,
+    t_states: (u8, u8),
+    flags: &'static str,
+    undocumented: bool,
+    description: &'static str,
+) -> OpcodeInfo {
+    OpcodeInfo {
+        opcode,
+        mnemonic,
+        operands,
+        length,
+        t_states,
+        flags,
+        undocumented,
+        description,
+    }
+}
+
+/// The 8085 instruction set, indexed by opcode.
+#[rustfmt::skip]
+pub static OPCODES: [OpcodeInfo; 256] = [
+    op(0x00, "nop", "", 1, (4, 4), "", false, "No operation"),
+    op(0x01, "lxi", "b,d16", 3, (10, 10), "", false, "Load BC with immediate data"),
+    op(0x02, "stax", "b", 1, (7, 7), "", false, "Store A at the address in BC"),
+    op(0x03, "inx", "b", 1, (6, 6), "K", false, "Increment BC"),
+    op(0x04, "inr", "b", 1, (4, 4), "S Z K AC P V", false, "Increment B"),
+    op(0x05, "dcr", "b", 1, (4, 4), "S Z K AC P V", false, "Decrement B"),
+    op(0x06, "mvi", "b,d8", 2, (7, 7), "", false, "Move immediate data into B"),
+    op(0x07, "rlc", "", 1, (4, 4), "CY", false, "Rotate A left"),
+    op(0x08, "dsub", "", 1, (10, 10), "S Z K AC P V CY", true, "Subtract BC from HL"),
+    op(0x09, "dad", "b", 1, (10, 10), "CY", false, "Add BC to HL"),
+    op(0x0A, "ldax", "b", 1, (7, 7), "", false, "Load A from the address in BC"),
+    op(0x0B, "dcx", "b", 1, (6, 6), "K", false, "Decrement BC"),
+    op(0x0C, "inr", "c", 1, (4, 4), "S Z K AC P V", false, "Increment C"),
+    op(0x0D, "dcr", "c", 1, (4, 4), "S Z K AC P V", false, "Decrement C"),
+    op(0x0E, "mvi", "c,d8", 2, (7, 7), "", false, "Move immediate data into C"),
+    op(0x0F, "rrc", "", 1, (4, 4), "CY", false, "Rotate A right"),
+    op(0x10, "arhl", "", 1, (7, 7), "CY", true, "Arithmetic shift HL right"),
+    op(0x11, "lxi", "d,d16", 3, (10, 10), "", false, "Load DE with immediate data"),
+    op(0x12, "stax", "d", 1, (7, 7), "", false, "Store A at the address in DE"),
+    op(0x13, "inx", "d", 1, (6, 6), "K", false, "Increment DE"),
+    op(0x14, "inr", "d", 1, (4, 4), "S Z K AC P V", false, "Increment D"),
+    op(0x15, "dcr", "d", 1, (4, 4), "S Z K AC P V", false, "Decrement D"),
+    op(0x16, "mvi", "d,d8", 2, (7, 7), "", false, "Move immediate data into D"),
+    op(0x17, "ral", "", 1, (4, 4), "CY", false, "Rotate A left through carry"),
+    op(0x18, "rdel", "", 1, (10, 10), "V CY", true, "Rotate DE left through carry"),
+    op(0x19, "dad", "d", 1, (10, 10), "CY", false, "Add DE to HL"),
+    op(0x1A, "ldax", "d", 1, (7, 7), "", false, "Load A from the address in DE"),
+    op(0x1B, "dcx", "d", 1, (6, 6), "K", false, "Decrement DE"),
+    op(0x1C, "inr", "e", 1, (4, 4), "S Z K AC P V", false, "Increment E"),
+    op(0x1D, "dcr", "e", 1, (4, 4), "S Z K AC P V", false, "Decrement E"),
+    op(0x1E, "mvi", "e,d8", 2, (7, 7), "", false, "Move immediate data into E"),
+    op(0x1F, "rar", "", 1, (4, 4), "CY", false, "Rotate A right through carry"),
+    op(0x20, "rim", "", 1, (4, 4), "", false, "Read interrupt masks and SID into A"),
+    op(0x21, "lxi", "h,d16", 3, (10, 10), "", false, "Load HL with immediate data"),
+    op(0x22, "shld", "a16", 3, (16, 16), "", false, "Store HL direct"),
+    op(0x23, "inx", "h", 1, (6, 6), "K", false, "Increment HL"),
+    op(0x24, "inr", "h", 1, (4, 4), "S Z K AC P V", false, "Increment H"),
+    op(0x25, "dcr", "h", 1, (4, 4), "S Z K AC P V", false, "Decrement H"),
+    op(0x26, "mvi", "h,d8", 2, (7, 7), "", false, "Move immediate data into H"),
+    op(0x27, "daa", "", 1, (4, 4), "S Z AC P CY", false, "Decimal adjust A"),
+    op(0x28, "ldhi", "d8", 2, (10, 10), "", true, "Load DE with HL plus immediate data"),
+    op(0x29, "dad", "h", 1, (10, 10), "CY", false, "Add HL to HL"),
+    op(0x2A, "lhld", "a16", 3, (16, 16), "", false, "Load HL direct"),
+    op(0x2B, "dcx", "h", 1, (6, 6), "K", false, "Decrement HL"),
+    op(0x2C, "inr", "l", 1, (4, 4), "S Z K AC P V", false, "Increment L"),
+    op(0x2D, "dcr", "l", 1, (4, 4), "S Z K AC P V", false, "Decrement L"),
+    op(0x2E, "mvi", "l,d8", 2, (7, 7), "", false, "Move immediate data into L"),
+    op(0x2F, "cma", "", 1, (4, 4), "", false, "Complement A"),
+    op(0x30, "sim", "", 1, (4, 4), "", false, "Set interrupt masks and SOD from A"),
+    op(0x31, "lxi", "sp,d16", 3, (10, 10), "", false, "Load SP with immediate data"),
+    op(0x32, "sta", "a16", 3, (13, 13), "", false, "Store A direct"),
+    op(0x33, "inx", "sp", 1, (6, 6), "K", false, "Increment SP"),
+    op(0x34, "inr", "m", 1, (10, 10), "S Z K AC P V", false, "Increment memory at HL"),
+    op(0x35, "dcr", "m", 1, (10, 10), "S Z K AC P V", false, "Decrement memory at HL"),
+    op(0x36, "mvi", "m,d8", 2, (10, 10), "", false, "Move immediate data into memory at HL"),
+    op(0x37, "stc", "", 1, (4, 4), "CY", false, "Set carry"),
+    op(0x38, "ldsi", "d8", 2, (10, 10), "", true, "Load DE with SP plus immediate data"),
+    op(0x39, "dad", "sp", 1, (10, 10), "CY", false, "Add SP to HL"),
+    op(0x3A, "lda", "a16", 3, (13, 13), "", false, "Load A direct"),
+    op(0x3B, "dcx", "sp", 1, (6, 6), "K", false, "Decrement SP"),
+    op(0x3C, "inr", "a", 1, (4, 4), "S Z K AC P V", false, "Increment A"),
+    op(0x3D, "dcr", "a", 1, (4, 4), "S Z K AC P V", false, "Decrement A"),
+    op(0x3E, "mvi", "a,d8", 2, (7, 7), "", false, "Move immediate data into A"),
+    op(0x3F, "cmc", "", 1, (4, 4), "CY", false, "Complement carry"),
+    op(0x40, "mov", "b,b", 1, (4, 4), "", false, "Copy B into B"),
+    op(0x41, "mov", "b,c", 1, (4, 4), "", false, "Copy C into B"),
+    op(0x42, "mov", "b,d", 1, (4, 4), "", false, "Copy D into B"),
+    op(0x43, "mov", "b,e", 1, (4, 4), "", false, "Copy E into B"),
+    op(0x44, "mov", "b,h", 1, (4, 4), "", false, "Copy H into B"),
+    op(0x45, "mov", "b,l", 1, (4, 4), "", false, "Copy L into B"),
+    op(0x46, "mov", "b,m", 1, (7, 7), "", false, "Copy memory at HL into B"),
+    op(0x47, "mov", "b,a", 1, (4, 4), "", false, "Copy A into B"),
+    op(0x48, "mov", "c,b", 1, (4, 4), "", false, "Copy B into C"),
+    op(0x49, "mov", "c,c", 1, (4, 4), "", false, "Copy C into C"),
+    op(0x4A, "mov", "c,d", 1, (4, 4), "", false, "Copy D into C"),
+    op(0x4B, "mov", "c,e", 1, (4, 4), "", false, "Copy E into C"),
+    op(0x4C, "mov", "c,h", 1, (4, 4), "", false, "Copy H into C"),
+    op(0x4D, "mov", "c,l", 1, (4, 4), "", false, "Copy L into C"),
+    op(0x4E, "mov", "c,m", 1, (7, 7), "", false, "Copy memory at HL into C"),
+    op(0x4F, "mov", "c,a", 1, (4, 4), "", false, "Copy A into C"),
+    op(0x50, "mov", "d,b", 1, (4, 4), "", false, "Copy B into D"),
+    op(0x51, "mov", "d,c", 1, (4, 4), "", false, "Copy C into D"),
+    op(0x52, "mov", "d,d", 1, (4, 4), "", false, "Copy D into D"),
+    op(0x53, "mov", "d,e", 1, (4, 4), "", false, "Copy E into D"),
+    op(0x54, "mov", "d,h", 1, (4, 4), "", false, "Copy H into D"),
+    op(0x55, "mov", "d,l", 1, (4, 4), "", false, "Copy L into D"),
+    op(0x56, "mov", "d,m", 1, (7, 7), "", false, "Copy memory at HL into D"),
+    op(0x57, "mov", "d,a", 1, (4, 4), "", false, "Copy A into D"),
+    op(0x58, "mov", "e,b", 1, (4, 4), "", false, "Copy B into E"),
+    op(0x59, "mov", "e,c", 1, (4, 4), "", false, "Copy C into E"),
+    op(0x5A, "mov", "e,d", 1, (4, 4), "", false, "Copy D into E"),
+    op(0x5B, "mov", "e,e", 1, (4, 4), "", false, "Copy E into E"),
+    op(0x5C, "mov", "e,h", 1, (4, 4), "", false, "Copy H into E"),
+    op(0x5D, "mov", "e,l", 1, (4, 4), "", false, "Copy L into E"),
+    op(0x5E, "mov", "e,m", 1, (7, 7), "", false, "Copy memory at HL into E"),
+    op(0x5F, "mov", "e,a", 1, (4, 4), "", false, "Copy A into E"),
+    op(0x60, "mov", "h,b", 1, (4, 4), "", false, "Copy B into H"),
+    op(0x61, "mov", "h,c", 1, (4, 4), "", false, "Copy C into H"),
+    op(0x62, "mov", "h,d", 1, (4, 4), "", false, "Copy D into H"),
+    op(0x63, "mov", "h,e", 1, (4, 4), "", false, "Copy E into H"),
+    op(0x64, "mov", "h,h", 1, (4, 4), "", false, "Copy H into H"),
+    op(0x65, "mov", "h,l", 1, (4, 4), "", false, "Copy L into H"),
+    op(0x66, "mov", "h,m", 1, (7, 7), "", false, "Copy memory at HL into H"),
+    op(0x67, "mov", "h,a", 1, (4, 4), "", false, "Copy A into H"),
+    op(0x68, "mov", "l,b", 1, (4, 4), "", false, "Copy B into L"),
+    op(0x69, "mov", "l,c", 1, (4, 4), "", false, "Copy C into L"),
+    op(0x6A, "mov", "l,d", 1, (4, 4), "", false, "Copy D into L"),
+    op(0x6B, "mov", "l,e", 1, (4, 4), "", false, "Copy E into L"),
+    op(0x6C, "mov", "l,h", 1, (4, 4), "", false, "Copy H into L"),
+    op(0x6D, "mov", "l,l", 1, (4, 4), "", false, "Copy L into L"),
+    op(0x6E, "mov", "l,m", 1, (7, 7), "", false, "Copy memory at HL into L"),
+    op(0x6F, "mov", "l,a", 1, (4, 4), "", false, "Copy A into L"),
+    op(0x70, "mov", "m,b", 1, (7, 7), "", false, "Copy B into memory at HL"),
+    op(0x71, "mov", "m,c", 1, (7, 7), "", false, "Copy C into memory at HL"),
+    op(0x72, "mov", "m,d", 1, (7, 7), "", false, "Copy D into memory at HL"),
+    op(0x73, "mov", "m,e", 1, (7, 7), "", false, "Copy E into memory at HL"),
+    op(0x74, "mov", "m,h", 1, (7, 7), "", false, "Copy H into memory at HL"),
+    op(0x75, "mov", "m,l", 1, (7, 7), "", false, "Copy L into memory at HL"),
+    op(0x76, "hlt", "", 1, (5, 5), "", false, "Halt until an interrupt or reset"),
+    op(0x77, "mov", "m,a", 1, (7, 7), "", false, "Copy A into memory at HL"),
+    op(0x78, "mov", "a,b", 1, (4, 4), "", false, "Copy B into A"),
+    op(0x79, "mov", "a,c", 1, (4, 4), "", false, "Copy C into A"),
+    op(0x7A, "mov", "a,d", 1, (4, 4), "", false, "Copy D into A"),
+    op(0x7B, "mov", "a,e", 1, (4, 4), "", false, "Copy E into A"),
+    op(0x7C, "mov", "a,h", 1, (4, 4), "", false, "Copy H into A"),
+    op(0x7D, "mov", "a,l", 1, (4, 4), "", false, "Copy L into A"),
+    op(0x7E, "mov", "a,m", 1, (7, 7), "", false, "Copy memory at HL into A"),
+    op(0x7F, "mov", "a,a", 1, (4, 4), "", false, "Copy A into A"),
+    op(0x80, "add", "b", 1, (4, 4), "S Z K AC P V CY", false, "Add B to A"),
+    op(0x81, "add", "c", 1, (4, 4), "S Z K AC P V CY", false, "Add C to A"),
+    op(0x82, "add", "d", 1, (4, 4), "S Z K AC P V CY", false, "Add D to A"),
+    op(0x83, "add", "e", 1, (4, 4), "S Z K AC P V CY", false, "Add E to A"),
+    op(0x84, "add", "h", 1, (4, 4), "S Z K AC P V CY", false, "Add H to A"),
+    op(0x85, "add", "l", 1, (4, 4), "S Z K AC P V CY", false, "Add L to A"),
+    op(0x86, "add", "m", 1, (7, 7), "S Z K AC P V CY", false, "Add memory at HL to A"),
+    op(0x87, "add", "a", 1, (4, 4), "S Z K AC P V CY", false, "Add A to A"),
+    op(0x88, "adc", "b", 1, (4, 4), "S Z K AC P V CY", false, "Add B and carry to A"),
+    op(0x89, "adc", "c", 1, (4, 4), "S Z K AC P V CY", false, "Add C and carry to A"),
+    op(0x8A, "adc", "d", 1, (4, 4), "S Z K AC P V CY", false, "Add D and carry to A"),
+    op(0x8B, "adc", "e", 1, (4, 4), "S Z K AC P V CY", false, "Add E and carry to A"),
+    op(0x8C, "adc", "h", 1, (4, 4), "S Z K AC P V CY", false, "Add H and carry to A"),
+    op(0x8D, "adc", "l", 1, (4, 4), "S Z K AC P V CY", false, "Add L and carry to A"),
+    op(0x8E, "adc", "m", 1, (7, 7), "S Z K AC P V CY", false, "Add memory at HL and carry to A"),
+    op(0x8F, "adc", "a", 1, (4, 4), "S Z K AC P V CY", false, "Add A and carry to A"),
+    op(0x90, "sub", "b", 1, (4, 4), "S Z K AC P V CY", false, "Subtract B from A"),
+    op(0x91, "sub", "c", 1, (4, 4), "S Z K AC P V CY", false, "Subtract C from A"),
+    op(0x92, "sub", "d", 1, (4, 4), "S Z K AC P V CY", false, "Subtract D from A"),
+    op(0x93, "sub", "e", 1, (4, 4), "S Z K AC P V CY", false, "Subtract E from A"),
+    op(0x94, "sub", "h", 1, (4, 4), "S Z K AC P V CY", false, "Subtract H from A"),
+    op(0x95, "sub", "l", 1, (4, 4), "S Z K AC P V CY", false, "Subtract L from A"),
+    op(0x96, "sub", "m", 1, (7, 7), "S Z K AC P V CY", false, "Subtract memory at HL from A"),
+    op(0x97, "sub", "a", 1, (4, 4), "S Z K AC P V CY", false, "Subtract A from A"),
+    op(0x98, "sbb", "b", 1, (4, 4), "S Z K AC P V CY", false, "Subtract B and borrow from A"),
+    op(0x99, "sbb", "c", 1, (4, 4), "S Z K AC P V CY", false, "Subtract C and borrow from A"),
+    op(0x9A, "sbb", "d", 1, (4, 4), "S Z K AC P V CY", false, "Subtract D and borrow from A"),
+    op(0x9B, "sbb", "e", 1, (4, 4), "S Z K AC P V CY", false, "Subtract E and borrow from A"),
+    op(0x9C, "sbb", "h", 1, (4, 4), "S Z K AC P V CY", false, "Subtract H and borrow from A"),
+    op(0x9D, "sbb", "l", 1, (4, 4), "S Z K AC P V CY", false, "Subtract L and borrow from A"),
+    op(0x9E, "sbb", "m", 1, (7, 7), "S Z K AC P V CY", false, "Subtract memory at HL and borrow from A"),
+    op(0x9F, "sbb", "a", 1, (4, 4), "S Z K AC P V CY", false, "Subtract A and borrow from A"),
+    op(0xA0, "ana", "b", 1, (4, 4), "S Z K AC P V CY", false, "AND B with A"),
+    op(0xA1, "ana", "c", 1, (4, 4), "S Z K AC P V CY", false, "AND C with A"),
+    op(0xA2, "ana", "d", 1, (4, 4), "S Z K AC P V CY", false, "AND D with A"),
+    op(0xA3, "ana", "e", 1, (4, 4), "S Z K AC P V CY", false, "AND E with A"),
+    op(0xA4, "ana", "h", 1, (4, 4), "S Z K AC P V CY", false, "AND H with A"),
+    op(0xA5, "ana", "l", 1, (4, 4), "S Z K AC P V CY", false, "AND L with A"),
+    op(0xA6, "ana", "m", 1, (7, 7), "S Z K AC P V CY", false, "AND memory at HL with A"),
+    op(0xA7, "ana", "a", 1, (4, 4), "S Z K AC P V CY", false, "AND A with A"),
+    op(0xA8, "xra", "b", 1, (4, 4), "S Z K AC P V CY", false, "Exclusive OR B with A"),
+    op(0xA9, "xra", "c", 1, (4, 4), "S Z K AC P V CY", false, "Exclusive OR C with A"),
+    op(0xAA, "xra", "d", 1, (4, 4), "S Z K AC P V CY", false, "Exclusive OR D with A"),
+    op(0xAB, "xra", "e", 1, (4, 4), "S Z K AC P V CY", false, "Exclusive OR E with A"),
+    op(0xAC, "xra", "h", 1, (4, 4), "S Z K AC P V CY", false, "Exclusive OR H with A"),
+    op(0xAD, "xra", "l", 1, (4, 4), "S Z K AC P V CY", false, "Exclusive OR L with A"),
+    op(0xAE, "xra", "m", 1, (7, 7), "S Z K AC P V CY", false, "Exclusive OR memory at HL with A"),
+    op(0xAF, "xra", "a", 1, (4, 4), "S Z K AC P V CY", false, "Exclusive OR A with A"),
+    op(0xB0, "ora", "b", 1, (4, 4), "S Z K AC P V CY", false, "OR B with A"),
+    op(0xB1, "ora", "c", 1, (4, 4), "S Z K AC P V CY", false, "OR C with A"),
+    op(0xB2, "ora", "d", 1, (4, 4), "S Z K AC P V CY", false, "OR D with A"),
+    op(0xB3, "ora", "e", 1, (4, 4), "S Z K AC P V CY", false, "OR E with A"),
+    op(0xB4, "ora", "h", 1, (4, 4), "S Z K AC P V CY", false, "OR H with A"),
+    op(0xB5, "ora", "l", 1, (4, 4), "S Z K AC P V CY", false, "OR L with A"),
+    op(0xB6, "ora", "m", 1, (7, 7), "S Z K AC P V CY", false, "OR memory at HL with A"),
+    op(0xB7, "ora", "a", 1, (4, 4), "S Z K AC P V CY", false, "OR A with A"),
+    op(0xB8, "cmp", "b", 1, (4, 4), "S Z K AC P V CY", false, "Compare B with A"),
+    op(0xB9, "cmp", "c", 1, (4, 4), "S Z K AC P V CY", false, "Compare C with A"),
+    op(0xBA, "cmp", "d", 1, (4, 4), "S Z K AC P V CY", false, "Compare D with A"),
+    op(0xBB, "cmp", "e", 1, (4, 4), "S Z K AC P V CY", false, "Compare E with A"),
+    op(0xBC, "cmp", "h", 1, (4, 4), "S Z K AC P V CY", false, "Compare H with A"),
+    op(0xBD, "cmp", "l", 1, (4, 4), "S Z K AC P V CY", false, "Compare L with A"),
+    op(0xBE, "cmp", "m", 1, (7, 7), "S Z K AC P V CY", false, "Compare memory at HL with A"),
+    op(0xBF, "cmp", "a", 1, (4, 4), "S Z K AC P V CY", false, "Compare A with A"),
+    op(0xC0, "rnz", "", 1, (12, 6), "", false, "Return if not zero"),
+    op(0xC1, "pop", "b", 1, (10, 10), "", false, "Pop BC off the stack"),
+    op(0xC2, "jnz", "a16", 3, (10, 7), "", false, "Jump if not zero"),
+    op(0xC3, "jmp", "a16", 3, (10, 10), "", false, "Jump"),
+    op(0xC4, "cnz", "a16", 3, (18, 9), "", false, "Call if not zero"),
+    op(0xC5, "push", "b", 1, (12, 12), "", false, "Push BC onto the stack"),
+    op(0xC6, "adi", "d8", 2, (7, 7), "S Z K AC P V CY", false, "Add immediate data to A"),
+    op(0xC7, "rst", "0", 1, (12, 12), "", false, "Restart at 00h"),
+    op(0xC8, "rz", "", 1, (12, 6), "", false, "Return if zero"),
+    op(0xC9, "ret", "", 1, (10, 10), "", false, "Return"),
+    op(0xCA, "jz", "a16", 3, (10, 7), "", false, "Jump if zero"),
+    op(0xCB, "rstv", "", 1, (12, 6), "", true, "Restart at 0040h on overflow"),
+    op(0xCC, "cz", "a16", 3, (18, 9), "", false, "Call if zero"),
+    op(0xCD, "call", "a16", 3, (18, 18), "", false, "Call"),
+    op(0xCE, "aci", "d8", 2, (7, 7), "S Z K AC P V CY", false, "Add immediate data and carry to A"),
+    op(0xCF, "rst", "1", 1, (12, 12), "", false, "Restart at 08h"),
+    op(0xD0, "rnc", "", 1, (12, 6), "", false, "Return if no carry"),
+    op(0xD1, "pop", "d", 1, (10, 10), "", false, "Pop DE off the stack"),
+    op(0xD2, "jnc", "a16", 3, (10, 7), "", false, "Jump if no carry"),
+    op(0xD3, "out", "d8", 2, (10, 10), "", false, "Write A to a port"),
+    op(0xD4, "cnc", "a16", 3, (18, 9), "", false, "Call if no carry"),
+    op(0xD5, "push", "d", 1, (12, 12), "", false, "Push DE onto the stack"),
+    op(0xD6, "sui", "d8", 2, (7, 7), "S Z K AC P V CY", false, "Subtract immediate data from A"),
+    op(0xD7, "rst", "2", 1, (12, 12), "", false, "Restart at 10h"),
+    op(0xD8, "rc", "", 1, (12, 6), "", false, "Return if carry"),
+    op(0xD9, "shlx", "", 1, (10, 10), "", true, "Store HL at the address in DE"),
+    op(0xDA, "jc", "a16", 3, (10, 7), "", false, "Jump if carry"),
+    op(0xDB, "in", "d8", 2, (10, 10), "", false, "Read a port into A"),
+    op(0xDC, "cc", "a16", 3, (18, 9), "", false, "Call if carry"),
+    op(0xDD, "jnk", "a16", 3, (10, 7), "", true, "Jump if not K"),
+    op(0xDE, "sbi", "d8", 2, (7, 7), "S Z K AC P V CY", false, "Subtract immediate data and borrow from A"),
+    op(0xDF, "rst", "3", 1, (12, 12), "", false, "Restart at 18h"),
+    op(0xE0, "rpo", "", 1, (12, 6), "", false, "Return if parity odd"),
+    op(0xE1, "pop", "h", 1, (10, 10), "", false, "Pop HL off the stack"),
+    op(0xE2, "jpo", "a16", 3, (10, 7), "", false, "Jump if parity odd"),
+    op(0xE3, "xthl", "", 1, (16, 16), "", false, "Exchange HL with the top of the stack"),
+    op(0xE4, "cpo", "a16", 3, (18, 9), "", false, "Call if parity odd"),
+    op(0xE5, "push", "h", 1, (12, 12), "", false, "Push HL onto the stack"),
+    op(0xE6, "ani", "d8", 2, (7, 7), "S Z K AC P V CY", false, "AND immediate data with A"),
+    op(0xE7, "rst", "4", 1, (12, 12), "", false, "Restart at 20h"),
+    op(0xE8, "rpe", "", 1, (12, 6), "", false, "Return if parity even"),
+    op(0xE9, "pchl", "", 1, (6, 6), "", false, "Jump to the address in HL"),
+    op(0xEA, "jpe", "a16", 3, (10, 7), "", false, "Jump if parity even"),
+    op(0xEB, "xchg", "", 1, (4, 4), "", false, "Exchange HL with DE"),
+    op(0xEC, "cpe", "a16", 3, (18, 9), "", false, "Call if parity even"),
+    op(0xED, "lhlx", "", 1, (10, 10), "", true, "Load HL from the address in DE"),
+    op(0xEE, "xri", "d8", 2, (7, 7), "S Z K AC P V CY", false, "Exclusive OR immediate data with A"),
+    op(0xEF, "rst", "5", 1, (12, 12), "", false, "Restart at 28h"),
+    op(0xF0, "rp", "", 1, (12, 6), "", false, "Return if plus"),
+    op(0xF1, "pop", "psw", 1, (10, 10), "S Z K AC P V CY", false, "Pop A and flags off the stack"),
+    op(0xF2, "jp", "a16", 3, (10, 7), "", false, "Jump if plus"),
+    op(0xF3, "di", "", 1, (4, 4), "", false, "Disable interrupts"),
+    op(0xF4, "cp", "a16", 3, (18, 9), "", false, "Call if plus"),
+    op(0xF5, "push", "psw", 1, (12, 12), "", false, "Push A and flags onto the stack"),
+    op(0xF6, "ori", "d8", 2, (7, 7), "S Z K AC P V CY", false, "OR immediate data with A"),
+    op(0xF7, "rst", "6", 1, (12, 12), "", false, "Restart at 30h"),
+    op(0xF8, "rm", "", 1, (12, 6), "", false, "Return if minus"),
+    op(0xF9, "sphl", "", 1, (6, 6), "", false, "Copy HL into SP"),
+    op(0xFA, "jm", "a16", 3, (10, 7), "", false, "Jump if minus"),
+    op(0xFB, "ei", "", 1, (4, 4), "", false, "Enable interrupts after the next instruction"),
+    op(0xFC, "cm", "a16", 3, (18, 9), "", false, "Call if minus"),
+    op(0xFD, "jk", "a16", 3, (10, 7), "", true, "Jump if K"),
+    op(0xFE, "cpi", "d8", 2, (7, 7), "S Z K AC P V CY", false, "Compare immediate data with A"),
+    op(0xFF, "rst", "7", 1, (12, 12), "", false, "Restart at 38h"),
+];
+
+impl OpcodeInfo {
+    /// operands that are registers, register pairs or RST numbers.
+    pub fn register_operands(&self) -> impl Iterator<Item = &'static str> {
+        self.operands
+            .split(',')
+            .filter(|o| !o.is_empty() && !matches!(*o, "d8" | "d16" | "a16"))
+    }
+
+    /// name the assembler looks the opcode up by, e.g. "mov_a_b" or "lxi_sp".
+    pub fn asm_name(&self) -> String {
+        let mut res = self.mnemonic.to_string();
+        for r in self.register_operands() {
+            res.push('_');
+            res.push_str(r);
+        }
+        res
+    }
+
+    /// the operands in assembler syntax with the data from `bytes`,
+    /// which start with the opcode. data is written in hex as "0ah".
+    pub fn format_operands(&self, bytes: &[u8]) -> String {
+        let byte = |i: usize| bytes.get(i).copied().unwrap_or(0);
+        match self.length {
+            3 => {
+                let val = (byte(2) as u16) << 8 | byte(1) as u16;
+                let ops = self.operands.trim_end_matches("d16");
+                format!("{}{:04x}h", ops.trim_end_matches("a16"), val)
+            }
+            2 => format!("{}{:02x}h", self.operands.trim_end_matches("d8"), byte(1)),
+            _ => self.operands.to_string(),
+        }
+    }
+
+    /// the instruction in assembler syntax, e.g. "mvi a,0ah".
+    pub fn format(&self, bytes: &[u8]) -> String {
+        let operands = self.format_operands(bytes);
+        if operands.is_empty() {
+            self.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.mnemonic, operands)
+        }
+    }
+}
+
+pub fn info(opcode: u8) -> &'static OpcodeInfo {
+    &OPCODES[opcode as usize]
+}
+
+/// number of bytes of the instruction starting with `opcode`.
+pub fn instruction_length(opcode: u8) -> usize {
+    OPCODES[opcode as usize].length as usize
+}
+
+/// find an opcode by its assembler name, see `OpcodeInfo::asm_name`.
+pub fn find(asm_name: &str) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|o| o.asm_name() == asm_name)
+}
+
+/// number of register operands and of data bytes that follow `mnemonic`.
+pub fn operand_counts(mnemonic: &str) -> Option<(usize, usize)> {
+    OPCODES
+        .iter()
+        .find(|o| o.mnemonic == mnemonic)
+        .map(|o| (o.register_operands().count(), o.length as usize - 1))
+}
+
+/// metadata of one opcode for an opcode reference.
+#[wasm_bindgen]
+pub fn get_opcode_info(opcode: u8) -> JsValue {
+    serde_wasm_bindgen::to_value(info(opcode)).unwrap()
+}
+
+/// metadata of all 256 opcodes.
+#[wasm_bindgen]
+pub fn get_opcode_table() -> JsValue {
+    serde_wasm_bindgen::to_value(&OPCODES[..]).unwrap()
+}
@@ -0,0 +1,55 @@
+/// Decode an asynchronous serial waveform.
+///
+/// `samples` are `(cycle, level)` pairs in order, each giving the level of the
+/// line from that cycle onwards. The line is taken to be idle (high) before
+/// the first sample. Frames are a low start bit, 8 data bits LSB first and a
+/// high stop bit. Frames with a bad stop bit are dropped.
+pub fn decode_async(samples: &[(u64, bool)], baud: u32, clock_hz: u32) -> Vec<u8> {
+    let mut res = Vec::new();
+    if baud == 0 {
+        return res;
+    }
+    let bit = clock_hz as f64 / baud as f64;
+
+    let level_at = |t: f64| -> bool {
+        match samples.partition_point(|&(c, _)| c as f64 <= t) {
+            0 => true,
+            i => samples[i - 1].1,
+        }
+    };
+
+    let mut prev = true;
+    let mut i = 0;
+    while i < samples.len() {
+        let (cycle, level) = samples[i];
+        if !prev || level {
+            prev = level;
+            i += 1;
+            continue;
+        }
+
+        // falling edge, check that it is a start bit and not a glitch
+        let start = cycle as f64;
+        if level_at(start + bit / 2.0) {
+            prev = level;
+            i += 1;
+            continue;
+        }
+
+        let mut byte = 0;
+        for n in 0..8 {
+            if level_at(start + bit * (1.5 + n as f64)) {
+                byte |= 1 << n;
+            }
+        }
+        let stop = start + bit * 9.5;
+        if level_at(stop) {
+            res.push(byte);
+        }
+
+        // continue looking for a start bit from the middle of the stop bit
+        i = samples.partition_point(|&(c, _)| c as f64 <= stop);
+        prev = level_at(stop);
+    }
+    res
+}
@@ -0,0 +1,112 @@
+use super::bus::Bus;
+use super::loader::{hex_bytes, Image, Segment};
+use wasm_bindgen::prelude::*;
+
+/// data bytes per record written.
+const RECORD_LEN: usize = 16;
+
+fn checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |s, b| s.wrapping_add(*b))
+}
+
+/// read Motorola S-record text into segments, S1 records that follow each
+/// other are joined. the S0 header and S5/S6 counts are checked and skipped,
+/// the S9 record ends the file and gives the entry point. the 24 and 32 bit
+/// records are refused.
+pub fn parse(text: &str) -> Result<Image, String> {
+    let mut image = Image::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let n = n + 1;
+        let (kind, rec) = match line.strip_prefix('S') {
+            Some(r) if !r.is_empty() && r.is_char_boundary(1) => r.split_at(1),
+            _ => return Err(format!("Line {}: record does not start with 'S'", n)),
+        };
+        let bytes = match hex_bytes(rec) {
+            Some(b) => b,
+            None => return Err(format!("Line {}: bad hex digits", n)),
+        };
+        if bytes.len() < 4 || bytes.len() != bytes[0] as usize + 1 {
+            return Err(format!(
+                "Line {}: record length does not match its count",
+                n
+            ));
+        }
+        let (body, sum) = bytes.split_at(bytes.len() - 1);
+        if checksum(body) != sum[0] {
+            return Err(format!(
+                "Line {}: bad checksum {:02X}, expected {:02X}",
+                n,
+                sum[0],
+                checksum(body)
+            ));
+        }
+        let addr = (body[1] as u16) << 8 | body[2] as u16;
+        let data = &body[3..];
+        match kind {
+            "0" | "5" | "6" => {}
+            "1" => {
+                if addr as u32 + data.len() as u32 > 0x10000 {
+                    return Err(format!("Line {}: data past address 0xFFFF", n));
+                }
+                image.add(addr, data);
+            }
+            "9" => {
+                image.entry = Some(addr);
+                return Ok(image);
+            }
+            "2" | "3" | "7" | "8" => {
+                return Err(format!(
+                    "Line {}: S{} records address more than 64K",
+                    n, kind
+                ))
+            }
+            t => return Err(format!("Line {}: unknown record type S{}", n, t)),
+        }
+    }
+    Err("Missing S9 termination record".to_string())
+}
+
+fn record(kind: u8, addr: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8 + 3, (addr >> 8) as u8, addr as u8];
+    bytes.extend_from_slice(data);
+    bytes.push(checksum(&bytes));
+    let digits: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("S{}{}\n", kind, digits.concat())
+}
+
+/// S19 text of the image: an S0 record with `header`, S1 records of 16 data
+/// bytes and an S9 record with the entry point, 0 if there is none.
+pub fn write(image: &Image, header: &str) -> String {
+    let mut res = record(0, 0, header.as_bytes());
+    for Segment { origin, data } in &image.segments {
+        for (i, chunk) in data.chunks(RECORD_LEN).enumerate() {
+            let addr = origin.wrapping_add((i * RECORD_LEN) as u16);
+            res.push_str(&record(1, addr, chunk));
+        }
+    }
+    res.push_str(&record(9, image.entry.unwrap_or(0), &[]));
+    res
+}
+
+/// S19 text of `start..=end` of memory, read without side effects.
+pub fn write_range<B: Bus + ?Sized>(memory: &B, start: u16, end: u16) -> String {
+    let data: Vec<u8> = (start..=end).map(|a| memory.peek(a)).collect();
+    write(&Image::from_bytes(&data, start), "")
+}
+
+/// assemble `code` into S19 from address 0, which is also the entry point.
+pub fn assemble(code: &str) -> Result<String, String> {
+    let (bin, _) = super::asm8085::assemble(code)?;
+    let mut image = Image::from_bytes(&bin, 0);
+    image.entry = Some(0);
+    Ok(write(&image, ""))
+}
+
+#[wasm_bindgen]
+pub fn assemble_srec_wasm(code: &str) -> Result<String, JsValue> {
+    Ok(assemble(code)?)
+}
@@ -0,0 +1,87 @@
+use super::bus::DeviceState;
+use super::interrupt::Interrupts;
+use super::ioport::IoPort;
+use super::trace::Registers;
+use serde::{Deserialize, Serialize};
+
+/// Version of the save state format, bumped whenever `SaveState` changes.
+pub const STATE_VERSION: u32 = 4;
+
+/// first bytes of a binary save state.
+const MAGIC: &[u8; 4] = b"PP85";
+
+/// Everything needed to resume the machine where it was saved.
+/// debugging aids (tracer, breakpoints, history, bus mode) are not saved.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveState {
+    pub version: u32,
+    pub registers: Registers,
+    pub ir: u8,
+    pub ie: bool,
+    pub hlt: bool,
+    pub ei_delay: bool,
+    pub cycles: u32, // T-states left of the instruction being run by `run`
+    pub total_cycles: u64,
+    pub clock_hz: u32,
+    pub interrupts: Interrupts,
+    pub sid: bool,
+    pub sod: bool,
+    pub undocumented: bool,
+    pub memory: Vec<DeviceState>, // contents of the mapped devices, in address order
+    pub io_ports: Vec<IoPort>,    // sorted by address
+    pub io_devices: Vec<DeviceState>, // contents of the other I/O devices, in port order
+}
+
+impl SaveState {
+    /// magic, version as little endian u32 and the bincode encoded state.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut res = MAGIC.to_vec();
+        res.extend_from_slice(&self.version.to_le_bytes());
+        res.extend(bincode::serialize(self).unwrap());
+        res
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<SaveState, String> {
+        if data.len() < 8 || &data[..4] != MAGIC {
+            return Err("Not a save state".to_string());
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&data[4..8]);
+        check_version(u32::from_le_bytes(version))?;
+        bincode::deserialize(&data[8..]).map_err(|e| format!("Corrupt save state: {}", e))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<SaveState, String> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| format!("Corrupt save state: {}", e))?;
+        match value.get("version").and_then(|v| v.as_u64()) {
+            Some(v) => check_version(v as u32)?,
+            None => return Err("Not a save state".to_string()),
+        }
+        serde_json::from_value(value).map_err(|e| format!("Corrupt save state: {}", e))
+    }
+}
+
+impl SaveState {
+    /// reject states that would make the cpu panic.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.clock_hz == 0 {
+            return Err("Save state has a clock of 0 Hz".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn check_version(version: u32) -> Result<(), String> {
+    if version != STATE_VERSION {
+        return Err(format!(
+            "Save state version {} is not supported, expected {}",
+            version, STATE_VERSION
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use super::interrupt::Interrupt;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// Registers and flags of the cpu.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "A:{:02x} F:{:02x} B:{:02x} C:{:02x} D:{:02x} E:{:02x} H:{:02x} L:{:02x} SP:{:04x}",
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l, self.sp
+        )
+    }
+}
+
+/// A memory or I/O access, for I/O `address` is the port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Access {
+    pub address: u16,
+    pub data: u8,
+}
+
+/// One executed instruction, or an acknowledged interrupt.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub bytes: Vec<u8>, // empty for an interrupt
+    pub mnemonic: String,
+    pub interrupt: Option<Interrupt>,
+    pub before: Registers,
+    pub after: Registers,
+    pub mem_reads: Vec<Access>, // not including the instruction bytes
+    pub mem_writes: Vec<Access>,
+    pub io_reads: Vec<Access>,
+    pub io_writes: Vec<Access>,
+    pub cycles: u8,
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(
+            f,
+            "{:04x}  {:<8}  {:<14}  {}  ->  {}",
+            self.pc,
+            bytes.join(" "),
+            self.mnemonic,
+            self.before,
+            self.after
+        )?;
+        for (name, accesses) in [
+            ("R", &self.mem_reads),
+            ("W", &self.mem_writes),
+            ("IN", &self.io_reads),
+            ("OUT", &self.io_writes),
+        ] {
+            for a in accesses.iter() {
+                write!(f, "  {} {:04x}={:02x}", name, a.address, a.data)?;
+            }
+        }
+        write!(f, "  {}T", self.cycles)
+    }
+}
+
+/// Where the trace goes.
+pub enum TraceOutput {
+    /// keep the last `capacity` records in memory.
+    RingBuffer {
+        records: VecDeque<TraceRecord>,
+        capacity: usize,
+    },
+    /// one line of text per instruction.
+    Text(Box<dyn Write + Send>),
+    /// one JSON object per line.
+    JsonLines(Box<dyn Write + Send>),
+}
+
+/// Records executed instructions, see `PP8085::set_tracer`.
+pub struct Tracer {
+    output: TraceOutput,
+    error: Option<io::Error>, // first write error, writing stops after it
+}
+
+impl Tracer {
+    pub fn new(output: TraceOutput) -> Tracer {
+        Tracer {
+            output,
+            error: None,
+        }
+    }
+
+    pub fn ring_buffer(capacity: usize) -> Tracer {
+        Tracer::new(TraceOutput::RingBuffer {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+        })
+    }
+
+    pub fn text<W: Write + Send + 'static>(w: W) -> Tracer {
+        Tracer::new(TraceOutput::Text(Box::new(w)))
+    }
+
+    pub fn json_lines<W: Write + Send + 'static>(w: W) -> Tracer {
+        Tracer::new(TraceOutput::JsonLines(Box::new(w)))
+    }
+
+    pub fn text_file(path: &str) -> io::Result<Tracer> {
+        Ok(Tracer::text(BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn json_lines_file(path: &str) -> io::Result<Tracer> {
+        Ok(Tracer::json_lines(BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn record(&mut self, record: TraceRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        let res = match &mut self.output {
+            TraceOutput::RingBuffer { records, capacity } => {
+                if *capacity > 0 {
+                    if records.len() == *capacity {
+                        records.pop_front();
+                    }
+                    records.push_back(record);
+                }
+                Ok(())
+            }
+            TraceOutput::Text(w) => writeln!(w, "{}", record),
+            TraceOutput::JsonLines(w) => serde_json::to_writer(&mut *w, &record)
+                .map_err(io::Error::from)
+                .and_then(|_| writeln!(w)),
+        };
+        if let Err(e) = res {
+            self.error = Some(e);
+        }
+    }
+
+    /// records kept in the ring buffer, oldest first.
+    pub fn records(&self) -> Vec<&TraceRecord> {
+        match &self.output {
+            TraceOutput::RingBuffer { records, .. } => records.iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if let TraceOutput::RingBuffer { records, .. } = &mut self.output {
+            records.clear();
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.output {
+            TraceOutput::Text(w) | TraceOutput::JsonLines(w) => w.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// the write error that stopped the trace, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
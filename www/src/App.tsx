@@ -65,8 +65,8 @@ const darkTheme = createTheme({
 });
 
 const mem_size = 1024*8;
+const history_depth = 1000;
 let wasm: typeof import("pp8085");
-let memory: any;
 
 class App extends React.Component<{}, wasm_state>{
   run_interval: NodeJS.Timer | null = null;
@@ -80,7 +80,9 @@ class App extends React.Component<{}, wasm_state>{
     this.handleRun = this.handleRun.bind(this);
     this.handlePause = this.handlePause.bind(this);
     this.handleCompile = this.handleCompile.bind(this);
+    this.handleImport = this.handleImport.bind(this);
     this.handleStep = this.handleStep.bind(this);
+    this.handleStepBack = this.handleStepBack.bind(this);
     this.handleReset = this.handleReset.bind(this);
     this.handleSpeed = this.handleSpeed.bind(this);
     this.handleWarnClose = this.handleWarnClose.bind(this);
@@ -92,8 +94,8 @@ class App extends React.Component<{}, wasm_state>{
 
   async componentDidMount () {
     wasm = await import('pp8085');
-    memory = await import('pp8085/pp8085_lib_bg.wasm')
     const cpu = wasm.PP8085.new();
+    cpu.set_history_depth(history_depth);
     const bin = wasm.parse_wasm(code);
     const rom = wasm.Memory.new_from_js(bin, mem_size);
     cpu.load_memory(rom);
@@ -118,9 +120,10 @@ class App extends React.Component<{}, wasm_state>{
   }
   
   handleCompile() {
-    let bin: Uint8Array;
+    let rom;
     try {
-      bin = this.state.parse_code(this.state.source);
+      const bin = this.state.parse_code(this.state.source);
+      rom = wasm.Memory.new_from_js(bin, mem_size);
     } catch(err) {
       this.setState(state=> {
         return {
@@ -132,7 +135,6 @@ class App extends React.Component<{}, wasm_state>{
       return;
     }
 
-    const rom = wasm.Memory.new_from_js(bin, mem_size);
     this.state.cpu.load_memory(rom);
     this.state.cpu.reset();
     if (this.run_interval != null) {
@@ -142,15 +144,59 @@ class App extends React.Component<{}, wasm_state>{
     this.setState(state => state);
   }
 
+  async handleImport(e: React.ChangeEvent<HTMLInputElement>) {
+    const file = e.target.files?.[0];
+    e.target.value = "";
+    if (file == null) {
+      return;
+    }
+    if (this.run_interval != null) {
+      clearInterval(this.run_interval);
+      this.run_interval = null;
+    }
+    this.state.cpu.reset();
+    try {
+      const text = await file.text();
+      if (text.trimStart().startsWith("S")) {
+        this.state.cpu.load_srec_wasm(text);
+      } else {
+        this.state.cpu.load_hex_wasm(text);
+      }
+    } catch(err) {
+      this.setState(state=> {
+        return {
+          ...state,
+          warn_open: true,
+          warning: err as string
+        }
+      })
+      return;
+    }
+    this.setState(state => state);
+  }
+
   handleRun() {
     if (this.run_interval == null) {
       this.run_interval = setInterval(()=> {
-        this.handleStep();
-        if (this.state.cpu.get_hlt() && this.run_interval != null) {
-          clearInterval(this.run_interval);
-          this.run_interval = null;
-          this.setState(state => state);
+        let reason;
+        try {
+          reason = this.state.cpu.run_until_break_wasm(1);
+        } catch(err) {
+          this.handlePause();
+          this.setState(state=> {
+            return {
+              ...state,
+              warn_open: true,
+              warning: err as string
+            }
+          })
+          return;
         }
+        // stop on HLT or a breakpoint
+        if (reason !== "StepLimit") {
+          this.handlePause();
+        }
+        this.setState(state => state);
       }, this.run_speed);
       this.setState(state => state);
     }
@@ -165,7 +211,27 @@ class App extends React.Component<{}, wasm_state>{
   }
 
   handleStep() {
-    this.state.cpu.run_next();
+    try {
+      this.state.cpu.run_next_wasm();
+    } catch(err) {
+      if (this.run_interval != null) {
+        clearInterval(this.run_interval);
+        this.run_interval = null;
+      }
+      this.setState(state=> {
+        return {
+          ...state,
+          warn_open: true,
+          warning: err as string
+        }
+      })
+      return;
+    }
+    this.setState(state => state);
+  }
+
+  handleStepBack() {
+    this.state.cpu.step_back();
     this.setState(state => state);
   }
 
@@ -200,13 +266,22 @@ class App extends React.Component<{}, wasm_state>{
   }
 
   handleIoEdit(addr: number, data: number) {
-    this.state.cpu.write_io(addr, data);
+    this.state.cpu.set_io_input(addr, data);
     this.setState(state=>state);
   }
 
   handleIOAdd(addr:number) {
     if (addr <= 0xff) {
-      this.state.cpu.add_io_port(addr);
+      if (!this.state.cpu.add_io_port(addr)) {
+        this.setState(state=> {
+          return {
+            ...state,
+            warn_open: true,
+            warning: `Port 0x${Number(addr).toString(16)} is used by another device`
+          }
+        })
+        return;
+      }
       this.setState(state=>state);
     }
   }
@@ -234,6 +309,13 @@ class App extends React.Component<{}, wasm_state>{
                     <Button variant="contained" onClick={this.handleCompile}>Compile & Load</Button>
                   </Box>
 
+                  <Box m={2}>
+                    <Button variant="outlined" component="label">
+                      Import HEX/S19
+                      <input type="file" accept=".hex,.ihx,.s19,.srec,.mot" hidden onChange={this.handleImport}/>
+                    </Button>
+                  </Box>
+
                   <Box m={2}>
                     <Button variant="contained" color="warning" onClick={this.handleReset}>Reset</Button>
                   </Box>
@@ -242,6 +324,10 @@ class App extends React.Component<{}, wasm_state>{
                     <Button variant="contained" color="primary" onClick={this.handleStep} disabled={this.run_interval != null || this.state.cpu.get_hlt()}>Step</Button>
                    </Box>
 
+                  <Box m={2}>
+                    <Button variant="contained" color="primary" onClick={this.handleStepBack} disabled={this.run_interval != null || this.state.cpu.get_history_len() === 0}>Step Back</Button>
+                  </Box>
+
                   <Box m={2}>
                     <ButtonGroup variant="outlined">
                       <Button color="success" onClick={this.handleRun} disabled={this.state.cpu.get_hlt() || this.run_interval != null}>Run</Button>
@@ -276,7 +362,7 @@ class App extends React.Component<{}, wasm_state>{
                 <Status cpu={this.state.cpu}/>
               </Box>
               <Box m={1}>
-                <MemTable ptr={this.state.cpu.get_memory_ptr()} memory={memory.memory} size={mem_size}/>
+                <MemTable size={mem_size} cpu={this.state.cpu}/>
               </Box>
               <Box m={1}>
                 <IoPorts handleEdit={this.handleIoEdit} handleAdd={this.handleIOAdd} handleRemove={this.handleIORemove} ports={this.state.cpu.get_io_ports()}/>
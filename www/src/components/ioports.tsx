@@ -2,31 +2,39 @@ import {Paper, Typography, Button, Box} from "@mui/material"
 import { DataGrid, GridColDef, GridCellEditCommitParams, MuiEvent, GridCallbackDetails, MuiBaseEvent} from '@mui/x-data-grid';
 import React, { useState } from "react";
 
-import PortDialog from './portdialog'
+import PortDialog, {PortLatchDialog} from './portdialog'
 
 interface port {
     addr: number,
-    data: number,
+    input: number,
+    output: number,
 }
 
 function IoPorts (props: {ports: any, handleEdit: (addr: number, data:number) => void, handleAdd: (addr: number)=>void, handleRemove:(addr: number)=>void}) {
     const {ports, handleEdit, handleAdd, handleRemove} = props;
-    let rows: {id: number, addr: string, data: string}[] = [];
+    let rows: {id: number, addr: string, input: string, output: string}[] = [];
     const [addopen, setAddOpen] = useState<boolean>(false);
     const [removeopen, setRemoveOpen] = useState<boolean>(false);
+    const [latchaddr, setLatchAddr] = useState<number | null>(null);
 
     Object.entries(ports).map(([_key, value])=>
       {
-        rows.push({id: (value as port).addr, addr: '0x'+(value as port).addr.toString(16), data: '0x'+(value as port).data.toString(16)});
+        const p = value as port;
+        rows.push({id: p.addr, addr: '0x'+p.addr.toString(16), input: '0x'+p.input.toString(16), output: '0x'+p.output.toString(16)});
         return null;
       }
     );
+    const latchport = (Object.values(ports) as port[]).find(p => p.addr === latchaddr) ?? null;
 
-    const columns: GridColDef[] = [{ field: 'addr', headerName: 'Address', width: 180, editable: false },
-    { field: 'data', headerName: 'Data', width: 180, editable: true}];
+    const columns: GridColDef[] = [{ field: 'addr', headerName: 'Address', width: 120, editable: false },
+    { field: 'input', headerName: 'Input', width: 120, editable: true},
+    { field: 'output', headerName: 'Output', width: 120, editable: false}];
 
     const handleChange = (params: GridCellEditCommitParams, _event: MuiEvent<MuiBaseEvent>, _details: GridCallbackDetails) => {
-      handleEdit(params.id as number, params.value as number);
+      const data = parseInt(String(params.value), 16);
+      if (!isNaN(data)) {
+        handleEdit(params.id as number, data & 0xff);
+      }
     }
 
     const handleAddClose = () => {setAddOpen(false)};
@@ -39,7 +47,7 @@ function IoPorts (props: {ports: any, handleEdit: (addr: number, data:number) =>
       <Paper elevation={3} sx={{p:2}}>
         <Typography variant="h4" align="left">I/O Ports</Typography>
         <Typography variant="subtitle1" align="left" color="primary">8085 only support I/O ports from 0x00 to 0xff</Typography>
-        <Typography variant="subtitle2" align="left" color="secondary">Data and Addresses are represented in HEX</Typography>
+        <Typography variant="subtitle2" align="left" color="secondary">Data and Addresses are represented in HEX, IN reads the input and OUT writes the output. Double click a port for its switches and LEDs</Typography>
         <DataGrid
           autoHeight
           disableColumnMenu
@@ -47,6 +55,7 @@ function IoPorts (props: {ports: any, handleEdit: (addr: number, data:number) =>
           columns={columns}
           rows={rows}
           onCellEditCommit={handleChange}
+          onRowDoubleClick={(params) => setLatchAddr(params.id as number)}
           rowsPerPageOptions={[5, 10, 15]}
         />
 
@@ -57,6 +66,7 @@ function IoPorts (props: {ports: any, handleEdit: (addr: number, data:number) =>
 
         <PortDialog description="Add a new I/O port" btn="Add" open={addopen} handleClose={handleAddClose} handleCommit={handleAddCommit}/>
         <PortDialog description="Remove I/O port" btn="Remove" open={removeopen} handleClose={handleRemoveClose} handleCommit={handleRemoveCommit}/>
+        <PortLatchDialog port={latchport} open={latchport != null} handleClose={()=>setLatchAddr(null)} handleInput={handleEdit}/>
       </Paper>
     )
 }
@@ -1,13 +1,22 @@
 import {useState} from 'react'
 import {Typography, Table, TableBody, TableCell, TableRow, TableContainer, Paper, TableHead, TablePagination, TableFooter} from '@mui/material'
 
-function MemTable(props: {memory: any, ptr: number, size: number}) {
-    const d = new Uint8Array(props.memory.buffer, props.ptr, props.size);
-    const data = Array.from(d);
-
+function MemTable(props: {size: number, cpu: any}) {
     const [page, setPage] = useState<number>(0);
     const [rowsPerPage, setRowsPerPage] = useState<number>(10);
 
+    // read through the memory map, so ROM and devices show what the cpu sees
+    const start = page*rowsPerPage;
+    const end = Math.min(start+rowsPerPage, props.size)-1;
+    const data: number[] = start <= end ? Array.from(props.cpu.peek_memory(start, end-start+1)) : [];
+    // decoded from the start of the page, bytes inside an instruction show none
+    const instructions = new Map<number, string>();
+    if (start <= end) {
+        for (const ins of props.cpu.get_disassembly(start, end)) {
+            instructions.set(ins.address, ins.operands ? `${ins.mnemonic} ${ins.operands}` : ins.mnemonic);
+        }
+    }
+
     const handleChangePage = (
         _event: React.MouseEvent<HTMLButtonElement> | null,
         newPage: number,
@@ -37,13 +46,15 @@ function MemTable(props: {memory: any, ptr: number, size: number}) {
                         <TableRow>
                             <TableCell>Address</TableCell>
                             <TableCell>Byte</TableCell>
+                            <TableCell>Instruction</TableCell>
                         </TableRow>
                     </TableHead>
                     <TableBody>
-                        {data.slice(page*rowsPerPage, page*rowsPerPage+rowsPerPage).map((n, i:number) => (
+                        {data.map((n, i:number) => (
                             <TableRow key={page*rowsPerPage+i}>
                                 <TableCell>{(page*rowsPerPage+i).toString(16).toUpperCase()}</TableCell>
                                 <TableCell>{n.toString(16).toUpperCase()}</TableCell>
+                                <TableCell>{instructions.get(page*rowsPerPage+i) ?? ""}</TableCell>
                             </TableRow>
                         ))}
                     </TableBody>
@@ -1,5 +1,5 @@
 import {useState} from 'react'
-import {Button, TextField, Dialog, DialogActions, DialogContent, DialogContentText, DialogTitle} from '@mui/material';
+import {Box, Button, Switch, TextField, Typography, Dialog, DialogActions, DialogContent, DialogContentText, DialogTitle} from '@mui/material';
 
 export default function PortDialog(props: {description: string, open: boolean, handleClose: ()=>void, handleCommit: (addr: number)=>void, btn: string}) {
   const {description, open, handleClose, handleCommit, btn} = props;
@@ -32,3 +32,51 @@ export default function PortDialog(props: {description: string, open: boolean, h
     </div>
   );
 }
+
+interface latches {
+  addr: number,
+  input: number,
+  output: number,
+}
+
+const bits = [7, 6, 5, 4, 3, 2, 1, 0];
+
+// switches drive the input latch of a port, LEDs show its output latch
+export function PortLatchDialog(props: {port: latches | null, open: boolean, handleClose: ()=>void, handleInput: (addr: number, data: number)=>void}) {
+  const {port, open, handleClose, handleInput} = props;
+
+  if (port == null) {
+    return null;
+  }
+
+  return (
+    <div>
+      <Dialog open={open} onClose={handleClose}>
+        <DialogTitle>Port 0x{port.addr.toString(16)}</DialogTitle>
+        <DialogContent>
+          <DialogContentText>Input switches</DialogContentText>
+          <Box display="flex">
+            {bits.map(b =>
+              <Box key={b} display="flex" flexDirection="column" alignItems="center">
+                <Switch size="small" checked={(port.input >> b & 1) === 1} onChange={() => handleInput(port.addr, port.input ^ (1 << b))}/>
+                <Typography variant="caption">D{b}</Typography>
+              </Box>
+            )}
+          </Box>
+          <DialogContentText sx={{mt: 2}}>Output LEDs</DialogContentText>
+          <Box display="flex" justifyContent="space-around">
+            {bits.map(b =>
+              <Box key={b} display="flex" flexDirection="column" alignItems="center">
+                <Box sx={{width: 16, height: 16, m: 1, borderRadius: "50%", bgcolor: (port.output >> b & 1) ? "error.main" : "grey.400"}}/>
+                <Typography variant="caption">D{b}</Typography>
+              </Box>
+            )}
+          </Box>
+        </DialogContent>
+        <DialogActions>
+          <Button onClick={handleClose}>Close</Button>
+        </DialogActions>
+      </Dialog>
+    </div>
+  );
+}